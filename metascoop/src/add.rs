@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use log::info;
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::github::GitHubApis;
+use crate::{apps, ratelimit};
+
+/// Repository topics that name a category differently
+const TOPIC_ALIASES: &[(&str, &str)] = &[
+    ("game", "Games"),
+    ("education", "Science & Education"),
+    ("science", "Science & Education"),
+    ("sms", "Phone & SMS"),
+    ("health", "Sports & Health"),
+    ("fitness", "Sports & Health"),
+    ("music", "Multimedia"),
+    ("video", "Multimedia"),
+    ("finance", "Money"),
+    ("maps", "Navigation"),
+    ("privacy", "Security"),
+];
+
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Maps repository topics to F-Droid categories, ignoring the others
+fn categories_from_topics(topics: &[String]) -> Vec<String> {
+    let mut categories: Vec<String> = Vec::new();
+
+    for topic in topics {
+        let topic = normalize(topic);
+
+        let category = apps::FDROID_CATEGORIES
+            .iter()
+            .find(|c| normalize(c) == topic)
+            .or_else(|| TOPIC_ALIASES.iter().find(|(alias, _)| *alias == topic).map(|(_, c)| c));
+
+        if let Some(category) = category {
+            if !categories.iter().any(|c| c == category) {
+                categories.push(category.to_string());
+            }
+        }
+    }
+
+    categories
+}
+
+/// Appends an entry for the repository at `repo_url` to apps.yaml
+pub async fn run(apps_path: &Path, apis: &GitHubApis, repo_url: &str, id: Option<&str>) -> Result<()> {
+    let repo = apps::repo_info(repo_url)
+        .with_context(|| format!("Failed to get repo info from URL: {}", repo_url))?;
+
+    let name = repo.name.trim_end_matches(".git");
+    let git = format!("https://{}/{}/{}", repo.host, repo.author, name);
+    let key = id
+        .map(str::to_string)
+        .unwrap_or_else(|| name.to_lowercase());
+
+    if apps_path.exists() {
+        let existing = apps::parse_app_file(apps_path)
+            .context("Failed to parse app file")?;
+
+        if let Some(app) = existing.iter().find(|app| app.key_name == key) {
+            anyhow::bail!("{} already has an app with key {:?} ({})", app.source, key, app.git);
+        }
+        if let Some(app) = existing.iter().find(|app| app.git.trim_end_matches('/') == git) {
+            anyhow::bail!("{} already lists {} as {:?}", app.source, git, app.key_name);
+        }
+    }
+
+    let github = apis.for_repo(&repo, "")?;
+
+    info!("Looking up {}/{} on {}", repo.author, name, github.base_url);
+
+    let route = format!("/repos/{}/{}", repo.author, name);
+    let gh_repo: octocrab::models::Repository = ratelimit::get_json(&github, &route).await
+        .with_context(|| format!("Failed to look up {}", git))?;
+
+    let mut entry = Mapping::new();
+    entry.insert("git".into(), git.clone().into());
+    entry.insert("name".into(), gh_repo.name.clone().into());
+
+    if let Some(description) = gh_repo.description.filter(|d| !d.is_empty()) {
+        entry.insert("description".into(), description.into());
+    }
+
+    if let Some(license) = gh_repo.license.filter(|l| l.spdx_id != "NOASSERTION") {
+        entry.insert("license".into(), license.spdx_id.into());
+    }
+
+    let topics = gh_repo.topics.unwrap_or_default();
+    let categories = categories_from_topics(&topics);
+    if !categories.is_empty() {
+        entry.insert(
+            "categories".into(),
+            Value::Sequence(categories.into_iter().map(Value::from).collect()),
+        );
+    } else if !topics.is_empty() {
+        info!("None of the topics {:?} match an F-Droid category", topics);
+    }
+
+    let mut document = Mapping::new();
+    document.insert(key.clone().into(), Value::Mapping(entry));
+    let yaml = serde_yaml::to_string(&document)?;
+
+    let existing = fs::read_to_string(apps_path).unwrap_or_default();
+    let separator = if existing.is_empty() {
+        ""
+    } else if existing.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(apps_path)
+        .with_context(|| format!("Failed to open {}", apps_path.display()))?;
+
+    write!(file, "{}{}", separator, yaml)?;
+
+    println!("{}", yaml.trim_end());
+    info!("Added {:?} to {}", key, apps_path.display());
+
+    Ok(())
+}
@@ -0,0 +1,441 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::checksum;
+
+/// Package details read from an APK's binary manifest and signature
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ApkInfo {
+    pub package_name: String,
+    pub version_code: i64,
+    pub version_name: String,
+    pub min_sdk_version: i64,
+    pub target_sdk_version: i64,
+    /// SHA-256 of the DER-encoded signing certificate, as used for `signer` in the F-Droid index
+    pub signer: String,
+    /// DER-encoded signing certificate
+    pub certificate: Vec<u8>,
+    /// `uses-permission` elements
+    pub permissions: Vec<Permission>,
+    /// `uses-permission-sdk-23` elements
+    pub permissions_sdk_23: Vec<Permission>,
+    /// ABIs of the native libraries under `lib/`
+    pub nativecode: Vec<String>,
+}
+
+/// A permission an APK requests
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permission {
+    pub name: String,
+    pub max_sdk_version: Option<i64>,
+}
+
+pub fn read_apk(path: &Path) -> Result<ApkInfo> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read APK: {}", path.display()))?;
+
+    parse_apk(&bytes).with_context(|| format!("Failed to parse APK: {}", path.display()))
+}
+
+pub fn parse_apk(bytes: &[u8]) -> Result<ApkInfo> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).context("APK is not a valid zip archive")?;
+
+    let manifest = {
+        let mut file = zip
+            .by_name("AndroidManifest.xml")
+            .context("APK has no AndroidManifest.xml")?;
+        let mut manifest = Vec::new();
+        file.read_to_end(&mut manifest)?;
+        manifest
+    };
+
+    let mut info = parse_manifest(&manifest)?;
+
+    let mut nativecode: Vec<String> = zip.file_names()
+        .filter_map(|n| n.strip_prefix("lib/")?.split_once('/'))
+        .map(|(abi, _)| abi.to_string())
+        .collect();
+    nativecode.sort();
+    nativecode.dedup();
+    info.nativecode = nativecode;
+
+    let certificate = match signing_block_certificate(bytes)? {
+        Some(cert) => cert,
+        None => jar_certificate(&mut zip)?
+            .ok_or_else(|| anyhow::anyhow!("APK is not signed"))?,
+    };
+
+    info.signer = checksum::sha256_hex(&certificate);
+    info.certificate = certificate;
+
+    Ok(info)
+}
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+
+const ATTR_NAME: u32 = 0x0101_0003;
+const ATTR_MAX_SDK_VERSION: u32 = 0x0101_0271;
+const ATTR_VERSION_CODE: u32 = 0x0101_021b;
+const ATTR_VERSION_NAME: u32 = 0x0101_021c;
+const ATTR_MIN_SDK_VERSION: u32 = 0x0101_020c;
+const ATTR_TARGET_SDK_VERSION: u32 = 0x0101_0270;
+
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+
+fn u16_at(b: &[u8], at: usize) -> Option<u16> {
+    b.get(at..at + 2).map(|s| u16::from_le_bytes([s[0], s[1]]))
+}
+
+fn u32_at(b: &[u8], at: usize) -> Option<u32> {
+    b.get(at..at + 4).map(|s| u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+}
+
+fn u64_at(b: &[u8], at: usize) -> Option<u64> {
+    b.get(at..at + 8).map(|s| u64::from_le_bytes(s.try_into().unwrap()))
+}
+
+fn parse_string_pool(b: &[u8], start: usize) -> Option<Vec<String>> {
+    let header_size = u16_at(b, start + 2)? as usize;
+    let size = u32_at(b, start + 4)? as usize;
+    let count = u32_at(b, start + 8)? as usize;
+    let utf8 = u32_at(b, start + 16)? & (1 << 8) != 0;
+    let strings_start = start + u32_at(b, start + 20)? as usize;
+
+    // The string offsets have to fit in the chunk, which has to fit in the file
+    if size > b.len() - start || count > size.checked_sub(header_size)? / 4 {
+        return None;
+    }
+
+    let mut strings = Vec::with_capacity(count);
+    for i in 0..count {
+        let mut at = strings_start + u32_at(b, start + header_size + i * 4)? as usize;
+
+        let s = if utf8 {
+            // UTF-16 length, then UTF-8 length, each one or two bytes
+            at += if b.get(at)? & 0x80 != 0 { 2 } else { 1 };
+            let mut len = *b.get(at)? as usize;
+            if len & 0x80 != 0 {
+                len = ((len & 0x7f) << 8) | *b.get(at + 1)? as usize;
+                at += 2;
+            } else {
+                at += 1;
+            }
+            String::from_utf8_lossy(b.get(at..at + len)?).into_owned()
+        } else {
+            let mut len = u16_at(b, at)? as usize;
+            if len & 0x8000 != 0 {
+                len = ((len & 0x7fff) << 16) | u16_at(b, at + 2)? as usize;
+                at += 4;
+            } else {
+                at += 2;
+            }
+            let units: Vec<u16> = (0..len).map(|j| u16_at(b, at + j * 2)).collect::<Option<_>>()?;
+            String::from_utf16_lossy(&units)
+        };
+
+        strings.push(s);
+    }
+
+    Some(strings)
+}
+
+/// Reads package name, version, SDK levels and permissions from a binary
+/// AndroidManifest.xml
+pub fn parse_manifest(b: &[u8]) -> Result<ApkInfo> {
+    parse_manifest_chunks(b).ok_or_else(|| anyhow::anyhow!("Malformed binary AndroidManifest.xml"))
+}
+
+fn parse_manifest_chunks(b: &[u8]) -> Option<ApkInfo> {
+    let mut info = ApkInfo::default();
+    let mut strings: Vec<String> = Vec::new();
+    let mut resource_ids: Vec<u32> = Vec::new();
+
+    let mut at = u16_at(b, 2)? as usize;
+    while at + 8 <= b.len() {
+        let chunk_type = u16_at(b, at)?;
+        let header_size = u16_at(b, at + 2)? as usize;
+        let size = u32_at(b, at + 4)? as usize;
+        if size < 8 {
+            return None;
+        }
+
+        match chunk_type {
+            RES_STRING_POOL_TYPE => strings = parse_string_pool(b, at)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_ids = (at + header_size..at + size)
+                    .step_by(4)
+                    .filter_map(|i| u32_at(b, i))
+                    .collect();
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let ext = at + header_size;
+                let name = strings.get(u32_at(b, ext + 4)? as usize)?.as_str();
+                let attr_start = u16_at(b, ext + 8)? as usize;
+                let attr_size = u16_at(b, ext + 10)? as usize;
+                let attr_count = u16_at(b, ext + 12)? as usize;
+                let mut permission = Permission::default();
+
+                for i in 0..attr_count {
+                    let attr = ext + attr_start + i * attr_size;
+                    let name_idx = u32_at(b, attr + 4)? as usize;
+                    let attr_name = strings.get(name_idx).map(String::as_str).unwrap_or("");
+                    let res_id = resource_ids.get(name_idx).copied().unwrap_or(0);
+                    let raw = u32_at(b, attr + 8)?;
+                    let data_type = *b.get(attr + 15)?;
+                    let data = u32_at(b, attr + 16)?;
+
+                    let as_string = || -> Option<String> {
+                        match data_type {
+                            TYPE_STRING => strings.get(data as usize).cloned(),
+                            _ if raw != u32::MAX => strings.get(raw as usize).cloned(),
+                            _ => Some(data.to_string()),
+                        }
+                    };
+                    let as_int = || -> Option<i64> {
+                        match data_type {
+                            TYPE_INT_DEC | TYPE_INT_HEX => Some(data as i32 as i64),
+                            _ => as_string()?.parse().ok(),
+                        }
+                    };
+
+                    match (name, attr_name, res_id) {
+                        ("manifest", "package", _) => info.package_name = as_string()?,
+                        ("manifest", _, ATTR_VERSION_CODE) | ("manifest", "versionCode", _) => {
+                            info.version_code = as_int()?
+                        }
+                        ("manifest", _, ATTR_VERSION_NAME) | ("manifest", "versionName", _) => {
+                            info.version_name = as_string().unwrap_or_default()
+                        }
+                        ("uses-sdk", _, ATTR_MIN_SDK_VERSION) | ("uses-sdk", "minSdkVersion", _) => {
+                            info.min_sdk_version = as_int().unwrap_or_default()
+                        }
+                        ("uses-sdk", _, ATTR_TARGET_SDK_VERSION) | ("uses-sdk", "targetSdkVersion", _) => {
+                            info.target_sdk_version = as_int().unwrap_or_default()
+                        }
+                        ("uses-permission" | "uses-permission-sdk-23", _, ATTR_NAME)
+                        | ("uses-permission" | "uses-permission-sdk-23", "name", _) => {
+                            permission.name = as_string().unwrap_or_default()
+                        }
+                        ("uses-permission" | "uses-permission-sdk-23", _, ATTR_MAX_SDK_VERSION)
+                        | ("uses-permission" | "uses-permission-sdk-23", "maxSdkVersion", _) => {
+                            permission.max_sdk_version = as_int()
+                        }
+                        _ => {}
+                    }
+                }
+
+                if !permission.name.is_empty() {
+                    match name {
+                        "uses-permission" => info.permissions.push(permission),
+                        "uses-permission-sdk-23" => info.permissions_sdk_23.push(permission),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        at += size;
+    }
+
+    if info.package_name.is_empty() {
+        return None;
+    }
+
+    Some(info)
+}
+
+const APK_SIG_BLOCK_MAGIC: &[u8] = b"APK Sig Block 42";
+const APK_SIGNATURE_SCHEME_V2_ID: u32 = 0x7109_871a;
+const APK_SIGNATURE_SCHEME_V3_ID: u32 = 0xf053_68c0;
+
+/// Returns the first signer's certificate from the APK Signing Block (v2/v3)
+fn signing_block_certificate(b: &[u8]) -> Result<Option<Vec<u8>>> {
+    // The End of Central Directory record is at least 22 bytes from the end
+    let eocd = (0..b.len().saturating_sub(21))
+        .rev()
+        .take(0xffff + 22)
+        .find(|&i| u32_at(b, i) == Some(0x0605_4b50))
+        .ok_or_else(|| anyhow::anyhow!("APK has no zip end of central directory"))?;
+
+    let cd_offset = u32_at(b, eocd + 16).unwrap_or(0) as usize;
+    if cd_offset < 24 || b.get(cd_offset - 16..cd_offset) != Some(APK_SIG_BLOCK_MAGIC) {
+        return Ok(None);
+    }
+
+    let block_size = u64_at(b, cd_offset - 24).unwrap_or(0) as usize;
+    let block_start = match block_size.checked_add(16).and_then(|size| (cd_offset + 8).checked_sub(size)) {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+
+    let mut pairs = Vec::new();
+    let mut at = block_start + 8;
+    while at + 12 <= cd_offset - 24 {
+        let len = u64_at(b, at).unwrap_or(0) as usize;
+        let id = u32_at(b, at + 8).unwrap_or(0);
+        let end = match (at + 8).checked_add(len) {
+            Some(end) if len >= 4 && end <= cd_offset - 24 => end,
+            _ => break,
+        };
+        pairs.push((id, &b[at + 12..end]));
+        at = end;
+    }
+
+    let value = pairs
+        .iter()
+        .find(|(id, _)| *id == APK_SIGNATURE_SCHEME_V3_ID)
+        .or_else(|| pairs.iter().find(|(id, _)| *id == APK_SIGNATURE_SCHEME_V2_ID))
+        .map(|(_, v)| *v);
+
+    Ok(value.and_then(first_signer_certificate))
+}
+
+/// Walks `signers -> signer -> signed data -> certificates -> certificate`,
+/// every level being a little-endian u32 length-prefixed value
+fn first_signer_certificate(value: &[u8]) -> Option<Vec<u8>> {
+    let prefixed = |b: &[u8], at: usize| -> Option<(usize, usize)> {
+        let len = u32_at(b, at)? as usize;
+        b.get(at + 4..at + 4 + len)?;
+        Some((at + 4, len))
+    };
+
+    let (signers, _) = prefixed(value, 0)?;
+    let (signer, _) = prefixed(value, signers)?;
+    let (signed_data, _) = prefixed(value, signer)?;
+    let (digests, digests_len) = prefixed(value, signed_data)?;
+    let (certificates, _) = prefixed(value, digests + digests_len)?;
+    let (certificate, certificate_len) = prefixed(value, certificates)?;
+
+    Some(value[certificate..certificate + certificate_len].to_vec())
+}
+
+/// Returns the certificate of a v1 (JAR) signature from META-INF/*.RSA, *.DSA or *.EC
+fn jar_certificate(zip: &mut zip::ZipArchive<Cursor<&[u8]>>) -> Result<Option<Vec<u8>>> {
+    let name = zip.file_names().find(|n| {
+        let n = n.to_uppercase();
+        n.starts_with("META-INF/") && (n.ends_with(".RSA") || n.ends_with(".DSA") || n.ends_with(".EC"))
+    });
+
+    let name = match name {
+        Some(n) => n.to_string(),
+        None => return Ok(None),
+    };
+
+    let mut pkcs7 = Vec::new();
+    zip.by_name(&name)?.read_to_end(&mut pkcs7)?;
+
+    Ok(pkcs7_certificate(&pkcs7))
+}
+
+/// Reads one DER TLV at `at`, returning (tag, content start, content length)
+fn der_tlv(b: &[u8], at: usize) -> Option<(u8, usize, usize)> {
+    let tag = *b.get(at)?;
+    let first = *b.get(at + 1)? as usize;
+
+    if first & 0x80 == 0 {
+        return Some((tag, at + 2, first));
+    }
+
+    let count = first & 0x7f;
+    if count == 0 || count > 4 {
+        return None;
+    }
+
+    let len = b.get(at + 2..at + 2 + count)?
+        .iter()
+        .fold(0usize, |acc, &x| (acc << 8) | x as usize);
+
+    Some((tag, at + 2 + count, len))
+}
+
+/// Extracts the first certificate of a PKCS#7 SignedData structure
+pub fn pkcs7_certificate(b: &[u8]) -> Option<Vec<u8>> {
+    // ContentInfo ::= SEQUENCE { contentType OID, content [0] EXPLICIT SignedData }
+    let (_, content_info, _) = der_tlv(b, 0)?;
+    let (_, oid, oid_len) = der_tlv(b, content_info)?;
+    let (_, explicit, _) = der_tlv(b, oid + oid_len)?;
+    // SignedData ::= SEQUENCE { version, digestAlgorithms, contentInfo, [0] certificates, ... }
+    let (_, signed_data, signed_data_len) = der_tlv(b, explicit)?;
+
+    let mut at = signed_data;
+    while at < signed_data + signed_data_len {
+        let (tag, start, len) = der_tlv(b, at)?;
+        if tag == 0xa0 {
+            let (_, cert_start, cert_len) = der_tlv(b, start)?;
+            return b.get(start..cert_start + cert_len).map(<[u8]>::to_vec);
+        }
+        at = start + len;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An APK Signing Block holding one v2 pair, followed by the end of
+    /// central directory pointing right after it
+    fn signing_block(block_size: u64, pair_len: u64) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend(block_size.to_le_bytes());
+        b.extend(pair_len.to_le_bytes());
+        b.extend(APK_SIGNATURE_SCHEME_V2_ID.to_le_bytes());
+        b.extend([0; 4]);
+        b.extend(block_size.to_le_bytes());
+        b.extend(APK_SIG_BLOCK_MAGIC);
+
+        let cd_offset = b.len() as u32;
+        b.extend(0x0605_4b50u32.to_le_bytes());
+        b.extend([0; 12]);
+        b.extend(cd_offset.to_le_bytes());
+        b.extend([0; 2]);
+        b
+    }
+
+    #[test]
+    fn signing_block_without_signer_has_no_certificate() {
+        assert_eq!(signing_block_certificate(&signing_block(40, 8)).unwrap(), None);
+    }
+
+    /// A binary XML file holding a string pool chunk with the given count and
+    /// chunk size, but no strings
+    fn string_pool(count: u32, size: u32) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend(0x0003u16.to_le_bytes());
+        b.extend(8u16.to_le_bytes());
+        b.extend((8 + size).to_le_bytes());
+
+        b.extend(RES_STRING_POOL_TYPE.to_le_bytes());
+        b.extend(28u16.to_le_bytes());
+        b.extend(size.to_le_bytes());
+        b.extend(count.to_le_bytes());
+        b.extend([0; 8]);
+        b.extend(28u32.to_le_bytes());
+        b.extend([0; 4]);
+        b
+    }
+
+    #[test]
+    fn string_pools_beyond_their_chunk_are_malformed() {
+        assert!(parse_manifest(&string_pool(u32::MAX, 28)).is_err());
+        assert!(parse_manifest(&string_pool(1, 28)).is_err());
+        assert!(parse_manifest(&string_pool(0, 1024)).is_err());
+        assert_eq!(parse_string_pool(&string_pool(0, 28), 8), Some(Vec::new()));
+    }
+
+    #[test]
+    fn huge_signing_block_sizes_are_ignored() {
+        assert_eq!(signing_block_certificate(&signing_block(u64::MAX, 8)).unwrap(), None);
+        assert_eq!(signing_block_certificate(&signing_block(u64::MAX - 8, 8)).unwrap(), None);
+        assert_eq!(signing_block_certificate(&signing_block(40, u64::MAX)).unwrap(), None);
+        assert_eq!(signing_block_certificate(&signing_block(40, u64::MAX - 4)).unwrap(), None);
+    }
+}
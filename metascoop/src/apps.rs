@@ -1,37 +1,213 @@
 use anyhow::{Context, Result};
+use indexmap::IndexMap;
 use octocrab::models::{repos::Release, repos::Asset};
-use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+use serde::de::{self, DeserializeSeed, IgnoredAny, IntoDeserializer, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 use unicode_normalization::UnicodeNormalization;
 use url::Url;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+use crate::output::Location;
+
+/// F-Droid's categories, see <https://f-droid.org/en/docs/Build_Metadata_Reference/#Categories>
+pub const FDROID_CATEGORIES: &[&str] = &[
+    "Connectivity",
+    "Development",
+    "Games",
+    "Graphics",
+    "Internet",
+    "Money",
+    "Multimedia",
+    "Navigation",
+    "Phone & SMS",
+    "Reading",
+    "Science & Education",
+    "Security",
+    "Sports & Health",
+    "System",
+    "Theming",
+    "Time",
+    "Writing",
+];
+
+/// F-Droid's anti-features, see <https://f-droid.org/en/docs/Anti-Features/>
+pub const FDROID_ANTI_FEATURES: &[&str] = &[
+    "Ads",
+    "ApplicationDebuggable",
+    "DisabledAlgorithm",
+    "KnownVuln",
+    "NoSourceSince",
+    "NonFreeAdd",
+    "NonFreeAssets",
+    "NonFreeDep",
+    "NonFreeNet",
+    "NSFW",
+    "Tracking",
+    "TetheredNet",
+    "UpstreamNonFree",
+];
+
+/// An app in apps.yaml, keyed by its name
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct AppInfo {
+    /// URL of the app's repository
     pub git: String,
+    /// Short summary; the repository description is used when it has one
     #[serde(default)]
     pub summary: String,
+    /// Author name; the repository owner when empty
     #[serde(default)]
     pub author: String,
+    /// Display name; the key of the app when empty
     #[serde(default)]
     pub name: String,
     #[serde(default)]
     pub description: String,
+    /// F-Droid categories
     #[serde(default)]
     pub categories: Vec<String>,
+    /// F-Droid anti-features
     #[serde(default)]
     pub anti_features: Vec<String>,
     #[serde(skip)]
     pub release_description: String,
+    /// SPDX license identifier; the repository license is used when it has one
     #[serde(default)]
     pub license: String,
     #[serde(skip)]
     pub key_name: String,
     #[serde(skip)]
     pub repo_author: String,
+    /// Release tags (or feed versions) that are never published
     #[serde(default)]
     pub skip_versions: Vec<String>,
+    #[serde(default)]
+    pub artifacts: Option<ArtifactSource>,
+    #[serde(default)]
+    pub feed: Option<FeedSource>,
+    #[serde(default)]
+    pub on_asset_change: AssetChangePolicy,
+    /// GitHub API URL, when it can't be derived from the host of `git`
+    #[serde(default)]
+    pub api_url: String,
+    /// How many of the newest versions `prune` keeps; its `--keep` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<usize>,
+    /// Where the app is defined
+    #[serde(skip)]
+    pub source: Location,
+}
+
+/// What to do when the release asset of an already published version was replaced upstream
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetChangePolicy {
+    /// Fail the run and keep serving the published file
+    #[default]
+    Error,
+    /// Download the new asset and publish it if signer and versionCode are compatible
+    Replace,
+}
+
+/// Nightly builds taken from GitHub Actions workflow artifacts
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ArtifactSource {
+    /// Workflow file name or id; all workflows are considered when empty
+    #[serde(default)]
+    pub workflow: String,
+    #[serde(default = "default_artifact_branch")]
+    pub branch: String,
+    /// Artifact name; the first artifact of a run is used when empty
+    #[serde(default)]
+    pub name: String,
+    /// How many of the most recent successful runs to publish
+    #[serde(default = "default_artifact_keep")]
+    pub keep: usize,
+}
+
+fn default_artifact_branch() -> String {
+    "main".to_string()
+}
+
+fn default_artifact_keep() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    #[default]
+    Json,
+    Atom,
+}
+
+/// Releases announced by a JSON document or Atom feed on the publisher's server.
+/// Paths are JSONPath-style (`$.releases[0].version`) for JSON feeds and
+/// XPath-style (`link[@rel='enclosure']/@href`) for Atom feeds.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FeedSource {
+    pub url: String,
+    #[serde(default)]
+    pub format: FeedFormat,
+    /// Path to the list of releases; the whole document is one release when empty
+    #[serde(default)]
+    pub items: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub apk_url: String,
+    /// Used when no APK URL is mapped, `{version}` is replaced by the release version
+    #[serde(default)]
+    pub apk_url_template: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub date: String,
+    /// Path to the APK's SHA-256, downloads are verified against it when set
+    #[serde(default)]
+    pub sha256: String,
+}
+
+impl FeedSource {
+    pub fn version_path(&self) -> &str {
+        match (self.version.is_empty(), self.format) {
+            (false, _) => &self.version,
+            (true, FeedFormat::Json) => "$.version",
+            (true, FeedFormat::Atom) => "title",
+        }
+    }
+
+    pub fn apk_url_path(&self) -> &str {
+        match (self.apk_url.is_empty(), self.format) {
+            (false, _) => &self.apk_url,
+            (true, _) if !self.apk_url_template.is_empty() => "",
+            (true, FeedFormat::Json) => "$.apk_url",
+            (true, FeedFormat::Atom) => "link[@rel='enclosure']/@href",
+        }
+    }
+
+    pub fn notes_path(&self) -> &str {
+        match (self.notes.is_empty(), self.format) {
+            (false, _) => &self.notes,
+            (true, FeedFormat::Json) => "$.notes",
+            (true, FeedFormat::Atom) => "content",
+        }
+    }
+
+    pub fn date_path(&self) -> &str {
+        match (self.date.is_empty(), self.format) {
+            (false, _) => &self.date,
+            (true, FeedFormat::Json) => "$.date",
+            (true, FeedFormat::Atom) => "updated",
+        }
+    }
 }
 
 impl AppInfo {
@@ -60,20 +236,337 @@ pub struct Repo {
     pub host: String,
 }
 
-pub fn parse_app_file(filepath: &Path) -> Result<Vec<AppInfo>> {
-    let content = fs::read_to_string(filepath)
-        .with_context(|| format!("Failed to read app file: {}", filepath.display()))?;
+/// A problem in an apps file, shown as `file:line:column: message`, the
+/// format editors understand
+#[derive(Debug)]
+pub struct AppsFileError {
+    pub location: Location,
+    pub message: String,
+}
 
-    let mut apps: HashMap<String, AppInfo> = serde_yaml::from_str(&content)
-        .with_context(|| format!("Failed to parse YAML from {}", filepath.display()))?;
+impl std::fmt::Display for AppsFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl std::error::Error for AppsFileError {}
+
+fn apps_file_error(location: Location, message: String) -> anyhow::Error {
+    AppsFileError { location, message }.into()
+}
+
+fn yaml_error(path: &Path, error: serde_yaml::Error) -> anyhow::Error {
+    let location = Location {
+        file: path.to_path_buf(),
+        line: error.location().map(|l| l.line()),
+        column: error.location().map(|l| l.column()),
+    };
+    // The location is already in front of the message
+    let message = error.to_string();
+    let message = match error.location() {
+        Some(_) => message.rsplit_once(" at line ").map_or(message.as_str(), |(m, _)| m).to_string(),
+        None => message,
+    };
+    apps_file_error(location, message)
+}
+
+/// Deserializes the apps and defaults of an apps file as they are written,
+/// before defaults are merged in, so errors point at the line and column of
+/// the field that is wrong
+fn check_apps_file(path: &Path, content: &str) -> Result<()> {
+    serde_yaml::Deserializer::from_str(content)
+        .deserialize_any(AppsFileCheck)
+        .map_err(|e| yaml_error(path, e))
+}
+
+struct AppsFileCheck;
+
+impl<'de> Visitor<'de> for AppsFileCheck {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a mapping of apps")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "include" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                "defaults" => map.next_value_seed(DefaultsCheck)?,
+                _ => {
+                    map.next_value::<AppInfo>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes `defaults` as an app that has every field but `git`
+struct DefaultsCheck;
+
+impl<'de> DeserializeSeed<'de> for DefaultsCheck {
+    type Value = ();
 
-    let mut list = Vec::new();
-    for (key, mut app) in apps.drain() {
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DefaultsCheck {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a mapping of app settings")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+        let map = WithGit { map, state: GitState::Pending };
+        AppInfo::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(())
+    }
+}
+
+/// A mapping that starts with a `git` URL, which defaults can't set
+struct WithGit<A> {
+    map: A,
+    state: GitState,
+}
+
+enum GitState {
+    Pending,
+    KeyGiven,
+    Done,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for WithGit<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, A::Error> {
+        if let GitState::Pending = self.state {
+            self.state = GitState::KeyGiven;
+            return seed.deserialize(IntoDeserializer::<A::Error>::into_deserializer("git")).map(Some);
+        }
+        self.map.next_key_seed(seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        if let GitState::KeyGiven = self.state {
+            self.state = GitState::Done;
+            return seed.deserialize(IntoDeserializer::<A::Error>::into_deserializer("https://example.com/defaults/app"));
+        }
+        self.map.next_value_seed(seed)
+    }
+}
+
+/// Layout of an apps file: apps keyed by name, plus the reserved `include`
+/// and `defaults` keys
+#[derive(Debug, Deserialize, JsonSchema)]
+struct AppsFile {
+    /// Files, or directories of *.yaml files, whose apps are added after the
+    /// apps of this file; paths are relative to this file
+    #[serde(default)]
+    include: Option<Includes>,
+    /// Settings the apps of this file and of the files it includes inherit
+    /// unless they set them themselves
+    #[serde(default)]
+    #[schemars(schema_with = "defaults_schema")]
+    defaults: serde_yaml::Mapping,
+    #[serde(flatten)]
+    #[schemars(with = "IndexMap<String, AppInfo>")]
+    apps: IndexMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum Includes {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Includes {
+    fn paths(&self) -> &[String] {
+        match self {
+            Includes::One(path) => std::slice::from_ref(path),
+            Includes::Many(paths) => paths,
+        }
+    }
+}
+
+/// Schema of an app entry with every field optional
+fn defaults_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    let mut schema = AppInfo::json_schema(gen).into_object();
+    schema.object().required.clear();
+    schema.object().properties.remove("git");
+    schema.metadata().description = None;
+    schemars::schema::Schema::Object(schema)
+}
+
+/// JSON Schema of apps.yaml, for editor completion and validation
+pub fn apps_schema() -> schemars::schema::RootSchema {
+    let mut schema = schemars::schema_for!(AppsFile);
+    schema.schema.metadata().title = Some("apps.yaml".to_string());
+    // schemars drops the value schema of flattened maps
+    schema.schema.object().additional_properties =
+        Some(Box::new(schemars::schema::Schema::new_ref("#/definitions/AppInfo".to_string())));
+    schema
+}
+
+/// Directory next to apps.yaml whose *.yaml files are read after it
+pub fn apps_dir(apps_path: &Path) -> PathBuf {
+    apps_path.parent().unwrap_or(Path::new("")).join("apps.d")
+}
+
+/// The *.yaml and *.yml files of `dir`, sorted by name
+fn yaml_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read directory: {}", dir.display()))? {
+        let path = entry?.path();
+        let is_yaml = path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml");
+        if is_yaml && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Location of the top-level `key` in `content`, without a line if it isn't found
+fn key_location(path: &Path, content: &str, key: &str) -> Location {
+    let line = content.lines().position(|line| {
+        [key.to_string(), format!("\"{}\"", key), format!("'{}'", key)]
+            .iter()
+            .any(|k| line.strip_prefix(k.as_str()).is_some_and(|rest| rest.trim_start().starts_with(':')))
+    });
+
+    Location {
+        file: path.to_path_buf(),
+        line: line.map(|line| line + 1),
+        column: None,
+    }
+}
+
+/// Sets the fields of `defaults` that `entry` doesn't set. Nested settings
+/// such as `artifacts` are merged key by key, and only into apps that have them.
+fn apply_defaults(entry: &mut serde_yaml::Mapping, defaults: &serde_yaml::Mapping) {
+    for (key, default) in defaults {
+        match (entry.get_mut(key), default) {
+            (Some(serde_yaml::Value::Mapping(value)), serde_yaml::Value::Mapping(default)) => {
+                apply_defaults(value, default);
+            }
+            (Some(_), _) | (None, serde_yaml::Value::Mapping(_)) => {}
+            (None, default) => {
+                entry.insert(key.clone(), default.clone());
+            }
+        }
+    }
+}
+
+/// Reads apps files, following their includes
+#[derive(Default)]
+struct AppLoader {
+    apps: Vec<AppInfo>,
+    /// Where each app is defined, by key
+    sources: HashMap<String, Location>,
+    /// Canonical paths of the files read so far
+    loaded: Vec<PathBuf>,
+}
+
+impl AppLoader {
+    fn is_loaded(&self, path: &Path) -> bool {
+        path.canonicalize().is_ok_and(|path| self.loaded.contains(&path))
+    }
+
+    /// Reads the apps of `path`; `defaults` are inherited from the including
+    /// files, innermost last. Returns the defaults of the file.
+    fn load(&mut self, path: &Path, defaults: &[&serde_yaml::Mapping]) -> Result<serde_yaml::Mapping> {
+        let canonical = path.canonicalize()
+            .with_context(|| format!("Failed to read app file: {}", path.display()))?;
+        if self.loaded.contains(&canonical) {
+            anyhow::bail!("{} is included more than once", path.display());
+        }
+        self.loaded.push(canonical);
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read app file: {}", path.display()))?;
+
+        check_apps_file(path, &content)?;
+
+        // Apps keep the order of the file, so runs are reproducible
+        let file: Option<AppsFile> = serde_yaml::from_str(&content)
+            .map_err(|e| yaml_error(path, e))?;
+        let Some(file) = file else {
+            return Ok(serde_yaml::Mapping::new());
+        };
+
+        let mut defaults = defaults.to_vec();
+        defaults.push(&file.defaults);
+
+        for (key, value) in file.apps {
+            let location = key_location(path, &content, &key);
+            self.add_app(location, key, value, &defaults)?;
+        }
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for include in file.include.iter().flat_map(Includes::paths) {
+            let include_path = dir.join(include);
+            if include_path.is_dir() {
+                for file_path in yaml_files(&include_path)? {
+                    self.load(&file_path, &defaults)?;
+                }
+            } else {
+                self.load(&include_path, &defaults)
+                    .with_context(|| format!("{}: include {:?}", key_location(path, &content, "include"), include))?;
+            }
+        }
+
+        Ok(file.defaults)
+    }
+
+    fn add_app(
+        &mut self,
+        location: Location,
+        key: String,
+        value: serde_yaml::Value,
+        defaults: &[&serde_yaml::Mapping],
+    ) -> Result<()> {
+        if let Some(other) = self.sources.get(&key) {
+            return Err(apps_file_error(location, format!("app {:?} is already defined at {}", key, other)));
+        }
+
+        let serde_yaml::Value::Mapping(mut entry) = value else {
+            return Err(apps_file_error(location, format!("{}: expected a mapping of app settings", key)));
+        };
+        for defaults in defaults.iter().rev() {
+            apply_defaults(&mut entry, defaults);
+        }
+
+        let mut app: AppInfo = match serde_yaml::from_value(serde_yaml::Value::Mapping(entry)) {
+            Ok(app) => app,
+            Err(e) => return Err(apps_file_error(location, format!("{}: {}", key, e))),
+        };
         app.key_name = key.clone();
 
-        let url = Url::parse(&app.git).with_context(|| {
-            format!("Invalid git URL '{}' for app with key '{}'", app.git, key)
-        })?;
+        let url = match Url::parse(&app.git) {
+            Ok(url) => url,
+            Err(e) => {
+                return Err(apps_file_error(
+                    location,
+                    format!("invalid git URL '{}' for app with key '{}': {}", app.git, key, e),
+                ));
+            }
+        };
 
         let path_segments: Vec<&str> = url
             .path_segments()
@@ -84,10 +577,31 @@ pub fn parse_app_file(filepath: &Path) -> Result<Vec<AppInfo>> {
             app.repo_author = path_segments[0].to_string();
         }
 
-        list.push(app);
+        app.source = location.clone();
+        self.sources.insert(key, location);
+        self.apps.push(app);
+
+        Ok(())
+    }
+}
+
+/// Reads the apps of `filepath`, the files it includes and the files of the
+/// apps.d directory next to it, which inherit the defaults of `filepath`
+pub fn parse_app_file(filepath: &Path) -> Result<Vec<AppInfo>> {
+    let mut loader = AppLoader::default();
+    let defaults = loader.load(filepath, &[])?;
+
+    let dir = apps_dir(filepath);
+    if dir.is_dir() {
+        for path in yaml_files(&dir)? {
+            // apps.yaml may include apps.d itself
+            if !loader.is_loaded(&path) {
+                loader.load(&path, &[&defaults])?;
+            }
+        }
     }
 
-    Ok(list)
+    Ok(loader.apps)
 }
 
 pub fn repo_info(repo_url: &str) -> Result<Repo> {
@@ -197,17 +711,20 @@ impl RepoIndex {
     }
 }
 
-pub fn read_meta_file(path: &Path) -> Result<HashMap<String, serde_yaml::Value>> {
+/// Fields of a metadata file, in the order of the file
+pub type MetaFile = IndexMap<String, serde_yaml::Value>;
+
+pub fn read_meta_file(path: &Path) -> Result<MetaFile> {
     let content = fs::read_to_string(path)
         .with_context(|| format!("Failed to read meta file: {}", path.display()))?;
 
-    let data: HashMap<String, serde_yaml::Value> = serde_yaml::from_str(&content)
+    let data: MetaFile = serde_yaml::from_str(&content)
         .with_context(|| format!("Failed to parse YAML from {}", path.display()))?;
 
     Ok(data)
 }
 
-pub fn write_meta_file(path: &Path, data: &HashMap<String, serde_yaml::Value>) -> Result<()> {
+pub fn write_meta_file(path: &Path, data: &MetaFile) -> Result<()> {
     let tmp_path = path.with_extension("yml.tmp");
     
     let content = serde_yaml::to_string(data)?;
@@ -220,6 +737,38 @@ pub fn write_meta_file(path: &Path, data: &HashMap<String, serde_yaml::Value>) -
     Ok(())
 }
 
+/// Package names in the order their apps are listed in apps.yaml, found
+/// through the APKs each app produced
+pub fn package_order(app_keys: &[String], index: &RepoIndex, apk_info_map: &HashMap<String, AppInfo>) -> Vec<String> {
+    let mut ordered: Vec<String> = Vec::new();
+
+    for key in app_keys {
+        let mut packages: Vec<&String> = index.packages
+            .iter()
+            .filter(|(_, packages)| {
+                packages.iter().any(|p| {
+                    apk_info_map.get(&p.apk_name).is_some_and(|app| &app.key_name == key)
+                })
+            })
+            .map(|(package_name, _)| package_name)
+            .collect();
+        packages.sort();
+
+        for package_name in packages {
+            if !ordered.contains(package_name) {
+                ordered.push(package_name.clone());
+            }
+        }
+    }
+
+    ordered
+}
+
+/// Position of `package_name` in `order`, packages of unknown apps come last
+pub fn order_position(order: &[String], package_name: &str) -> usize {
+    order.iter().position(|p| p == package_name).unwrap_or(order.len())
+}
+
 #[derive(Debug, Default)]
 pub struct RepoMetadata {
     pub screenshots: Vec<PathBuf>,
@@ -228,7 +777,7 @@ pub struct RepoMetadata {
 pub fn find_metadata(cloned_repo_path: &Path) -> Result<RepoMetadata> {
     let mut metadata = RepoMetadata::default();
     
-    for entry in walkdir::WalkDir::new(cloned_repo_path) {
+    for entry in walkdir::WalkDir::new(cloned_repo_path).sort_by_file_name() {
         let entry = entry?;
         if !entry.file_type().is_file() {
             continue;
@@ -263,3 +812,56 @@ pub fn has_significant_changes(old: &RepoIndex, new: &RepoIndex) -> (String, boo
     
     ("".to_string(), false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apps_file_error(content: &str) -> AppsFileError {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("apps.yaml");
+        fs::write(&path, content).unwrap();
+        parse_app_file(&path).unwrap_err().downcast().unwrap()
+    }
+
+    #[test]
+    fn defaults_errors_point_at_the_field() {
+        let error = apps_file_error("defaults:\n  author: Example\n  categories: System\n\nmy-app:\n  git: https://github.com/a/b\n");
+        assert_eq!((error.location.line, error.location.column), (Some(3), Some(15)));
+        assert!(error.message.starts_with("defaults.categories: invalid type"), "{}", error.message);
+
+        let error = apps_file_error("defaults:\n  git: https://github.com/a/b\n");
+        assert_eq!(error.location.line, Some(2));
+        assert!(error.message.contains("duplicate field `git`"), "{}", error.message);
+    }
+
+    #[test]
+    fn app_errors_point_at_the_field() {
+        let error = apps_file_error("my-app:\n  git: https://github.com/a/b\n  categoris: [System]\n");
+        assert_eq!((error.location.line, error.location.column), (Some(3), Some(3)));
+        assert!(error.message.starts_with("my-app: unknown field `categoris`"), "{}", error.message);
+
+        let error = apps_file_error("my-app:\n  git: https://github.com/a/b\n  artifacts:\n    kep: 3\n");
+        assert_eq!(error.location.line, Some(4));
+        assert!(error.message.starts_with("my-app.artifacts: unknown field `kep`"), "{}", error.message);
+    }
+
+    #[test]
+    fn apps_inherit_defaults_of_their_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("apps.yaml");
+        fs::write(&path, "include: games.yaml\ndefaults:\n  author: Example\n  retention: 2\n\
+            tool:\n  git: https://github.com/a/tool\n  retention: 5\n").unwrap();
+        fs::write(dir.path().join("games.yaml"), "defaults:\n  categories: [Games]\n\
+            game:\n  git: https://github.com/a/game\n").unwrap();
+
+        let apps = parse_app_file(&path).unwrap();
+        assert_eq!(apps[0].key_name, "tool");
+        assert_eq!((apps[0].author.as_str(), apps[0].retention), ("Example", Some(5)));
+        assert!(apps[0].categories.is_empty());
+        assert_eq!(apps[1].key_name, "game");
+        assert_eq!((apps[1].author.as_str(), apps[1].retention), ("Example", Some(2)));
+        assert_eq!(apps[1].categories, ["Games"]);
+        assert_eq!(apps[1].source.file, dir.path().join("games.yaml"));
+    }
+}
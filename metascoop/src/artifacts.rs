@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use octocrab::models::workflows::{Run, WorkflowListArtifact};
+use octocrab::params::actions::ArchiveFormat;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::apps::{ArtifactSource, Repo};
+use crate::github::GitHubApi;
+use crate::{download, output, ratelimit};
+
+/// A successful workflow run whose artifact contains an APK
+#[derive(Debug, Clone)]
+pub struct ArtifactBuild {
+    /// Version label used in place of a release tag, e.g. "nightly-42"
+    pub label: String,
+    pub run_id: u64,
+    pub head_sha: String,
+    pub artifact_id: u64,
+    pub artifact_name: String,
+    /// Commit messages since the previous published run
+    pub changelog: String,
+}
+
+pub fn version_label(run: &Run) -> String {
+    format!("nightly-{}", run.run_number)
+}
+
+/// Lists the most recent successful runs on the configured branch that still
+/// have a matching, unexpired artifact. Newest runs come first.
+pub async fn list_builds(
+    github: &GitHubApi,
+    repo: &Repo,
+    source: &ArtifactSource,
+) -> Result<Vec<ArtifactBuild>> {
+    let runs = ratelimit::retry(github, || async move {
+        let workflows = github.client.workflows(&repo.author, &repo.name);
+
+        let builder = if source.workflow.is_empty() {
+            workflows.list_all_runs()
+        } else {
+            workflows.list_runs(source.workflow.clone())
+        };
+
+        builder
+            .branch(source.branch.clone())
+            .status("success")
+            .exclude_pull_requests(true)
+            .per_page(100u8)
+            .send()
+            .await
+    })
+        .await
+        .with_context(|| format!("Failed to list workflow runs for {}/{}", repo.author, repo.name))?
+        .items;
+
+    let mut builds: Vec<(ArtifactBuild, Run)> = Vec::new();
+
+    for run in runs {
+        if builds.len() >= source.keep {
+            break;
+        }
+
+        let run_id = run.id;
+        let artifacts = ratelimit::retry(github, || async move {
+            github
+                .client
+                .actions()
+                .list_workflow_run_artifacts(&repo.author, &repo.name, run_id)
+                .per_page(100u8)
+                .send()
+                .await
+        })
+            .await
+            .with_context(|| format!("Failed to list artifacts of run {}", run.id))?
+            .value
+            .map(|page| page.items)
+            .unwrap_or_default();
+
+        let artifact = match find_artifact(&artifacts, &source.name) {
+            Some(a) => a,
+            None => continue,
+        };
+
+        builds.push((
+            ArtifactBuild {
+                label: version_label(&run),
+                run_id: run.id.0,
+                head_sha: run.head_sha.clone(),
+                artifact_id: artifact.id.0,
+                artifact_name: artifact.name.clone(),
+                changelog: String::new(),
+            },
+            run,
+        ));
+    }
+
+    // Each build's changelog covers the commits since the next older build
+    let mut result = Vec::with_capacity(builds.len());
+    for i in 0..builds.len() {
+        let (mut build, run) = builds[i].clone();
+
+        build.changelog = match builds.get(i + 1) {
+            Some((older, _)) => {
+                commit_messages(github, repo, &older.head_sha, &build.head_sha)
+                    .await
+                    .unwrap_or_else(|_| run.head_commit.message.clone())
+            }
+            None => run.head_commit.message.clone(),
+        };
+
+        result.push(build);
+    }
+
+    Ok(result)
+}
+
+fn find_artifact<'a>(
+    artifacts: &'a [WorkflowListArtifact],
+    name: &str,
+) -> Option<&'a WorkflowListArtifact> {
+    artifacts
+        .iter()
+        .filter(|a| !a.expired)
+        .find(|a| name.is_empty() || a.name == name)
+}
+
+async fn commit_messages(github: &GitHubApi, repo: &Repo, base: &str, head: &str) -> Result<String> {
+    let comparison = ratelimit::retry(github, || async move {
+        github
+            .client
+            .commits(&repo.author, &repo.name)
+            .compare(base, head)
+            .per_page(100u8)
+            .send()
+            .await
+    })
+        .await?;
+
+    let lines: Vec<String> = comparison
+        .commits
+        .iter()
+        .rev()
+        .filter_map(|c| c.commit.message.lines().next())
+        .map(|subject| format!("* {}", subject))
+        .collect();
+
+    Ok(lines.join("\n"))
+}
+
+/// Downloads the artifact archive and extracts the first APK it contains.
+/// Returns the SHA-256 of the APK.
+pub async fn download_artifact_apk(
+    github: &GitHubApi,
+    repo: &Repo,
+    artifact_id: u64,
+    target_path: &Path,
+) -> Result<String> {
+    let archive = ratelimit::retry(github, || async move {
+        github
+            .client
+            .actions()
+            .download_artifact(&repo.author, &repo.name, artifact_id.into(), ArchiveFormat::Zip)
+            .await
+    })
+        .await
+        .with_context(|| format!("Failed to download artifact {}", artifact_id))?;
+
+    // Unpacking the archive is CPU heavy, so it runs on a blocking thread
+    let bytes = output::spawn_blocking(move || -> Result<Vec<u8>> {
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive))
+            .context("Artifact is not a valid zip archive")?;
+
+        let apk_index = (0..zip.len())
+            .find(|&i| {
+                zip.by_index(i)
+                    .map(|f| f.is_file() && f.name().ends_with(".apk"))
+                    .unwrap_or(false)
+            })
+            .ok_or_else(|| anyhow::anyhow!("Artifact {} does not contain an APK", artifact_id))?;
+
+        let mut apk = zip.by_index(apk_index)?;
+        let mut bytes = Vec::with_capacity(apk.size() as usize);
+        apk.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }).await?;
+
+    download::write_verified(bytes, target_path, None).await
+}
@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::apps::{PackageInfo, RepoIndex};
+use crate::release_notes;
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// Feed of all apps, in the repo directory
+pub const FEED_FILE: &str = "feed.xml";
+
+/// Directory of the per-app feeds, named after the package, in the repo
+/// directory
+pub const APP_FEEDS_DIR: &str = "feeds";
+
+/// Entries kept in a feed, older ones are dropped
+const MAX_ENTRIES: usize = 50;
+
+/// An entry of a feed, announcing a version newly published in the repo
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Entry {
+    /// Stays the same across runs, so readers don't show a version twice
+    id: String,
+    title: String,
+    /// RFC 3339 time the version was added to the repo
+    updated: String,
+    /// Download link of the APK
+    link: String,
+    /// Changelog of the version
+    content: String,
+}
+
+/// Adds an entry for every version that is in `new` but wasn't in `old` to
+/// the Atom feed of the repo, `feed.xml`, and to the feed of its app in
+/// `feeds/<package>.xml`. Feeds are only written when there are new
+/// versions. Returns the number of new versions.
+pub fn update_feeds(repo_dir: &Path, metadata_dir: &Path, old: &RepoIndex, new: &RepoIndex) -> Result<usize> {
+    let address = new.repo.get("address").and_then(|a| a.as_str()).unwrap_or_default();
+    let repo_name = new.repo.get("name").and_then(|n| n.as_str()).unwrap_or("F-Droid repo");
+
+    let mut new_entries: BTreeMap<&str, Vec<Entry>> = BTreeMap::new();
+    for (package_name, packages) in &new.packages {
+        let old_codes: HashSet<i32> = old.packages.get(package_name)
+            .map(|pkgs| pkgs.iter().map(|p| p.version_code).collect())
+            .unwrap_or_default();
+
+        for package in packages.iter().filter(|p| !old_codes.contains(&p.version_code)) {
+            let entry = new_entry(package, &app_name(new, package_name), address, metadata_dir);
+            new_entries.entry(package_name.as_str()).or_default().push(entry);
+        }
+    }
+
+    let count = new_entries.values().map(Vec::len).sum();
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let all_entries: Vec<Entry> = new_entries.values().flatten().cloned().collect();
+    let feed_path = repo_dir.join(FEED_FILE);
+    let feed = Feed {
+        id: format!("{}/{}", address, FEED_FILE),
+        title: repo_name.to_string(),
+        author: repo_name.to_string(),
+    };
+    update_feed(&feed_path, &feed, all_entries)?;
+
+    for (package_name, entries) in new_entries {
+        let file_name = format!("{}/{}.xml", APP_FEEDS_DIR, package_name);
+        let feed = Feed {
+            id: format!("{}/{}", address, file_name),
+            title: format!("{}: {}", repo_name, app_name(new, package_name)),
+            author: repo_name.to_string(),
+        };
+        update_feed(&repo_dir.join(&file_name), &feed, entries)?;
+    }
+
+    info!("Added {} new versions to {:?} and the app feeds", count, feed_path);
+    Ok(count)
+}
+
+/// Name of the app of a package in the index, or the package name
+fn app_name(index: &RepoIndex, package_name: &str) -> String {
+    index.apps.iter()
+        .find(|app| app.get("packageName").and_then(|p| p.as_str()) == Some(package_name))
+        .and_then(|app| app.get("name").or_else(|| app.get("localized")?.get("en-US")?.get("name")))
+        .and_then(|n| n.as_str())
+        .unwrap_or(package_name)
+        .to_string()
+}
+
+fn new_entry(package: &PackageInfo, app_name: &str, address: &str, metadata_dir: &Path) -> Entry {
+    let changelog_path = metadata_dir
+        .join(&package.package_name)
+        .join("en-US")
+        .join("changelogs")
+        .join(format!("{}.txt", package.version_code));
+
+    Entry {
+        id: format!("urn:fdroid:{}:{}", package.package_name, package.version_code),
+        title: format!("{} {}", app_name, package.version_name),
+        updated: release_notes::rfc3339(u64::try_from(package.added / 1000).unwrap_or(0)),
+        link: format!("{}/{}", address, package.apk_name),
+        content: fs::read_to_string(changelog_path)
+            .map(|c| c.trim().to_string())
+            .unwrap_or_else(|_| format!("{} {} ({}) is available", app_name, package.version_name, package.version_code)),
+    }
+}
+
+/// What describes a feed itself
+struct Feed {
+    id: String,
+    title: String,
+    author: String,
+}
+
+/// Puts `entries` at the top of the feed at `path`, newest first, replacing
+/// entries with the same id and dropping the oldest beyond [`MAX_ENTRIES`]
+fn update_feed(path: &Path, feed: &Feed, mut entries: Vec<Entry>) -> Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(content) => parse_entries(&content)
+            .with_context(|| format!("Failed to parse feed {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read feed {}", path.display())),
+    };
+
+    let new_ids: HashSet<String> = entries.iter().map(|e| e.id.clone()).collect();
+    entries.extend(existing.into_iter().filter(|e| !new_ids.contains(&e.id)));
+    // RFC 3339 times in UTC sort as strings
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated).then_with(|| a.title.cmp(&b.title)));
+    entries.truncate(MAX_ENTRIES);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, render_feed(feed, &entries))
+        .with_context(|| format!("Failed to write feed {}", path.display()))
+}
+
+/// Reads back the entries of a feed written by [`render_feed`]
+fn parse_entries(content: &str) -> Result<Vec<Entry>> {
+    let doc = roxmltree::Document::parse(content)?;
+
+    let entries = doc.root_element()
+        .children()
+        .filter(|n| n.has_tag_name((ATOM_NS, "entry")))
+        .map(|entry| {
+            let text = |name: &str| entry.children()
+                .find(|n| n.has_tag_name((ATOM_NS, name)))
+                .and_then(|n| n.text())
+                .unwrap_or_default()
+                .to_string();
+            let link = entry.children()
+                .find(|n| n.has_tag_name((ATOM_NS, "link")))
+                .and_then(|n| n.attribute("href"))
+                .unwrap_or_default()
+                .to_string();
+
+            Entry {
+                id: text("id"),
+                title: text("title"),
+                updated: text("updated"),
+                link,
+                content: text("content"),
+            }
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+fn render_feed(feed: &Feed, entries: &[Entry]) -> String {
+    let updated = entries.first().map(|e| e.updated.as_str()).unwrap_or_default();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str(&format!("<feed xmlns=\"{}\">\n", ATOM_NS));
+    xml.push_str(&format!("  <id>{}</id>\n", escape(&feed.id)));
+    xml.push_str(&format!("  <title>{}</title>\n", escape(&feed.title)));
+    xml.push_str(&format!("  <updated>{}</updated>\n", escape(updated)));
+    xml.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape(&feed.id)));
+    xml.push_str(&format!("  <author><name>{}</name></author>\n", escape(&feed.author)));
+
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}</id>\n", escape(&entry.id)));
+        xml.push_str(&format!("    <title>{}</title>\n", escape(&entry.title)));
+        xml.push_str(&format!("    <updated>{}</updated>\n", escape(&entry.updated)));
+        xml.push_str(&format!("    <link href=\"{}\"/>\n", escape(&entry.link)));
+        xml.push_str(&format!("    <content type=\"text\">{}</content>\n", escape(&entry.content)));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,207 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+
+use crate::apps::RepoIndex;
+use crate::index::{self, PublishedIndex};
+use crate::output;
+
+/// Programs that can generate the repo index, for the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum IndexBackend {
+    /// `fdroid update` from fdroidserver
+    #[default]
+    Fdroid,
+    /// Built into metascoop, without fdroidserver, Python or the Android SDK
+    Native,
+}
+
+impl IndexBackend {
+    pub fn backend(self) -> Box<dyn FdroidBackend> {
+        match self {
+            IndexBackend::Fdroid => Box::new(FdroidCli),
+            IndexBackend::Native => Box::new(NativeBackend::default()),
+        }
+    }
+}
+
+/// What an index update does besides writing the index, like the options of
+/// `fdroid update`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpdateOptions {
+    /// Create a metadata file for packages that have none
+    pub create_metadata: bool,
+    /// Delete APKs of packages that have no metadata file
+    pub delete_unknown: bool,
+    /// The last update of the sync, whose index is published. Earlier ones
+    /// only give the metadata something to read, so no diffs are made from
+    /// their index.
+    pub final_update: bool,
+}
+
+/// A problem the backend reported about the repo
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FdroidWarning {
+    /// Package the warning is about, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    /// APK file the warning is about, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apk_name: Option<String>,
+    pub message: String,
+}
+
+/// Updates the repo index and metadata files in an fdroid directory, the one
+/// holding `repo`, `metadata` and `config.yml`
+pub trait FdroidBackend: Send {
+    /// Updates the index, returning the warnings about packages and APKs
+    fn update(&mut self, fdroid_dir: &Path, options: &UpdateOptions) -> Result<Vec<FdroidWarning>>;
+}
+
+impl<B: FdroidBackend + ?Sized> FdroidBackend for &mut B {
+    fn update(&mut self, fdroid_dir: &Path, options: &UpdateOptions) -> Result<Vec<FdroidWarning>> {
+        (**self).update(fdroid_dir, options)
+    }
+}
+
+/// Runs `fdroid update` from fdroidserver, printing its output through the
+/// reporter
+pub struct FdroidCli;
+
+impl FdroidBackend for FdroidCli {
+    fn update(&mut self, fdroid_dir: &Path, options: &UpdateOptions) -> Result<Vec<FdroidWarning>> {
+        let mut args = vec!["update", "--pretty"];
+        if options.create_metadata {
+            args.push("--create-metadata");
+        }
+        if options.delete_unknown {
+            args.push("--delete-unknown");
+        }
+
+        let mut child = Command::new("fdroid")
+            .args(&args)
+            .current_dir(fdroid_dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to run fdroid update")?;
+
+        // Both streams are read on threads so neither fills up, and their
+        // lines are reported here, in the order they come, as fdroid runs
+        let (lines, received) = mpsc::channel();
+        let stdout = child.stdout.take().map(|stdout| forward_lines(stdout, false, lines.clone()));
+        let stderr = child.stderr.take().map(|stderr| forward_lines(stderr, true, lines));
+
+        let mut warnings = Vec::new();
+        for (from_stderr, line) in received {
+            if !from_stderr {
+                output::print(line);
+                continue;
+            }
+            match parse_log_line(&line) {
+                Some(("WARNING", message)) => {
+                    warn!("{}", message);
+                    warnings.push(parse_warning(message));
+                }
+                Some(("ERROR" | "CRITICAL", message)) => error!("{}", message),
+                Some((_, message)) => info!("{}", message),
+                None => output::print(&line),
+            }
+        }
+
+        for reader in stdout.into_iter().chain(stderr) {
+            let _ = reader.join();
+        }
+        let result = child.wait().context("Failed to run fdroid update")?;
+
+        if !result.success() {
+            anyhow::bail!("Error while running \"fdroid {}\"", args.join(" "));
+        }
+
+        Ok(warnings)
+    }
+}
+
+/// Sends every line of `reader` to `lines`, with whether it is from stderr
+fn forward_lines(reader: impl Read + Send + 'static, from_stderr: bool, lines: Sender<(bool, String)>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(|line| line.ok()) {
+            if lines.send((from_stderr, line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Splits a line of fdroidserver's log into level and message. Verbose runs
+/// prefix it with the time.
+fn parse_log_line(line: &str) -> Option<(&str, &str)> {
+    static LOG_LINE: OnceLock<Regex> = OnceLock::new();
+    let re = LOG_LINE.get_or_init(|| {
+        Regex::new(r"^(?:\d{4}-\d\d-\d\d \d\d:\d\d:\d\d(?:,\d+)? )?(DEBUG|INFO|WARNING|ERROR|CRITICAL): (.*)$").unwrap()
+    });
+
+    let captures = re.captures(line)?;
+    Some((captures.get(1)?.as_str(), captures.get(2)?.as_str()))
+}
+
+/// Finds the APK a warning of fdroidserver is about, such as "Ignoring
+/// package without metadata: app.apk" or "Skipping 'app.apk' with invalid
+/// signature!"
+fn parse_warning(message: &str) -> FdroidWarning {
+    static APK_NAME: OnceLock<Regex> = OnceLock::new();
+    let re = APK_NAME.get_or_init(|| Regex::new(r"[\w.+-]+\.apk\b").unwrap());
+
+    FdroidWarning {
+        package: None,
+        apk_name: re.find(message).map(|m| m.as_str().to_string()),
+        message: message.to_string(),
+    }
+}
+
+/// The built-in index generator, see [`index::update`]
+#[derive(Debug, Default)]
+pub struct NativeBackend {
+    /// Index of the repo before the first update, which diffs start from
+    published: Option<PublishedIndex>,
+}
+
+impl FdroidBackend for NativeBackend {
+    fn update(&mut self, fdroid_dir: &Path, options: &UpdateOptions) -> Result<Vec<FdroidWarning>> {
+        let published = self.published.get_or_insert_with(|| PublishedIndex::read(&fdroid_dir.join("repo")));
+        index::update(fdroid_dir, options, published)
+    }
+}
+
+/// Stands in for fdroid, for running a sync without fdroidserver: records
+/// every update, writes a given index and returns given warnings
+#[derive(Debug, Default)]
+pub struct FakeBackend {
+    /// Written to `repo/index-v1.json` on every update
+    pub index: Option<RepoIndex>,
+    pub warnings: Vec<FdroidWarning>,
+    /// Options of every update so far
+    pub updates: Vec<UpdateOptions>,
+}
+
+impl FdroidBackend for FakeBackend {
+    fn update(&mut self, fdroid_dir: &Path, options: &UpdateOptions) -> Result<Vec<FdroidWarning>> {
+        self.updates.push(options.clone());
+
+        if let Some(index) = &self.index {
+            let path = fdroid_dir.join("repo").join("index-v1.json");
+            fs::write(&path, serde_json::to_string_pretty(index)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+
+        Ok(self.warnings.clone())
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use octocrab::models::repos::{Asset, Release};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::apps::Repo;
+use crate::github::GitHubApi;
+use crate::{download, ratelimit};
+
+/// Names of release assets that list checksums for several files
+const SUMS_FILE_NAMES: &[&str] = &[
+    "sha256sums",
+    "sha256sums.txt",
+    "checksums.txt",
+    "checksums.sha256",
+];
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(sha256_hex(&bytes))
+}
+
+/// Parses GitHub's asset `digest` field, e.g. "sha256:0a1b..."
+pub fn parse_digest(digest: &str) -> Option<String> {
+    digest
+        .strip_prefix("sha256:")
+        .filter(|h| is_sha256(h))
+        .map(|h| h.to_lowercase())
+}
+
+/// Finds the checksum of `file_name` in a `sha256sum`-style listing. A listing
+/// that is a single bare hash applies to any file.
+pub fn find_in_sums(content: &str, file_name: &str) -> Option<String> {
+    let lines = content.lines().map(str::trim).filter(|l| !l.is_empty());
+
+    let mut single = None;
+    for (i, line) in lines.enumerate() {
+        let mut parts = line.split_whitespace();
+        let hash = parts.next()?;
+        if !is_sha256(hash) {
+            continue;
+        }
+
+        match parts.next() {
+            Some(name) if name.trim_start_matches('*').rsplit('/').next() == Some(file_name) => {
+                return Some(hash.to_lowercase());
+            }
+            None if i == 0 => single = Some(hash.to_lowercase()),
+            _ => single = None,
+        }
+    }
+
+    single
+}
+
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Looks up the SHA-256 the publisher provided for `apk`, first from GitHub's
+/// asset digest, then from companion `<apk>.sha256` or `SHA256SUMS` assets
+pub async fn expected_sha256(
+    github: &GitHubApi,
+    repo: &Repo,
+    release: &Release,
+    apk: &Asset,
+) -> Result<Option<String>> {
+    let route = format!("/repos/{}/{}/releases/assets/{}", repo.author, repo.name, apk.id.0);
+    let raw: serde_json::Value = ratelimit::get_json(github, &route).await
+        .context("Failed to look up asset digest")?;
+
+    if let Some(hash) = raw.get("digest").and_then(|d| d.as_str()).and_then(parse_digest) {
+        return Ok(Some(hash));
+    }
+
+    let companion = release.assets.iter().find(|a| {
+        let name = a.name.to_lowercase();
+        name == format!("{}.sha256", apk.name.to_lowercase())
+            || name == format!("{}.sha256sum", apk.name.to_lowercase())
+    });
+
+    if let Some(asset) = companion {
+        let content = download::fetch_asset(github, &repo.author, &repo.name, asset.id.0).await
+            .with_context(|| format!("Failed to fetch checksum file {:?}", asset.name))?;
+
+        // A companion file belongs to this APK, whatever file name it lists
+        return String::from_utf8_lossy(&content)
+            .split_whitespace()
+            .next()
+            .filter(|h| is_sha256(h))
+            .map(|h| Ok(Some(h.to_lowercase())))
+            .unwrap_or_else(|| anyhow::bail!("Checksum file {:?} does not contain a SHA-256", asset.name));
+    }
+
+    let sums = match release
+        .assets
+        .iter()
+        .find(|a| SUMS_FILE_NAMES.contains(&a.name.to_lowercase().as_str()))
+    {
+        Some(asset) => asset,
+        None => return Ok(None),
+    };
+
+    let content = download::fetch_asset(github, &repo.author, &repo.name, sums.id.0).await
+        .with_context(|| format!("Failed to fetch checksum file {:?}", sums.name))?;
+
+    match find_in_sums(&String::from_utf8_lossy(&content), &apk.name) {
+        Some(hash) => Ok(Some(hash)),
+        None => anyhow::bail!("Checksum file {:?} does not list {:?}", sums.name, apk.name),
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use url::Url;
+
+use crate::output;
+
+const REDACTED: &str = "***";
+
+/// Tokens per host, e.g. github.com, a GitHub Enterprise host, gitlab.example.org or codeberg.org.
+/// Clones share their tokens, so a token refreshed through one is used by all.
+#[derive(Debug, Default, Clone)]
+pub struct Credentials {
+    tokens: Arc<RwLock<BTreeMap<String, String>>>,
+}
+
+/// A credentials file entry: either the token itself or where to read it from
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TokenSource {
+    Token(String),
+    Env { env: String },
+}
+
+impl Credentials {
+    /// Reads a YAML file mapping hosts to tokens:
+    ///
+    /// ```yaml
+    /// github.com: ghp_...
+    /// gitlab.example.org:
+    ///   env: GITLAB_TOKEN
+    /// ```
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read credentials file: {}", path.display()))?;
+
+        let entries: BTreeMap<String, TokenSource> = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse YAML from {}", path.display()))?;
+
+        let mut credentials = Self::default();
+        for (host, source) in entries {
+            let token = match source {
+                TokenSource::Token(token) => token,
+                TokenSource::Env { env } => match std::env::var(&env) {
+                    Ok(token) => token,
+                    Err(_) => continue,
+                },
+            };
+            credentials.insert(&host, &token);
+        }
+
+        Ok(credentials)
+    }
+
+    /// Sets the token for `host`, replacing any previous one
+    pub fn insert(&mut self, host: &str, token: &str) {
+        if token.is_empty() {
+            return;
+        }
+
+        register_secret(token);
+        self.tokens.write().unwrap().insert(normalize_host(host), token.to_string());
+    }
+
+    /// Adds the tokens of `other` for hosts that don't have one yet
+    pub fn merge_missing(&mut self, other: Credentials) {
+        let other = other.tokens.read().unwrap().clone();
+        let mut tokens = self.tokens.write().unwrap();
+        for (host, token) in other {
+            tokens.entry(host).or_insert(token);
+        }
+    }
+
+    pub fn token_for_host(&self, host: &str) -> Option<String> {
+        self.tokens.read().unwrap().get(&normalize_host(host)).cloned()
+    }
+
+    pub fn token_for_url(&self, url: &str) -> Option<String> {
+        let url = Url::parse(url).ok()?;
+        self.token_for_host(url.host_str()?)
+    }
+
+    /// Hosts we have a token for, for logging
+    pub fn hosts(&self) -> Vec<String> {
+        self.tokens.read().unwrap().keys().cloned().collect()
+    }
+}
+
+/// Maps hosts to the one users give tokens for: api.github.com to
+/// github.com. Other `api.` hosts may belong to someone else than the host
+/// without the prefix, so they keep theirs.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim().to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    match host {
+        "api.github.com" => "github.com".to_string(),
+        host => host.to_string(),
+    }
+}
+
+/// Tokens to redact from output, shared by the clones of a [`output::Output`]
+#[derive(Debug, Default, Clone)]
+pub struct Secrets {
+    secrets: Arc<Mutex<Vec<String>>>,
+}
+
+impl Secrets {
+    pub fn register(&self, secret: &str) {
+        if secret.len() < 4 {
+            return;
+        }
+
+        let mut secrets = self.secrets.lock().unwrap();
+        if !secrets.iter().any(|s| s == secret) {
+            secrets.push(secret.to_string());
+            // Longest first, so a token containing another is fully redacted
+            secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        }
+    }
+
+    /// Replaces every registered token in `text`
+    pub fn redact(&self, text: &str) -> String {
+        let secrets = self.secrets.lock().unwrap();
+
+        let mut text = text.to_string();
+        for secret in secrets.iter() {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+
+        text
+    }
+}
+
+/// Marks `secret` to be redacted from the output of the current
+/// [`output::Output::scope`]
+pub fn register_secret(secret: &str) {
+    output::current().secrets().register(secret);
+}
+
+/// Replaces every token known to the current [`output::Output::scope`] in `text`
+pub fn redact(text: &str) -> String {
+    output::current().secrets().redact(text)
+}
+
+/// The `Authorization` header git should send to `host` when cloning over HTTPS
+pub fn git_auth_header(host: &str, token: &str) -> String {
+    let host = normalize_host(host);
+
+    let user = if host.contains("gitlab") {
+        "oauth2"
+    } else if host.contains("github") {
+        "x-access-token"
+    } else {
+        "token"
+    };
+
+    let basic = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, token));
+    let header = format!("Authorization: Basic {}", basic);
+
+    register_secret(&basic);
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_github_api_host_shares_the_github_token() {
+        let mut credentials = Credentials::default();
+        credentials.insert("github.com", "github-token");
+        credentials.insert("example.org", "example-token");
+
+        assert_eq!(credentials.token_for_url("https://api.github.com/repos/a/b").as_deref(), Some("github-token"));
+        assert_eq!(credentials.token_for_host("WWW.GitHub.com").as_deref(), Some("github-token"));
+        assert_eq!(credentials.token_for_url("https://api.example.org/v1/releases"), None);
+        assert_eq!(credentials.token_for_url("https://example.org/app.apk").as_deref(), Some("example-token"));
+    }
+}
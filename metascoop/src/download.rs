@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::credentials::Credentials;
+use crate::github::GitHubApi;
+use crate::{checksum, output, ratelimit};
+
+fn client() -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_secs(300))
+        .build()?)
+}
+
+fn asset_request(github: &GitHubApi, owner: &str, repo: &str, asset_id: u64) -> Result<reqwest::RequestBuilder> {
+    let asset_url = format!(
+        "{}/repos/{}/{}/releases/assets/{}",
+        github.base_url, owner, repo, asset_id
+    );
+
+    let mut request = client()?
+        .get(&asset_url)
+        .header("Accept", "application/octet-stream")
+        .header("User-Agent", "metascoop-rs");
+
+    if let Some(token) = github.token() {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+
+    Ok(request)
+}
+
+/// Downloads a release asset to `target_path` and returns its SHA-256. The
+/// file is only moved into place if it matches `expected_sha256`.
+pub async fn download_asset(
+    github: &GitHubApi,
+    owner: &str,
+    repo: &str,
+    asset_id: u64,
+    target_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<String> {
+    let response = ratelimit::send(github, asset_request(github, owner, repo, asset_id)?).await?;
+
+    save_response(response, target_path, expected_sha256).await
+}
+
+/// Fetches a small release asset, e.g. a checksum file, into memory
+pub async fn fetch_asset(github: &GitHubApi, owner: &str, repo: &str, asset_id: u64) -> Result<Vec<u8>> {
+    let response = ratelimit::send(github, asset_request(github, owner, repo, asset_id)?).await?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to download asset: HTTP {}", response.status());
+    }
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Downloads a file from a plain HTTP(S) URL, e.g. an APK linked from a feed.
+/// The token for the URL's host is sent if there is one.
+pub async fn download_url(
+    credentials: &Credentials,
+    url: &str,
+    target_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<String> {
+    let mut request = client()?
+        .get(url)
+        .header("User-Agent", "metascoop-rs");
+
+    if let Some(token) = credentials.token_for_url(url) {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+
+    let response = request.send().await?;
+
+    save_response(response, target_path, expected_sha256).await
+}
+
+/// Writes the response body to a temporary file next to `target_path` and
+/// renames it into place once complete and verified
+async fn save_response(
+    response: reqwest::Response,
+    target_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<String> {
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to download asset: HTTP {}", response.status());
+    }
+
+    let bytes = response.bytes().await?;
+
+    write_verified(bytes, target_path, expected_sha256).await
+}
+
+/// Writes `bytes` to `target_path` through a temporary file, refusing to
+/// rename it into place when the content doesn't match `expected_sha256`.
+/// Hashing and writing run on a blocking thread.
+pub async fn write_verified(
+    bytes: impl AsRef<[u8]> + Send + 'static,
+    target_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<String> {
+    let target_path = target_path.to_path_buf();
+    let expected_sha256 = expected_sha256.map(str::to_string);
+
+    output::spawn_blocking(move || {
+        let bytes = bytes.as_ref();
+        let temp_path = target_path.with_extension("tmp");
+
+        let mut file = fs::File::create(&temp_path)
+            .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+
+        let actual = checksum::sha256_hex(bytes);
+
+        if let Some(expected) = expected_sha256 {
+            if !actual.eq_ignore_ascii_case(&expected) {
+                let _ = fs::remove_file(&temp_path);
+                anyhow::bail!(
+                    "Checksum mismatch for {}: expected sha256 {}, got {}",
+                    target_path.display(), expected, actual
+                );
+            }
+        }
+
+        fs::rename(&temp_path, &target_path)?;
+
+        Ok(actual)
+    }).await
+}
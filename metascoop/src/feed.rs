@@ -0,0 +1,311 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::time::Duration;
+use url::Url;
+
+use crate::apps::{FeedFormat, FeedSource};
+use crate::credentials::Credentials;
+
+/// A release announced by a JSON or Atom feed
+#[derive(Debug, Clone, Default)]
+pub struct FeedRelease {
+    pub version: String,
+    pub apk_url: String,
+    pub notes: String,
+    pub date: String,
+    pub sha256: String,
+}
+
+pub async fn fetch_releases(credentials: &Credentials, source: &FeedSource) -> Result<Vec<FeedRelease>> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()?;
+
+    let mut request = client
+        .get(&source.url)
+        .header("User-Agent", "metascoop-rs");
+
+    if let Some(token) = credentials.token_for_url(&source.url) {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch feed {}", source.url))?;
+
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to fetch feed {}: HTTP {}", source.url, response.status());
+    }
+
+    let body = response.text().await?;
+
+    parse_releases(source, &body)
+}
+
+pub fn parse_releases(source: &FeedSource, body: &str) -> Result<Vec<FeedRelease>> {
+    let mut releases = match source.format {
+        FeedFormat::Json => parse_json(source, body)?,
+        FeedFormat::Atom => parse_atom(source, body)?,
+    };
+
+    for release in &mut releases {
+        if release.apk_url.is_empty() && !source.apk_url_template.is_empty() {
+            release.apk_url = source.apk_url_template.replace("{version}", &release.version);
+        }
+        // Feeds often link to their APKs relative to themselves
+        if !release.apk_url.is_empty() {
+            if let Ok(apk_url) = Url::parse(&source.url).and_then(|url| url.join(&release.apk_url)) {
+                release.apk_url = apk_url.to_string();
+            }
+        }
+    }
+
+    releases.retain(|r| !r.version.is_empty() && !r.apk_url.is_empty());
+
+    Ok(releases)
+}
+
+fn parse_json(source: &FeedSource, body: &str) -> Result<Vec<FeedRelease>> {
+    let doc: Value = serde_json::from_str(body)
+        .with_context(|| format!("Failed to parse JSON feed {}", source.url))?;
+
+    let items: Vec<&Value> = if source.items.is_empty() {
+        vec![&doc]
+    } else {
+        match json_path(&doc, &source.items) {
+            Some(Value::Array(items)) => items.iter().collect(),
+            Some(item) => vec![item],
+            None => anyhow::bail!("Path {:?} not found in feed {}", source.items, source.url),
+        }
+    };
+
+    let field = |item: &Value, path: &str| -> String {
+        if path.is_empty() {
+            return String::new();
+        }
+        match json_path(item, path) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(other) => other.to_string(),
+        }
+    };
+
+    Ok(items
+        .into_iter()
+        .map(|item| FeedRelease {
+            version: field(item, source.version_path()),
+            apk_url: field(item, source.apk_url_path()),
+            notes: field(item, source.notes_path()),
+            date: field(item, source.date_path()),
+            sha256: field(item, &source.sha256),
+        })
+        .collect())
+}
+
+/// Resolves a JSONPath-style expression such as `$.releases[0].assets.apk`.
+/// Only child access by name and array index is supported.
+pub fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.trim().trim_start_matches('$');
+    let mut current = value;
+
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (name, indices) = match segment.find('[') {
+            Some(idx) => (&segment[..idx], &segment[idx..]),
+            None => (segment, ""),
+        };
+
+        if !name.is_empty() {
+            current = current.get(name)?;
+        }
+
+        for index in indices.split('[').filter(|s| !s.is_empty()) {
+            let index: usize = index.trim_end_matches(']').trim().parse().ok()?;
+            current = current.get(index)?;
+        }
+    }
+
+    Some(current)
+}
+
+fn parse_atom(source: &FeedSource, body: &str) -> Result<Vec<FeedRelease>> {
+    let doc = roxmltree::Document::parse(body)
+        .with_context(|| format!("Failed to parse Atom feed {}", source.url))?;
+
+    let items_path = if source.items.is_empty() { "entry" } else { &source.items };
+
+    Ok(xml_path_nodes(doc.root_element(), items_path)
+        .into_iter()
+        .map(|entry| FeedRelease {
+            version: xml_path(entry, source.version_path()),
+            apk_url: xml_path(entry, source.apk_url_path()),
+            notes: xml_path(entry, source.notes_path()),
+            date: xml_path(entry, source.date_path()),
+            sha256: xml_path(entry, &source.sha256),
+        })
+        .collect())
+}
+
+/// A single step of an XPath-style expression, e.g. `link[@rel='enclosure']`
+struct XmlStep<'p> {
+    name: &'p str,
+    predicate: Option<(&'p str, &'p str)>,
+}
+
+fn parse_xml_step(step: &str) -> XmlStep<'_> {
+    match step.find('[') {
+        Some(idx) => {
+            let predicate = step[idx + 1..]
+                .trim_end_matches(']')
+                .trim_start_matches('@')
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim().trim_matches(|c| c == '\'' || c == '"')));
+
+            XmlStep { name: &step[..idx], predicate }
+        }
+        None => XmlStep { name: step, predicate: None },
+    }
+}
+
+fn xml_path_nodes<'a, 'input>(
+    root: roxmltree::Node<'a, 'input>,
+    path: &str,
+) -> Vec<roxmltree::Node<'a, 'input>> {
+    xml_path_nodes_of(root, &xml_steps(path))
+}
+
+fn xml_path_nodes_of<'a, 'input>(
+    root: roxmltree::Node<'a, 'input>,
+    steps: &[&str],
+) -> Vec<roxmltree::Node<'a, 'input>> {
+    let mut nodes = vec![root];
+
+    for step in steps {
+        let step = parse_xml_step(step);
+
+        nodes = nodes
+            .iter()
+            .flat_map(|n| n.children())
+            .filter(|c| c.is_element() && c.tag_name().name() == step.name)
+            .filter(|c| match step.predicate {
+                Some((attr, value)) => c.attribute(attr) == Some(value),
+                None => true,
+            })
+            .collect();
+    }
+
+    nodes
+}
+
+/// Splits an XPath-style expression into its steps, at the slashes outside
+/// of predicates, so `link[@type='application/zip']/@href` has two
+fn xml_steps(path: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in path.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) if depth > 0 => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('[', None) => depth += 1,
+            (']', None) => depth -= 1,
+            ('/', None) if depth == 0 => {
+                steps.push(&path[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    steps.push(&path[start..]);
+
+    steps.retain(|s| !s.is_empty());
+    steps
+}
+
+/// Resolves an XPath-style expression relative to `node`. A trailing `@attr`
+/// step selects an attribute, otherwise the element's text is returned.
+pub fn xml_path(node: roxmltree::Node, path: &str) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+
+    let mut steps = xml_steps(path);
+    let attribute = steps.pop_if(|step| step.starts_with('@')).map(|step| &step[1..]);
+
+    let target = match xml_path_nodes_of(node, &steps).into_iter().next() {
+        Some(n) => n,
+        None => return String::new(),
+    };
+
+    match attribute {
+        Some(attr) => target.attribute(attr).unwrap_or_default().to_string(),
+        None => target
+            .descendants()
+            .filter(|n| n.is_text())
+            .filter_map(|n| n.text())
+            .collect::<String>()
+            .trim()
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testserver::{Response, Server};
+
+    fn source(yaml: &str) -> FeedSource {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn atom_paths_can_have_slashes_in_predicates() {
+        let server = Server::start(|_| Response::new(200, r#"<feed xmlns="http://www.w3.org/2005/Atom">
+  <entry>
+    <title>1.1</title>
+    <link rel="alternate" type="text/html" href="https://example.org/releases/1.1"/>
+    <link rel="enclosure" type="application/vnd.android.package-archive" href="downloads/app-1.1.apk"/>
+  </entry>
+</feed>"#)).await;
+        let source = source(&format!(
+            "url: {}/feeds/releases.atom\nformat: atom\napk_url: \"link[@type='application/vnd.android.package-archive']/@href\"\n",
+            server.url,
+        ));
+
+        let releases = fetch_releases(&Credentials::default(), &source).await.unwrap();
+
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].version, "1.1");
+        assert_eq!(releases[0].apk_url, format!("{}/feeds/downloads/app-1.1.apk", server.url));
+    }
+
+    #[tokio::test]
+    async fn json_apk_urls_are_relative_to_the_feed() {
+        let server = Server::start(|_| Response::json(serde_json::json!({
+            "releases": [
+                { "version": "2.0", "apk": "/files/app-2.0.apk" },
+                { "version": "1.0", "apk": "https://cdn.example.org/app-1.0.apk" },
+            ],
+        }))).await;
+        let source = source(&format!(
+            "url: {}/api/latest.json\nitems: $.releases\nversion: $.version\napk_url: $.apk\n",
+            server.url,
+        ));
+
+        let releases = fetch_releases(&Credentials::default(), &source).await.unwrap();
+
+        let apk_urls: Vec<&str> = releases.iter().map(|r| r.apk_url.as_str()).collect();
+        assert_eq!(apk_urls, [format!("{}/files/app-2.0.apk", server.url).as_str(), "https://cdn.example.org/app-1.0.apk"]);
+        assert_eq!(server.requests()[0].path, "/api/latest.json");
+    }
+
+    #[test]
+    fn xml_steps_split_outside_predicates() {
+        assert_eq!(xml_steps("/entry/link[@type='a/b']/@href"), ["entry", "link[@type='a/b']", "@href"]);
+        assert_eq!(xml_steps("link[@title=\"x]/y\"]"), ["link[@title=\"x]/y\"]"]);
+        assert_eq!(xml_steps("@href"), ["@href"]);
+    }
+}
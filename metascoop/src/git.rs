@@ -2,15 +2,34 @@ use anyhow::{Context, Result};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-pub fn clone_repo(git_url: &str) -> Result<PathBuf> {
+use crate::credentials;
+
+/// Clones `git_url` into a temporary directory. The token is passed to git
+/// as an HTTP header through the environment, so it never shows up in the
+/// command line, the remote URL or git's error messages.
+pub fn clone_repo(git_url: &str, token: Option<&str>) -> Result<PathBuf> {
     let temp_dir = tempfile::tempdir()
         .context("Failed to create temporary directory")?;
     
     let dir_path = temp_dir.path().to_path_buf();
     
-    let output = Command::new("git")
+    let mut command = Command::new("git");
+    command
         .args(["clone", git_url, dir_path.to_str().unwrap()])
-        .output()
+        .env("GIT_TERMINAL_PROMPT", "0");
+
+    if let Some(token) = token {
+        let url = url::Url::parse(git_url)
+            .with_context(|| format!("Failed to parse git URL: {}", git_url))?;
+        let host = url.host_str().unwrap_or_default();
+
+        command
+            .env("GIT_CONFIG_COUNT", "1")
+            .env("GIT_CONFIG_KEY_0", format!("http.{}://{}/.extraheader", url.scheme(), host))
+            .env("GIT_CONFIG_VALUE_0", credentials::git_auth_header(host, token));
+    }
+
+    let output = command.output()
         .context("Failed to execute git clone")?;
 
     if !output.status.success() {
@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use octocrab::Octocrab;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::apps::Repo;
+use crate::credentials::Credentials;
+use crate::ratelimit::RateLimit;
+
+pub const GITHUB_HOST: &str = "github.com";
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Forges that aren't GitHub, so have no GitHub API to derive
+const OTHER_FORGE_HOSTS: &[&str] = &["gitlab.com", "codeberg.org", "gitea.com", "bitbucket.org", "git.sr.ht"];
+
+/// The API of github.com or of a GitHub Enterprise Server
+#[derive(Debug, Clone)]
+pub struct GitHubApi {
+    pub client: Octocrab,
+    /// e.g. `https://api.github.com` or `https://git.example.com/api/v3`
+    pub base_url: String,
+    /// Token sent with requests made outside of octocrab, e.g. asset
+    /// downloads, shared by the clones so it can be refreshed
+    token: Arc<RwLock<Option<String>>>,
+    pub rate_limit: RateLimit,
+}
+
+impl GitHubApi {
+    pub fn new(base_url: &str, token: Option<&str>) -> Result<Self> {
+        let mut builder = Octocrab::builder()
+            .base_uri(base_url)
+            .with_context(|| format!("Invalid GitHub API URL: {}", base_url))?;
+
+        if let Some(token) = token {
+            builder = builder.personal_token(token.to_string());
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: Arc::new(RwLock::new(token.map(str::to_string))),
+            rate_limit: RateLimit::default(),
+        })
+    }
+
+    /// Wraps an already authenticated client, e.g. a GitHub App installation
+    pub fn with_client(client: Octocrab, base_url: &str, token: Option<&str>) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: Arc::new(RwLock::new(token.map(str::to_string))),
+            rate_limit: RateLimit::default(),
+        }
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.token.read().unwrap().clone()
+    }
+
+    /// Replaces the token of requests made outside of octocrab, e.g. with a
+    /// refreshed GitHub App installation token
+    pub fn set_token(&self, token: &str) {
+        *self.token.write().unwrap() = Some(token.to_string());
+    }
+}
+
+/// API URL of a GitHub host: api.github.com for github.com, `/api/v3` on the
+/// host itself for GitHub Enterprise Server. Known forges that aren't GitHub
+/// have none.
+pub fn default_api_url(host: &str) -> Option<String> {
+    if host.is_empty() || host == GITHUB_HOST {
+        Some(GITHUB_API_URL.to_string())
+    } else if OTHER_FORGE_HOSTS.contains(&host) || host.starts_with("gitlab.") {
+        None
+    } else {
+        Some(format!("https://{}/api/v3", host))
+    }
+}
+
+/// GitHub API clients per API URL, created on first use
+pub struct GitHubApis {
+    credentials: Credentials,
+    /// API URLs configured per host, overriding [`default_api_url`]
+    api_urls: HashMap<String, String>,
+    /// Longest a request waits for the rate limit of its API
+    max_rate_limit_wait: Duration,
+    clients: Mutex<HashMap<String, GitHubApi>>,
+}
+
+impl GitHubApis {
+    pub fn new(credentials: Credentials, api_urls: HashMap<String, String>, max_rate_limit_wait: Duration) -> Self {
+        Self {
+            credentials,
+            api_urls,
+            max_rate_limit_wait,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// API URL for `host`, from the configuration or derived from the host
+    pub fn api_url(&self, host: &str) -> Result<String> {
+        self.api_urls
+            .get(host)
+            .cloned()
+            .or_else(|| default_api_url(host))
+            .ok_or_else(|| anyhow::anyhow!(
+                "{} isn't a GitHub host, give its apps a feed, or an API URL if it is a GitHub Enterprise Server",
+                host
+            ))
+    }
+
+    /// Uses `api` for every request to its API URL
+    pub fn insert(&self, api: GitHubApi) {
+        api.rate_limit.set_max_wait(self.max_rate_limit_wait);
+        self.clients.lock().unwrap().insert(api.base_url.clone(), api);
+    }
+
+    /// The API of github.com
+    pub fn github_com(&self) -> Result<GitHubApi> {
+        self.get(GITHUB_HOST, &self.api_url(GITHUB_HOST)?)
+    }
+
+    /// The API hosting `repo`, or `api_url` when the app configures one
+    pub fn for_repo(&self, repo: &Repo, api_url: &str) -> Result<GitHubApi> {
+        let api_url = if api_url.is_empty() {
+            self.api_url(&repo.host)?
+        } else {
+            api_url.to_string()
+        };
+
+        self.get(&repo.host, &api_url)
+    }
+
+    fn get(&self, host: &str, api_url: &str) -> Result<GitHubApi> {
+        let key = api_url.trim_end_matches('/');
+
+        if let Some(api) = self.clients.lock().unwrap().get(key) {
+            return Ok(api.clone());
+        }
+
+        let token = self.credentials
+            .token_for_host(host)
+            .or_else(|| self.credentials.token_for_url(api_url));
+
+        let api = GitHubApi::new(api_url, token.as_deref())?;
+        self.insert(api.clone());
+
+        Ok(api)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_url_of_a_host() {
+        assert_eq!(default_api_url("github.com").as_deref(), Some("https://api.github.com"));
+        assert_eq!(default_api_url("git.example.com").as_deref(), Some("https://git.example.com/api/v3"));
+        assert_eq!(default_api_url("gitlab.com"), None);
+        assert_eq!(default_api_url("gitlab.example.org"), None);
+        assert_eq!(default_api_url("codeberg.org"), None);
+
+        let api_urls = HashMap::from([("codeberg.org".to_string(), "https://codeberg.org/api/v3".to_string())]);
+        let apis = GitHubApis::new(Credentials::default(), api_urls, Duration::from_secs(60));
+        assert_eq!(apis.api_url("codeberg.org").unwrap(), "https://codeberg.org/api/v3");
+        let error = apis.api_url("gitlab.com").unwrap_err();
+        assert!(error.to_string().contains("isn't a GitHub host"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn refreshed_tokens_reach_every_clone() {
+        let mut credentials = Credentials::default();
+        credentials.insert(GITHUB_HOST, "installation-token-1");
+        let apis = GitHubApis::new(credentials.clone(), HashMap::new(), Duration::from_secs(60));
+
+        apis.github_com().unwrap().set_token("installation-token-2");
+        credentials.clone().insert(GITHUB_HOST, "installation-token-2");
+
+        assert_eq!(apis.github_com().unwrap().token().as_deref(), Some("installation-token-2"));
+        assert_eq!(credentials.token_for_url("https://github.com/a/b").as_deref(), Some("installation-token-2"));
+    }
+}
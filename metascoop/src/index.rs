@@ -0,0 +1,902 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use md5::Md5;
+use serde_json::{json, Map, Value};
+use sha2::Digest;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::apk::{self, ApkInfo, Permission};
+use crate::apps::{self, MetaFile};
+use crate::backend::{FdroidWarning, UpdateOptions};
+use crate::checksum;
+use crate::jar::{self, RepoKey};
+
+/// Version of the index format, as written by fdroidserver 2.x
+const INDEX_VERSION: u64 = 20002;
+
+const LOCALE: &str = "en-US";
+
+/// Screenshot directories of a locale, with their name in index-v2
+const SCREENSHOT_KINDS: &[(&str, &str)] = &[
+    ("phoneScreenshots", "phone"),
+    ("sevenInchScreenshots", "sevenInch"),
+    ("tenInchScreenshots", "tenInch"),
+    ("tvScreenshots", "tv"),
+    ("wearScreenshots", "wear"),
+];
+
+/// Metadata fields copied as they are into both indexes
+const PLAIN_FIELDS: &[(&str, &str)] = &[
+    ("AuthorName", "authorName"),
+    ("AuthorEmail", "authorEmail"),
+    ("AuthorWebSite", "authorWebSite"),
+    ("License", "license"),
+    ("SourceCode", "sourceCode"),
+    ("WebSite", "webSite"),
+    ("IssueTracker", "issueTracker"),
+    ("Changelog", "changelog"),
+    ("Translation", "translation"),
+];
+
+/// Metadata fields that are localized in index-v2
+const TEXT_FIELDS: &[(&str, &str)] = &[
+    ("Name", "name"),
+    ("Summary", "summary"),
+    ("Description", "description"),
+];
+
+/// Repo settings read from fdroid's `config.yml`
+struct RepoConfig {
+    url: String,
+    name: String,
+    description: String,
+    icon: String,
+    keystore: PathBuf,
+    keystore_pass: String,
+    key_alias: String,
+}
+
+impl RepoConfig {
+    fn load(fdroid_dir: &Path) -> Result<Self> {
+        let path = fdroid_dir.join("config.yml");
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: serde_yaml::Mapping = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse YAML from {}", path.display()))?;
+
+        let get = |key: &str| config_value(&config, key);
+        let require = |key: &str| get(key)
+            .ok_or_else(|| anyhow::anyhow!("{} has no {}", path.display(), key));
+
+        Ok(Self {
+            url: require("repo_url")?,
+            name: get("repo_name").unwrap_or_default(),
+            description: get("repo_description").unwrap_or_default(),
+            icon: get("repo_icon").unwrap_or_else(|| "icon.png".to_string()),
+            keystore: fdroid_dir.join(get("keystore").unwrap_or_else(|| "keystore.p12".to_string())),
+            keystore_pass: require("keystorepass")?,
+            key_alias: require("repo_keyalias")?,
+        })
+    }
+}
+
+/// A string setting, or the environment variable named by `{env: NAME}`
+fn config_value(config: &serde_yaml::Mapping, key: &str) -> Option<String> {
+    match config.get(key)? {
+        serde_yaml::Value::String(s) => Some(s.trim().to_string()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        serde_yaml::Value::Mapping(m) => {
+            let var = m.get("env")?.as_str()?;
+            std::env::var(var).ok()
+        }
+        _ => None,
+    }
+}
+
+/// SHA-256 fingerprint of the repo signing certificate, from the keystore in
+/// `config.yml`. It is never read from the signed index, which is what the
+/// fingerprint is there to check.
+pub fn repo_fingerprint(fdroid_dir: &Path) -> Result<String> {
+    let config = RepoConfig::load(fdroid_dir)?;
+    let key = RepoKey::load(&config.keystore, &config.keystore_pass, &config.key_alias)?;
+    Ok(checksum::sha256_hex(key.certificate()))
+}
+
+/// An APK of the repo directory
+struct Apk {
+    file_name: String,
+    sha256: String,
+    size: u64,
+    info: ApkInfo,
+    /// MD5 of the hex-encoded certificate, the legacy `sig` of index-v1
+    sig: String,
+    added: u64,
+}
+
+/// Oldest indexes clients can still update from with a diff
+const MAX_DIFFS: usize = 10;
+
+/// The index-v2 clients have: the one published before a sync, with the
+/// diffs leading to it from older indexes
+#[derive(Debug, Clone, Default)]
+pub struct PublishedIndex {
+    index: Option<Value>,
+    /// Merge patches to `index`, by the timestamp of the index they apply to
+    diffs: BTreeMap<u64, Value>,
+}
+
+impl PublishedIndex {
+    /// Reads `index-v2.json` of the repo directory and the diffs `entry.json`
+    /// lists. Diffs that can't be read are left out.
+    pub fn read(repo_dir: &Path) -> Self {
+        let read_json = |path: &Path| -> Option<Value> {
+            serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+        };
+
+        let index = read_json(&repo_dir.join("index-v2.json"));
+        let mut diffs = BTreeMap::new();
+        if let Some(entry) = read_json(&repo_dir.join("entry.json")) {
+            for (timestamp, diff) in entry["diffs"].as_object().into_iter().flatten() {
+                let (Ok(timestamp), Some(name)) = (timestamp.parse(), diff["name"].as_str()) else { continue };
+                match read_json(&repo_dir.join(name.trim_start_matches('/'))) {
+                    Some(patch) => {
+                        diffs.insert(timestamp, patch);
+                    }
+                    None => warn!("Dropping diff {}, which can't be read", name),
+                }
+            }
+        }
+
+        Self { index, diffs }
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.index.as_ref()?["repo"]["timestamp"].as_u64()
+    }
+}
+
+/// Writes `index-v1.json`, `index-v2.json` and `entry.json` in the repo
+/// directory under `fdroid_dir`, and signs `index-v1.jar` and `entry.jar`, the
+/// way `fdroid update` does. The final update of a sync also writes the diffs
+/// from the `published` index and older ones. APKs that can't be read and
+/// packages without metadata are skipped with a warning.
+pub fn update(fdroid_dir: &Path, options: &UpdateOptions, published: &PublishedIndex) -> Result<Vec<FdroidWarning>> {
+    let config = RepoConfig::load(fdroid_dir)?;
+    let key = RepoKey::load(&config.keystore, &config.keystore_pass, &config.key_alias)?;
+    info!("Signing with key {:?}, repo fingerprint {}", config.key_alias, checksum::sha256_hex(key.certificate()));
+
+    let repo_dir = fdroid_dir.join("repo");
+    let metadata_dir = fdroid_dir.join("metadata");
+    let timestamp = now_millis();
+
+    let previous: Option<Value> = fs::read_to_string(repo_dir.join("index-v2.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+
+    let mut warnings = Vec::new();
+    let mut packages: BTreeMap<String, Vec<Apk>> = BTreeMap::new();
+    for apk in scan_apks(&repo_dir, previous.as_ref(), timestamp, &mut warnings)? {
+        packages.entry(apk.info.package_name.clone()).or_default().push(apk);
+    }
+
+    let mut v1_apps = Vec::new();
+    let mut v1_packages = Map::new();
+    let mut v2_packages = Map::new();
+    let mut categories = BTreeSet::new();
+    let mut anti_features = BTreeSet::new();
+
+    for (package_name, mut apks) in packages {
+        let meta_path = metadata_dir.join(format!("{}.yml", package_name));
+        apks.sort_by_key(|a| std::cmp::Reverse(a.info.version_code));
+
+        if !meta_path.exists() {
+            if options.create_metadata {
+                create_metadata(&meta_path, &apks[0].info)?;
+            } else if options.delete_unknown {
+                for apk in &apks {
+                    fs::remove_file(repo_dir.join(&apk.file_name))?;
+                    warnings.push(package_warning(&package_name, Some(apk), "Deleted APK of a package without metadata"));
+                }
+                continue;
+            } else {
+                warnings.push(package_warning(&package_name, None, "Skipped package without metadata"));
+                continue;
+            }
+        }
+
+        let meta = apps::read_meta_file(&meta_path)?;
+        let package_dir = repo_dir.join(&package_name);
+        copy_graphics(&metadata_dir.join(&package_name), &package_dir)?;
+
+        let app_categories = string_list(meta.get("Categories"));
+        let app_anti_features = string_list(meta.get("AntiFeatures"));
+        categories.extend(app_categories.iter().cloned());
+        anti_features.extend(app_anti_features.iter().cloned());
+
+        let suggested = suggested_apk(&meta, &apks);
+        let added = apks.iter().map(|a| a.added).min().unwrap_or(timestamp);
+        let previous_added = previous.as_ref()
+            .and_then(|p| p["packages"][&package_name]["metadata"]["added"].as_u64());
+        let added = previous_added.unwrap_or(added).min(added);
+        let last_updated = apks.iter().map(|a| a.added).max().unwrap_or(timestamp);
+
+        // index-v1
+        let mut app = Map::new();
+        for (field, name) in PLAIN_FIELDS.iter().chain(TEXT_FIELDS) {
+            if let Some(value) = meta_string(&meta, field) {
+                app.insert(name.to_string(), json!(value));
+            }
+        }
+        if !app_categories.is_empty() {
+            app.insert("categories".to_string(), json!(app_categories));
+        }
+        if !app_anti_features.is_empty() {
+            app.insert("antiFeatures".to_string(), json!(app_anti_features));
+        }
+        app.insert("suggestedVersionName".to_string(), json!(suggested.info.version_name));
+        app.insert("suggestedVersionCode".to_string(), json!(suggested.info.version_code.to_string()));
+        app.insert("added".to_string(), json!(added));
+        app.insert("packageName".to_string(), json!(package_name));
+        app.insert("lastUpdated".to_string(), json!(last_updated));
+
+        let mut localized = Map::new();
+        for (locale, kinds) in graphics(&package_dir) {
+            let mut entry = Map::new();
+            for (kind, files) in kinds {
+                if kind == "icon" {
+                    entry.insert("icon".to_string(), json!(files[0].0));
+                } else {
+                    let names: Vec<&String> = files.iter().map(|(name, _)| name).collect();
+                    entry.insert(kind, json!(names));
+                }
+            }
+            if let Some(whats_new) = whats_new(&metadata_dir, &package_name, &locale, suggested.info.version_code) {
+                entry.insert("whatsNew".to_string(), json!(whats_new));
+            }
+            localized.insert(locale, Value::Object(entry));
+        }
+        if !localized.is_empty() {
+            app.insert("localized".to_string(), Value::Object(localized));
+        }
+        v1_apps.push(Value::Object(app));
+
+        v1_packages.insert(
+            package_name.clone(),
+            Value::Array(apks.iter().map(v1_package).collect()),
+        );
+
+        // index-v2
+        let mut metadata = Map::new();
+        metadata.insert("added".to_string(), json!(added));
+        metadata.insert("lastUpdated".to_string(), json!(last_updated));
+        for (field, name) in PLAIN_FIELDS {
+            if let Some(value) = meta_string(&meta, field) {
+                metadata.insert(name.to_string(), json!(value));
+            }
+        }
+        for (field, name) in TEXT_FIELDS {
+            if let Some(value) = meta_string(&meta, field) {
+                metadata.insert(name.to_string(), json!({ LOCALE: value }));
+            }
+        }
+        if !app_categories.is_empty() {
+            metadata.insert("categories".to_string(), json!(app_categories));
+        }
+
+        let mut screenshots = Map::new();
+        for (locale, kinds) in graphics(&package_dir) {
+            for (kind, files) in kinds {
+                if kind == "icon" {
+                    let icon = metadata.entry("icon").or_insert_with(|| json!({}));
+                    icon[&locale] = file_entry(&format!("/{}/{}/{}", package_name, locale, files[0].0), &files[0].1)?;
+                } else if let Some((_, v2_kind)) = SCREENSHOT_KINDS.iter().find(|(k, _)| *k == kind) {
+                    let entries: Vec<Value> = files.iter()
+                        .map(|(name, path)| file_entry(&format!("/{}/{}/{}/{}", package_name, locale, kind, name), path))
+                        .collect::<Result<_>>()?;
+                    let by_locale = screenshots.entry(v2_kind.to_string()).or_insert_with(|| json!({}));
+                    by_locale[&locale] = Value::Array(entries);
+                }
+            }
+        }
+        if !screenshots.is_empty() {
+            metadata.insert("screenshots".to_string(), Value::Object(screenshots));
+        }
+        metadata.insert("preferredSigner".to_string(), json!(suggested.info.signer));
+
+        let mut versions = Map::new();
+        for apk in &apks {
+            let mut version = v2_version(apk);
+            if let Some(whats_new) = whats_new(&metadata_dir, &package_name, LOCALE, apk.info.version_code) {
+                version["whatsNew"] = json!({ LOCALE: whats_new });
+            }
+            if !app_anti_features.is_empty() {
+                let by_name: Map<String, Value> = app_anti_features.iter()
+                    .map(|name| (name.clone(), json!({})))
+                    .collect();
+                version["antiFeatures"] = Value::Object(by_name);
+            }
+            versions.insert(apk.sha256.clone(), version);
+        }
+
+        v2_packages.insert(package_name.clone(), json!({
+            "metadata": metadata,
+            "versions": versions,
+        }));
+    }
+
+    // Repo
+    let icon_path = repo_dir.join("icons").join(&config.icon);
+    let v1 = json!({
+        "repo": {
+            "timestamp": timestamp,
+            "version": INDEX_VERSION,
+            "name": config.name,
+            "icon": config.icon,
+            "address": config.url,
+            "description": config.description,
+        },
+        "requests": { "install": [], "uninstall": [] },
+        "apps": v1_apps,
+        "packages": v1_packages,
+    });
+
+    let mut v2_repo = json!({
+        "name": { LOCALE: config.name },
+        "description": { LOCALE: config.description },
+        "address": config.url,
+        "timestamp": timestamp,
+    });
+    if icon_path.exists() {
+        v2_repo["icon"] = json!({ LOCALE: file_entry(&format!("/icons/{}", config.icon), &icon_path)? });
+    }
+    if !categories.is_empty() {
+        v2_repo["categories"] = names_by_locale(&categories);
+    }
+    if !anti_features.is_empty() {
+        v2_repo["antiFeatures"] = names_by_locale(&anti_features);
+    }
+    let v2 = json!({ "repo": v2_repo, "packages": v2_packages });
+
+    // Files
+    let v1_json = serde_json::to_string_pretty(&v1)?;
+    fs::write(repo_dir.join("index-v1.json"), &v1_json)?;
+    jar::write_signed_jar(&repo_dir.join("index-v1.jar"), "index-v1.json", v1_json.as_bytes(), &key)?;
+
+    let v2_json = serde_json::to_string_pretty(&v2)?;
+    fs::write(repo_dir.join("index-v2.json"), &v2_json)?;
+
+    let mut entry = json!({
+        "timestamp": timestamp,
+        "version": INDEX_VERSION,
+        "index": {
+            "name": "/index-v2.json",
+            "sha256": checksum::sha256_hex(v2_json.as_bytes()),
+            "size": v2_json.len(),
+            "numPackages": v2["packages"].as_object().map_or(0, Map::len),
+        },
+    });
+    if options.final_update {
+        if let Some(diffs) = write_diffs(&repo_dir, published, &v2)? {
+            entry["diffs"] = diffs;
+        }
+    }
+
+    let entry_json = serde_json::to_string_pretty(&entry)?;
+    fs::write(repo_dir.join("entry.json"), &entry_json)?;
+    jar::write_signed_jar(&repo_dir.join("entry.jar"), "entry.json", entry_json.as_bytes(), &key)?;
+
+    info!("Wrote the index of {} packages", v1["packages"].as_object().map_or(0, Map::len));
+
+    Ok(warnings)
+}
+
+/// Logs and returns a warning about a package or one of its APKs
+fn package_warning(package_name: &str, apk: Option<&Apk>, message: &str) -> FdroidWarning {
+    let message = match apk {
+        Some(apk) => format!("{}: {}", message, apk.file_name),
+        None => format!("{}: {}", message, package_name),
+    };
+    warn!("{}", message);
+
+    FdroidWarning {
+        package: Some(package_name.to_string()),
+        apk_name: apk.map(|a| a.file_name.clone()),
+        message,
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Reads every APK of the repo directory. APKs already in the previous index
+/// keep the time they were added.
+fn scan_apks(repo_dir: &Path, previous: Option<&Value>, timestamp: u64, warnings: &mut Vec<FdroidWarning>) -> Result<Vec<Apk>> {
+    let mut apks = Vec::new();
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(repo_dir)
+        .with_context(|| format!("Failed to read {}", repo_dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "apk"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let bytes = fs::read(&path)
+            .with_context(|| format!("Failed to read APK: {}", path.display()))?;
+        let info = match apk::parse_apk(&bytes) {
+            Ok(info) => info,
+            Err(e) => {
+                let message = format!("Skipped APK that can't be read: {}: {:#}", file_name, e);
+                warn!("{}", message);
+                warnings.push(FdroidWarning {
+                    package: None,
+                    apk_name: Some(file_name),
+                    message,
+                });
+                continue;
+            }
+        };
+        let sha256 = checksum::sha256_hex(&bytes);
+
+        let added = previous
+            .and_then(|p| p["packages"][&info.package_name]["versions"][&sha256]["added"].as_u64())
+            .unwrap_or(timestamp);
+
+        apks.push(Apk {
+            file_name,
+            sig: hex::encode(Md5::digest(hex::encode(&info.certificate))),
+            size: bytes.len() as u64,
+            sha256,
+            info,
+            added,
+        });
+    }
+
+    Ok(apks)
+}
+
+/// Writes a metadata file for a new package, like `fdroid update --create-metadata`
+fn create_metadata(path: &Path, info: &ApkInfo) -> Result<()> {
+    info!("Creating metadata file {:?}", path);
+
+    let mut meta = MetaFile::new();
+    meta.insert("License".to_string(), "Unknown".into());
+    meta.insert("CurrentVersion".to_string(), info.version_name.clone().into());
+    meta.insert("CurrentVersionCode".to_string(), info.version_code.into());
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    apps::write_meta_file(path, &meta)
+}
+
+/// The newest APK not newer than `CurrentVersionCode`
+fn suggested_apk<'a>(meta: &MetaFile, apks: &'a [Apk]) -> &'a Apk {
+    let current = meta.get("CurrentVersionCode").and_then(|v| match v {
+        serde_yaml::Value::Number(n) => n.as_i64(),
+        serde_yaml::Value::String(s) => s.parse().ok(),
+        _ => None,
+    });
+
+    // APKs are sorted newest first
+    current
+        .and_then(|code| apks.iter().find(|a| a.info.version_code <= code))
+        .unwrap_or(&apks[0])
+}
+
+/// Copies the icons and screenshots of the metadata directory of a package
+/// to the repo, replacing the published ones
+fn copy_graphics(metadata_package_dir: &Path, package_dir: &Path) -> Result<()> {
+    let Ok(locales) = fs::read_dir(metadata_package_dir) else {
+        return Ok(());
+    };
+
+    for locale in locales.filter_map(|e| e.ok()).filter(|e| e.path().is_dir()) {
+        let locale_dir = locale.path();
+        let repo_locale_dir = package_dir.join(locale.file_name());
+
+        for (kind, _) in SCREENSHOT_KINDS {
+            let Ok(files) = fs::read_dir(locale_dir.join(kind)) else {
+                continue;
+            };
+
+            let target = repo_locale_dir.join(kind);
+            let _ = fs::remove_dir_all(&target);
+            fs::create_dir_all(&target)?;
+            for file in files.filter_map(|e| e.ok()).filter(|e| e.path().is_file()) {
+                fs::copy(file.path(), target.join(file.file_name()))?;
+            }
+        }
+
+        let icon = locale_dir.join("icon.png");
+        if icon.is_file() {
+            fs::create_dir_all(&repo_locale_dir)?;
+            fs::copy(&icon, repo_locale_dir.join("icon.png"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Published icons and screenshots of a package, by locale, then by
+/// screenshot directory or `icon`, as sorted file names and paths
+fn graphics(package_dir: &Path) -> BTreeMap<String, BTreeMap<String, Vec<(String, PathBuf)>>> {
+    let mut graphics: BTreeMap<String, BTreeMap<String, Vec<(String, PathBuf)>>> = BTreeMap::new();
+
+    let Ok(locales) = fs::read_dir(package_dir) else {
+        return graphics;
+    };
+
+    for locale in locales.filter_map(|e| e.ok()).filter(|e| e.path().is_dir()) {
+        let locale_name = locale.file_name().to_string_lossy().into_owned();
+        let mut kinds = BTreeMap::new();
+
+        for (kind, _) in SCREENSHOT_KINDS {
+            let Ok(files) = fs::read_dir(locale.path().join(kind)) else {
+                continue;
+            };
+            let mut files: Vec<(String, PathBuf)> = files.filter_map(|e| e.ok())
+                .filter(|e| e.path().is_file())
+                .map(|e| (e.file_name().to_string_lossy().into_owned(), e.path()))
+                .collect();
+            files.sort();
+            if !files.is_empty() {
+                kinds.insert(kind.to_string(), files);
+            }
+        }
+
+        let icon = locale.path().join("icon.png");
+        if icon.is_file() {
+            kinds.insert("icon".to_string(), vec![("icon.png".to_string(), icon)]);
+        }
+
+        if !kinds.is_empty() {
+            graphics.insert(locale_name, kinds);
+        }
+    }
+
+    graphics
+}
+
+/// The changelog of a version, from `metadata/<package>/<locale>/changelogs`
+fn whats_new(metadata_dir: &Path, package_name: &str, locale: &str, version_code: i64) -> Option<String> {
+    let path = metadata_dir
+        .join(package_name)
+        .join(locale)
+        .join("changelogs")
+        .join(format!("{}.txt", version_code));
+    fs::read_to_string(path).ok().filter(|s| !s.trim().is_empty())
+}
+
+fn meta_string(meta: &MetaFile, field: &str) -> Option<String> {
+    match meta.get(field)? {
+        serde_yaml::Value::String(s) if !s.is_empty() => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// A list field, written as a YAML list, mapping or comma-separated string
+fn string_list(value: Option<&serde_yaml::Value>) -> Vec<String> {
+    match value {
+        Some(serde_yaml::Value::String(s)) => s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Some(serde_yaml::Value::Sequence(items)) => items.iter()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        Some(serde_yaml::Value::Mapping(map)) => map.keys()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn names_by_locale(names: &BTreeSet<String>) -> Value {
+    let by_name: Map<String, Value> = names.iter()
+        .map(|name| (name.clone(), json!({ "name": { LOCALE: name } })))
+        .collect();
+    Value::Object(by_name)
+}
+
+/// A file reference of index-v2
+fn file_entry(name: &str, path: &Path) -> Result<Value> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .len();
+    Ok(json!({
+        "name": name,
+        "sha256": checksum::file_sha256(path)?,
+        "size": size,
+    }))
+}
+
+fn permissions_v1(permissions: &[Permission]) -> Value {
+    Value::Array(permissions.iter().map(|p| json!([p.name, p.max_sdk_version])).collect())
+}
+
+fn permissions_v2(permissions: &[Permission]) -> Value {
+    Value::Array(permissions.iter().map(|p| match p.max_sdk_version {
+        Some(max) => json!({ "name": p.name, "maxSdkVersion": max }),
+        None => json!({ "name": p.name }),
+    }).collect())
+}
+
+fn v1_package(apk: &Apk) -> Value {
+    let mut package = json!({
+        "added": apk.added,
+        "apkName": apk.file_name,
+        "hash": apk.sha256,
+        "hashType": "sha256",
+        "minSdkVersion": apk.info.min_sdk_version,
+        "packageName": apk.info.package_name,
+        "sig": apk.sig,
+        "signer": apk.info.signer,
+        "size": apk.size,
+        "targetSdkVersion": apk.info.target_sdk_version,
+        "versionCode": apk.info.version_code,
+        "versionName": apk.info.version_name,
+    });
+    if !apk.info.nativecode.is_empty() {
+        package["nativecode"] = json!(apk.info.nativecode);
+    }
+    if !apk.info.permissions.is_empty() {
+        package["uses-permission"] = permissions_v1(&apk.info.permissions);
+    }
+    if !apk.info.permissions_sdk_23.is_empty() {
+        package["uses-permission-sdk-23"] = permissions_v1(&apk.info.permissions_sdk_23);
+    }
+    package
+}
+
+fn v2_version(apk: &Apk) -> Value {
+    let mut manifest = json!({
+        "versionName": apk.info.version_name,
+        "versionCode": apk.info.version_code,
+        "usesSdk": {
+            "minSdkVersion": apk.info.min_sdk_version,
+            "targetSdkVersion": apk.info.target_sdk_version,
+        },
+        "signer": { "sha256": [apk.info.signer] },
+    });
+    if !apk.info.nativecode.is_empty() {
+        manifest["nativecode"] = json!(apk.info.nativecode);
+    }
+    if !apk.info.permissions.is_empty() {
+        manifest["usesPermission"] = permissions_v2(&apk.info.permissions);
+    }
+    if !apk.info.permissions_sdk_23.is_empty() {
+        manifest["usesPermissionSdk23"] = permissions_v2(&apk.info.permissions_sdk_23);
+    }
+
+    json!({
+        "added": apk.added,
+        "file": {
+            "name": format!("/{}", apk.file_name),
+            "sha256": apk.sha256,
+            "size": apk.size,
+        },
+        "manifest": manifest,
+    })
+}
+
+/// Writes the JSON Merge Patches (RFC 7386) from the published index-v2 and
+/// the older indexes it has diffs from to the `new` one, as
+/// `diff/<timestamp>.json`, keeping the [`MAX_DIFFS`] newest. Other files of
+/// `diff` are removed, as they don't lead to the new index. Returns the
+/// `diffs` of entry.json.
+fn write_diffs(repo_dir: &Path, published: &PublishedIndex, new: &Value) -> Result<Option<Value>> {
+    let mut patches = BTreeMap::new();
+    if let (Some(index), Some(timestamp)) = (&published.index, published.timestamp()) {
+        let patch = merge_patch(index, new).unwrap_or_else(|| json!({}));
+        for (&old_timestamp, old_patch) in &published.diffs {
+            match compose_patches(old_patch, &patch) {
+                Some(composed) => {
+                    patches.insert(old_timestamp, composed);
+                }
+                None => info!("Dropping the diff from {}, which can't be written as one patch", old_timestamp),
+            }
+        }
+        patches.insert(timestamp, patch);
+    }
+    while patches.len() > MAX_DIFFS {
+        patches.pop_first();
+    }
+
+    let diff_dir = repo_dir.join("diff");
+    if let Ok(entries) = fs::read_dir(&diff_dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let keep = entry.path().file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+                .is_some_and(|t| patches.contains_key(&t));
+            if !keep {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+
+    if patches.is_empty() {
+        return Ok(None);
+    }
+    fs::create_dir_all(&diff_dir)?;
+
+    let mut diffs = Map::new();
+    for (timestamp, patch) in patches {
+        let num_packages = patch["packages"].as_object().map_or(0, Map::len);
+        let patch_json = serde_json::to_string_pretty(&patch)?;
+        let name = format!("{}.json", timestamp);
+        fs::write(diff_dir.join(&name), &patch_json)?;
+
+        diffs.insert(timestamp.to_string(), json!({
+            "name": format!("/diff/{}", name),
+            "sha256": checksum::sha256_hex(patch_json.as_bytes()),
+            "size": patch_json.len(),
+            "numPackages": num_packages,
+        }));
+    }
+    Ok(Some(Value::Object(diffs)))
+}
+
+/// The merge patch doing `first` and then `second`. `None` when that can't
+/// be written as one patch: when `second` patches an object into a member
+/// that `first` removed or replaced, the result depends on what was there.
+fn compose_patches(first: &Value, second: &Value) -> Option<Value> {
+    let (Value::Object(first), Value::Object(second)) = (first, second) else {
+        return None;
+    };
+
+    let mut patch = first.clone();
+    for (key, second_value) in second {
+        let value = match (first.get(key), second_value) {
+            (Some(first_value @ Value::Object(_)), Value::Object(_)) => compose_patches(first_value, second_value)?,
+            (Some(_), Value::Object(_)) => return None,
+            _ => second_value.clone(),
+        };
+        patch.insert(key.clone(), value);
+    }
+    Some(Value::Object(patch))
+}
+
+/// The merge patch turning `old` into `new`, `None` when they are equal
+fn merge_patch(old: &Value, new: &Value) -> Option<Value> {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut patch = Map::new();
+            for (key, old_value) in old {
+                match new.get(key) {
+                    None => {
+                        patch.insert(key.clone(), Value::Null);
+                    }
+                    Some(new_value) => {
+                        if let Some(p) = merge_patch(old_value, new_value) {
+                            patch.insert(key.clone(), p);
+                        }
+                    }
+                }
+            }
+            for (key, new_value) in new {
+                if !old.contains_key(key) {
+                    patch.insert(key.clone(), new_value.clone());
+                }
+            }
+            (!patch.is_empty()).then_some(Value::Object(patch))
+        }
+        _ if old == new => None,
+        _ => Some(new.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies a merge patch as clients do (RFC 7386)
+    fn apply(target: &Value, patch: &Value) -> Value {
+        let Value::Object(patch) = patch else { return patch.clone() };
+        let mut result = match target {
+            Value::Object(target) => target.clone(),
+            _ => Map::new(),
+        };
+        for (key, value) in patch {
+            if value.is_null() {
+                result.remove(key);
+            } else {
+                let patched = apply(result.get(key).unwrap_or(&Value::Null), value);
+                result.insert(key.clone(), patched);
+            }
+        }
+        Value::Object(result)
+    }
+
+    fn index(timestamp: u64, packages: Value) -> Value {
+        json!({ "repo": { "timestamp": timestamp, "name": "Repo" }, "packages": packages })
+    }
+
+    #[test]
+    fn merge_patch_leads_to_the_new_value() {
+        let old = json!({ "a": 1, "b": { "c": [1, 2], "d": "x" }, "e": true });
+        let new = json!({ "a": 1, "b": { "c": [2], "f": null }, "g": { "h": 1 } });
+
+        let patch = merge_patch(&old, &new).unwrap();
+        assert_eq!(patch, json!({ "b": { "c": [2], "d": null, "f": null }, "e": null, "g": { "h": 1 } }));
+        // A null in the new value can't be told apart from a removal
+        assert_eq!(apply(&old, &patch), json!({ "a": 1, "b": { "c": [2] }, "g": { "h": 1 } }));
+        assert_eq!(merge_patch(&old, &old), None);
+    }
+
+    #[test]
+    fn composed_patches_do_both() {
+        let v1 = json!({ "packages": { "a": { "v": 1 }, "b": { "v": 1 } }, "n": 1 });
+        let v2 = json!({ "packages": { "a": { "v": 2 }, "c": { "v": 1 } }, "n": 2 });
+        let v3 = json!({ "packages": { "a": { "v": 3, "w": 1 }, "c": { "v": 1 }, "d": { "v": 1 } }, "n": 2 });
+
+        let first = merge_patch(&v1, &v2).unwrap();
+        let second = merge_patch(&v2, &v3).unwrap();
+        let composed = compose_patches(&first, &second).unwrap();
+        assert_eq!(apply(&v1, &composed), v3);
+    }
+
+    #[test]
+    fn composing_a_removed_member_patched_again_fails() {
+        // Package b is removed, then added back with other fields
+        let v1 = json!({ "packages": { "b": { "v": 1, "x": 1 } } });
+        let v2 = json!({ "packages": {} });
+        let v3 = json!({ "packages": { "b": { "v": 2 } } });
+
+        let first = merge_patch(&v1, &v2).unwrap();
+        let second = merge_patch(&v2, &v3).unwrap();
+        assert_eq!(compose_patches(&first, &second), None);
+    }
+
+    #[test]
+    fn diffs_lead_from_published_and_older_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_dir = dir.path();
+
+        let v1 = index(1, json!({ "a": { "v": 1 } }));
+        let v2 = index(2, json!({ "a": { "v": 2 } }));
+        let v3 = index(3, json!({ "a": { "v": 2 }, "b": { "v": 1 } }));
+        let stale = json!({ "unrelated": true });
+
+        fs::create_dir_all(repo_dir.join("diff")).unwrap();
+        fs::write(repo_dir.join("diff/1.json"), merge_patch(&v1, &v2).unwrap().to_string()).unwrap();
+        fs::write(repo_dir.join("diff/0.json"), stale.to_string()).unwrap();
+        fs::write(repo_dir.join("index-v2.json"), v2.to_string()).unwrap();
+        fs::write(repo_dir.join("entry.json"), json!({ "diffs": { "1": { "name": "/diff/1.json" } } }).to_string()).unwrap();
+
+        let published = PublishedIndex::read(repo_dir);
+        let diffs = write_diffs(repo_dir, &published, &v3).unwrap().unwrap();
+
+        assert_eq!(diffs.as_object().unwrap().keys().collect::<Vec<_>>(), ["1", "2"]);
+        assert!(!repo_dir.join("diff/0.json").exists());
+        for (old, timestamp) in [(&v1, 1), (&v2, 2)] {
+            let content = fs::read_to_string(repo_dir.join(format!("diff/{}.json", timestamp))).unwrap();
+            assert_eq!(diffs[timestamp.to_string()]["sha256"], checksum::sha256_hex(content.as_bytes()));
+            assert_eq!(apply(old, &serde_json::from_str(&content).unwrap()), v3);
+        }
+        assert_eq!(diffs["2"]["numPackages"], 1);
+    }
+
+    #[test]
+    fn string_list_reads_every_form() {
+        let list = |yaml: &str| string_list(Some(&serde_yaml::from_str(yaml).unwrap()));
+
+        assert_eq!(list("System, Games ,"), ["System", "Games"]);
+        assert_eq!(list("[System, Games]"), ["System", "Games"]);
+        assert_eq!(list("{System: x, Games: y}"), ["System", "Games"]);
+        assert!(list("3").is_empty());
+        assert!(string_list(None).is_empty());
+    }
+}
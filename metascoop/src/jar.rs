@@ -0,0 +1,553 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use cms::builder::{SignedDataBuilder, SignerInfoBuilder};
+use cms::cert::{CertificateChoices, IssuerAndSerialNumber};
+use cms::content_info::ContentInfo;
+use cms::signed_data::{EncapsulatedContentInfo, SignedData, SignerIdentifier};
+use der::asn1::OctetString;
+use der::oid::ObjectIdentifier;
+use der::{Decode, Encode};
+use p12_keystore::{KeyStore, KeyStoreEntry};
+use rsa::pkcs1v15::{Signature, SigningKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+use x509_cert::Certificate;
+
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+const ID_SHA_1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.14.3.2.26");
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+
+const CREATED_BY: &str = concat!("metascoop ", env!("CARGO_PKG_VERSION"));
+
+/// RSA key and certificate of a repo, read from its PKCS#12 keystore
+pub struct RepoKey {
+    alias: String,
+    key: SigningKey<Sha256>,
+    certificate: Certificate,
+    certificate_der: Vec<u8>,
+}
+
+impl RepoKey {
+    pub fn load(keystore_path: &Path, password: &str, alias: &str) -> Result<Self> {
+        let data = fs::read(keystore_path)
+            .with_context(|| format!("Failed to read keystore: {}", keystore_path.display()))?;
+        let keystore = KeyStore::from_pkcs12(&data, password)
+            .map_err(|e| anyhow::anyhow!("Failed to open keystore {}: {}", keystore_path.display(), e))?;
+
+        // keytool lowercases aliases
+        let chain = keystore.entries()
+            .find(|(name, _)| name.eq_ignore_ascii_case(alias))
+            .and_then(|(_, entry)| match entry {
+                KeyStoreEntry::PrivateKeyChain(chain) => Some(chain),
+                KeyStoreEntry::Certificate(_) => None,
+            })
+            .ok_or_else(|| anyhow::anyhow!("Keystore {} has no key {:?}", keystore_path.display(), alias))?;
+
+        let key = rsa::RsaPrivateKey::from_pkcs8_der(chain.key())
+            .context("Failed to read the repo key, only RSA keys are supported")?;
+        let certificate_der = chain.chain().first()
+            .ok_or_else(|| anyhow::anyhow!("Key {:?} has no certificate", alias))?
+            .as_der()
+            .to_vec();
+        let certificate = Certificate::from_der(&certificate_der)
+            .context("Failed to parse the repo certificate")?;
+
+        Ok(Self {
+            alias: alias.to_string(),
+            key: SigningKey::new(key),
+            certificate,
+            certificate_der,
+        })
+    }
+
+    /// DER-encoded certificate, whose SHA-256 is the repo fingerprint
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate_der
+    }
+
+    /// Base name of the signature files, as jarsigner names them
+    fn signature_name(&self) -> String {
+        self.alias.chars()
+            .take(8)
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_uppercase() } else { '_' })
+            .collect()
+    }
+}
+
+fn base64_sha256(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(Sha256::digest(bytes))
+}
+
+/// Writes a JAR holding one file, `name` with `content`, with a v1 JAR
+/// signature as F-Droid clients expect for `entry.jar` and `index-v1.jar`
+pub fn write_signed_jar(path: &Path, name: &str, content: &[u8], key: &RepoKey) -> Result<()> {
+    let section = format!("Name: {}\r\nSHA-256-Digest: {}\r\n\r\n", name, base64_sha256(content));
+    let manifest = format!("Manifest-Version: 1.0\r\nCreated-By: {}\r\n\r\n{}", CREATED_BY, section);
+    let signature_file = format!(
+        "Signature-Version: 1.0\r\nCreated-By: {}\r\nSHA-256-Digest-Manifest: {}\r\n\r\nName: {}\r\nSHA-256-Digest: {}\r\n\r\n",
+        CREATED_BY,
+        base64_sha256(manifest.as_bytes()),
+        name,
+        base64_sha256(section.as_bytes()),
+    );
+    let signature_block = sign(signature_file.as_bytes(), key)?;
+
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default();
+
+    let signature_name = key.signature_name();
+    let entries: [(String, &[u8]); 4] = [
+        ("META-INF/MANIFEST.MF".to_string(), manifest.as_bytes()),
+        (format!("META-INF/{}.SF", signature_name), signature_file.as_bytes()),
+        (format!("META-INF/{}.RSA", signature_name), &signature_block),
+        (name.to_string(), content),
+    ];
+    for (entry_name, bytes) in entries {
+        zip.start_file(entry_name, options)?;
+        zip.write_all(bytes)?;
+    }
+    zip.finish()
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Detached PKCS#7 signature of `data`
+fn sign(data: &[u8], key: &RepoKey) -> Result<Vec<u8>> {
+    let digest = Sha256::digest(data);
+    let content = EncapsulatedContentInfo {
+        econtent_type: ID_DATA,
+        econtent: None,
+    };
+    let digest_algorithm = AlgorithmIdentifierOwned {
+        oid: ID_SHA_256,
+        parameters: None,
+    };
+    let sid = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+        issuer: key.certificate.tbs_certificate.issuer.clone(),
+        serial_number: key.certificate.tbs_certificate.serial_number.clone(),
+    });
+
+    let signer_info = SignerInfoBuilder::new(&key.key, sid, digest_algorithm.clone(), &content, Some(&digest))
+        .map_err(|e| anyhow::anyhow!("Failed to sign: {}", e))?;
+
+    let signed_data = SignedDataBuilder::new(&content)
+        .add_digest_algorithm(digest_algorithm)
+        .and_then(|b| b.add_certificate(CertificateChoices::Certificate(key.certificate.clone())))
+        .and_then(|b| b.add_signer_info::<_, Signature>(signer_info))
+        .and_then(|b| b.build())
+        .map_err(|e| anyhow::anyhow!("Failed to sign: {}", e))?;
+
+    signed_data.to_der().map_err(|e| anyhow::anyhow!("Failed to encode signature: {}", e))
+}
+
+/// Digest algorithms of JAR signatures: jarsigner used SHA-1 before Java 9
+#[derive(Debug, Clone, Copy)]
+enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// From the prefix of a manifest attribute, e.g. "SHA-256" of "SHA-256-Digest"
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SHA1" | "SHA-1" => Some(Self::Sha1),
+            "SHA-256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    fn from_oid(oid: ObjectIdentifier) -> Option<Self> {
+        match oid {
+            ID_SHA_1 => Some(Self::Sha1),
+            ID_SHA_256 => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    fn digest(self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => Sha1::digest(bytes).to_vec(),
+            Self::Sha256 => Sha256::digest(bytes).to_vec(),
+        }
+    }
+
+    fn padding(self) -> Pkcs1v15Sign {
+        match self {
+            Self::Sha1 => Pkcs1v15Sign::new::<Sha1>(),
+            Self::Sha256 => Pkcs1v15Sign::new::<Sha256>(),
+        }
+    }
+}
+
+/// Files of a JAR whose v1 signature verified, with the signer's certificate
+pub struct SignedJar {
+    /// DER-encoded certificate, whose SHA-256 is the repo fingerprint
+    pub certificate: Vec<u8>,
+    /// Signed files, without those of META-INF
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+/// Verifies the v1 JAR signature of `path` as F-Droid clients do: every file
+/// is listed in the manifest with its digest, the signature file covers the
+/// manifest and the PKCS#7 block signs the signature file
+pub fn verify_signed_jar(path: &Path) -> Result<SignedJar> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .with_context(|| format!("{} is not a valid JAR", path.display()))?;
+
+    let mut entries = BTreeMap::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)
+            .with_context(|| format!("Failed to read {} of {}", entry.name(), path.display()))?;
+        entries.insert(entry.name().to_string(), bytes);
+    }
+
+    let manifest = entries.get("META-INF/MANIFEST.MF")
+        .ok_or_else(|| anyhow::anyhow!("JAR is not signed, it has no META-INF/MANIFEST.MF"))?;
+    let block_name = entries.keys()
+        .find(|n| n.starts_with("META-INF/") && n.to_ascii_uppercase().ends_with(".RSA"))
+        .ok_or_else(|| anyhow::anyhow!("JAR has no RSA signature block"))?;
+    let signature_file_name = format!("{}.SF", &block_name[..block_name.len() - 4]);
+    let signature_file = entries.get(&signature_file_name)
+        .ok_or_else(|| anyhow::anyhow!("JAR has no {}", signature_file_name))?;
+
+    let certificate = verify_signature_block(&entries[block_name], signature_file)
+        .with_context(|| format!("Signature {} doesn't verify", block_name))?;
+    let covered = verify_signature_file(signature_file, manifest)
+        .with_context(|| format!("{} doesn't match the manifest", signature_file_name))?;
+
+    let digests = manifest_digests(manifest)?;
+    let files: BTreeMap<String, Vec<u8>> = entries.into_iter()
+        .filter(|(name, _)| !name.starts_with("META-INF/"))
+        .collect();
+    for (name, content) in &files {
+        let (algorithm, digest) = digests.get(name)
+            .ok_or_else(|| anyhow::anyhow!("{} is not listed in the manifest", name))?;
+        if let Coverage::Sections(names) = &covered {
+            if !names.contains(name) {
+                anyhow::bail!("{} is not covered by the signature", name);
+            }
+        }
+        if algorithm.digest(content) != *digest {
+            anyhow::bail!("Digest of {} doesn't match the manifest", name);
+        }
+    }
+
+    Ok(SignedJar { certificate, files })
+}
+
+/// Checks the PKCS#7 signature of the signature file, returning the
+/// certificate that made it
+fn verify_signature_block(block: &[u8], signature_file: &[u8]) -> Result<Vec<u8>> {
+    let content_info = ContentInfo::from_der(block)
+        .map_err(|e| anyhow::anyhow!("Failed to parse PKCS#7: {}", e))?;
+    let signed_data: SignedData = content_info.content.decode_as()
+        .map_err(|e| anyhow::anyhow!("Failed to parse PKCS#7: {}", e))?;
+    let signer = signed_data.signer_infos.0.iter().next()
+        .ok_or_else(|| anyhow::anyhow!("PKCS#7 has no signer"))?;
+
+    let certificate = signed_data.certificates.iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            CertificateChoices::Certificate(certificate) => Some(certificate),
+            _ => None,
+        })
+        .find(|certificate| match &signer.sid {
+            SignerIdentifier::IssuerAndSerialNumber(id) => {
+                certificate.tbs_certificate.issuer == id.issuer
+                    && certificate.tbs_certificate.serial_number == id.serial_number
+            }
+            SignerIdentifier::SubjectKeyIdentifier(_) => true,
+        })
+        .ok_or_else(|| anyhow::anyhow!("PKCS#7 has no certificate of its signer"))?;
+
+    let algorithm = DigestAlgorithm::from_oid(signer.digest_alg.oid)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported digest algorithm {}", signer.digest_alg.oid))?;
+    let digest = algorithm.digest(signature_file);
+
+    // With signed attributes, the signature covers them and they hold the
+    // digest of the content
+    let signed = match &signer.signed_attrs {
+        Some(attributes) => {
+            let message_digest = attributes.iter()
+                .find(|a| a.oid == ID_MESSAGE_DIGEST)
+                .and_then(|a| a.values.iter().next())
+                .and_then(|v| v.decode_as::<OctetString>().ok())
+                .ok_or_else(|| anyhow::anyhow!("PKCS#7 has no message digest"))?;
+            if message_digest.as_bytes() != digest.as_slice() {
+                anyhow::bail!("Message digest doesn't match the signature file");
+            }
+            let encoded = attributes.to_der()
+                .map_err(|e| anyhow::anyhow!("Failed to encode signed attributes: {}", e))?;
+            algorithm.digest(&encoded)
+        }
+        None => digest,
+    };
+
+    let public_key_der = certificate.tbs_certificate.subject_public_key_info.to_der()
+        .map_err(|e| anyhow::anyhow!("Failed to encode public key: {}", e))?;
+    let public_key = RsaPublicKey::from_public_key_der(&public_key_der)
+        .context("Failed to read the signer's key, only RSA keys are supported")?;
+    public_key.verify(algorithm.padding(), &signed, signer.signature.as_bytes())
+        .context("Signature is invalid")?;
+
+    certificate.to_der().map_err(|e| anyhow::anyhow!("Failed to encode certificate: {}", e))
+}
+
+/// Parts of the manifest a signature file vouches for
+#[derive(Debug, PartialEq)]
+enum Coverage {
+    /// The digest of the whole manifest matches
+    Whole,
+    /// Only the sections of these files match, the manifest was changed
+    /// since it was signed
+    Sections(BTreeSet<String>),
+}
+
+/// Checks the digest of the whole manifest in the signature file or, when
+/// the manifest changed since, those of its sections. Files whose section
+/// isn't covered by the signature file aren't signed.
+fn verify_signature_file(signature_file: &[u8], manifest: &[u8]) -> Result<Coverage> {
+    let signature_sections = sections(signature_file);
+    let main = signature_sections.first().map(|s| attributes(s)).unwrap_or_default();
+
+    let whole = main.iter().find_map(|(key, value)| {
+        let algorithm = DigestAlgorithm::from_name(key.strip_suffix("-Digest-Manifest")?)?;
+        Some((algorithm, value))
+    });
+    if let Some((algorithm, value)) = whole {
+        if base64_decode(value)? == algorithm.digest(manifest) {
+            return Ok(Coverage::Whole);
+        }
+    }
+
+    let manifest_sections: BTreeMap<String, &[u8]> = sections(manifest).into_iter()
+        .filter_map(|section| Some((section_name(section)?, section)))
+        .collect();
+    let mut covered = BTreeSet::new();
+    for section in signature_sections.iter().skip(1) {
+        let Some(name) = section_name(section) else { continue };
+        let (algorithm, digest) = section_digest(section)?
+            .ok_or_else(|| anyhow::anyhow!("Section {} has no digest", name))?;
+        let manifest_section = manifest_sections.get(&name)
+            .ok_or_else(|| anyhow::anyhow!("{} is not in the manifest", name))?;
+        if algorithm.digest(manifest_section) != digest {
+            anyhow::bail!("Digest of the manifest section of {} doesn't match", name);
+        }
+        covered.insert(name);
+    }
+
+    Ok(Coverage::Sections(covered))
+}
+
+/// Digest of every file listed in the manifest
+fn manifest_digests(manifest: &[u8]) -> Result<BTreeMap<String, (DigestAlgorithm, Vec<u8>)>> {
+    let mut digests = BTreeMap::new();
+    for section in sections(manifest).into_iter().skip(1) {
+        if let (Some(name), Some(digest)) = (section_name(section), section_digest(section)?) {
+            digests.insert(name, digest);
+        }
+    }
+    Ok(digests)
+}
+
+/// Splits a manifest into its sections, each with the blank line ending it,
+/// as their digests are computed
+fn sections(manifest: &[u8]) -> Vec<&[u8]> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    let mut at = 0;
+    while at < manifest.len() {
+        let line_end = manifest[at..].iter().position(|&b| b == b'\n').map_or(manifest.len(), |i| at + i + 1);
+        let line = &manifest[at..line_end];
+        if line == b"\r\n" || line == b"\n" {
+            if at > start {
+                sections.push(&manifest[start..line_end]);
+            }
+            start = line_end;
+        }
+        at = line_end;
+    }
+    if start < manifest.len() {
+        sections.push(&manifest[start..]);
+    }
+    sections
+}
+
+/// Attributes of a section, joining lines continued with a leading space
+fn attributes(section: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(section);
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix(' '), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ if !line.is_empty() => lines.push(line.to_string()),
+            _ => {}
+        }
+    }
+
+    lines.into_iter()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(": ")?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn section_name(section: &[u8]) -> Option<String> {
+    attributes(section).into_iter()
+        .find(|(key, _)| key == "Name")
+        .map(|(_, value)| value)
+}
+
+fn section_digest(section: &[u8]) -> Result<Option<(DigestAlgorithm, Vec<u8>)>> {
+    for (key, value) in attributes(section) {
+        let algorithm = key.strip_suffix("-Digest").and_then(DigestAlgorithm::from_name);
+        if let Some(algorithm) = algorithm {
+            return Ok(Some((algorithm, base64_decode(&value)?)));
+        }
+    }
+    Ok(None)
+}
+
+fn base64_decode(value: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD.decode(value.trim())
+        .with_context(|| format!("Invalid digest {:?}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> RepoKey {
+        let keystore = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/keystore.p12");
+        RepoKey::load(&keystore, "testpass", "repokey").unwrap()
+    }
+
+    fn read_entries(path: &Path) -> Vec<(String, Vec<u8>)> {
+        let mut zip = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut entry = zip.by_index(i).unwrap();
+                let mut bytes = Vec::new();
+                entry.read_to_end(&mut bytes).unwrap();
+                (entry.name().to_string(), bytes)
+            })
+            .collect()
+    }
+
+    fn write_entries(path: &Path, entries: &[(String, Vec<u8>)]) {
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, bytes) in entries {
+            zip.start_file(name.as_str(), zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// Signs entry.json and rewrites the JAR with `tamper`
+    fn tampered_jar(dir: &Path, tamper: impl FnOnce(&mut Vec<(String, Vec<u8>)>)) -> std::path::PathBuf {
+        let path = dir.join("entry.jar");
+        write_signed_jar(&path, "entry.json", b"{\"timestamp\": 1}", &test_key()).unwrap();
+        let mut entries = read_entries(&path);
+        tamper(&mut entries);
+        write_entries(&path, &entries);
+        path
+    }
+
+    fn append_to_manifest(entries: &mut [(String, Vec<u8>)], section: &str) {
+        let manifest = entries.iter_mut().find(|(name, _)| name == "META-INF/MANIFEST.MF").unwrap();
+        manifest.1.extend_from_slice(section.as_bytes());
+    }
+
+    #[test]
+    fn signed_jar_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = test_key();
+        let path = dir.path().join("index-v1.jar");
+        write_signed_jar(&path, "index-v1.json", b"{}", &key).unwrap();
+
+        let signed = verify_signed_jar(&path).unwrap();
+        assert_eq!(signed.certificate, key.certificate());
+        assert_eq!(signed.files.keys().collect::<Vec<_>>(), ["index-v1.json"]);
+        assert_eq!(signed.files["index-v1.json"], b"{}");
+    }
+
+    #[test]
+    fn file_added_with_its_own_manifest_section_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = b"{\"packages\": {}}".to_vec();
+        let path = tampered_jar(dir.path(), |entries| {
+            let section = format!("Name: index-v1.json\r\nSHA-256-Digest: {}\r\n\r\n", base64_sha256(&content));
+            append_to_manifest(entries, &section);
+            entries.push(("index-v1.json".to_string(), content));
+        });
+
+        let error = verify_signed_jar(&path).err().unwrap();
+        assert!(format!("{:#}", error).contains("index-v1.json is not covered by the signature"), "{:#}", error);
+    }
+
+    #[test]
+    fn unrelated_manifest_section_keeps_signed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tampered_jar(dir.path(), |entries| {
+            append_to_manifest(entries, "Name: other.json\r\nSHA-256-Digest: AAAA\r\n\r\n");
+        });
+
+        let signed = verify_signed_jar(&path).unwrap();
+        assert_eq!(signed.files.keys().collect::<Vec<_>>(), ["entry.json"]);
+    }
+
+    #[test]
+    fn changed_file_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tampered_jar(dir.path(), |entries| {
+            let entry = entries.iter_mut().find(|(name, _)| name == "entry.json").unwrap();
+            entry.1 = b"{\"timestamp\": 2}".to_vec();
+        });
+
+        let error = verify_signed_jar(&path).err().unwrap();
+        assert!(format!("{:#}", error).contains("Digest of entry.json doesn't match"), "{:#}", error);
+    }
+
+    #[test]
+    fn file_missing_from_manifest_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tampered_jar(dir.path(), |entries| {
+            entries.push(("index-v1.json".to_string(), b"{}".to_vec()));
+        });
+
+        let error = verify_signed_jar(&path).err().unwrap();
+        assert!(format!("{:#}", error).contains("index-v1.json is not listed in the manifest"), "{:#}", error);
+    }
+
+    #[test]
+    fn changed_signature_file_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = tampered_jar(dir.path(), |entries| {
+            let signature_file = entries.iter_mut().find(|(name, _)| name.ends_with(".SF")).unwrap();
+            signature_file.1.extend_from_slice(b"Name: index-v1.json\r\nSHA-256-Digest: AAAA\r\n\r\n");
+        });
+
+        let error = verify_signed_jar(&path).err().unwrap();
+        assert!(format!("{:#}", error).contains("doesn't verify"), "{:#}", error);
+    }
+}
@@ -0,0 +1,55 @@
+//! Keeps an F-Droid repository up to date with the releases of the apps
+//! listed in apps.yaml.
+//!
+//! [`Pipeline`] runs a whole sync, with [`Hook`]s to observe or adjust its
+//! steps, and reports it in a [`SyncReport`]. Its output goes to the current
+//! [`output::Output::scope`]. The modules below can also be used on their
+//! own: [`apps`] parses apps.yaml and the F-Droid index, [`metadata`] fills in
+//! metadata files and [`md`] regenerates the README table.
+
+pub mod apps;
+pub mod backend;
+pub mod credentials;
+pub mod github;
+pub mod md;
+pub mod metadata;
+pub mod output;
+pub mod pipeline;
+pub mod plan;
+pub mod release_notes;
+pub mod summary;
+pub mod sync;
+
+// Commands of the metascoop binary, not part of the library API
+#[doc(hidden)]
+pub mod add;
+#[doc(hidden)]
+pub mod notify;
+#[doc(hidden)]
+pub mod prune;
+#[doc(hidden)]
+pub mod status;
+#[doc(hidden)]
+pub mod validate;
+#[doc(hidden)]
+pub mod verify;
+
+mod apk;
+mod artifacts;
+mod atom;
+mod checksum;
+mod download;
+mod feed;
+mod file;
+mod git;
+mod index;
+mod jar;
+mod published;
+mod ratelimit;
+mod site;
+mod templates;
+#[cfg(test)]
+mod testserver;
+
+pub use apps::{parse_app_file, AppInfo, RepoIndex};
+pub use pipeline::{Hook, Pipeline, SyncOptions, SyncReport};
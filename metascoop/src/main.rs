@@ -1,516 +1,438 @@
-mod apps;
-mod file;
-mod git;
-mod md;
-
 use anyhow::{Context, Result};
-use clap::Parser;
-use log::{error, info};
+use clap::{Parser, Subcommand};
+use log::{info, warn, Level};
 use octocrab::Octocrab;
-use std::collections::HashMap;
+use secrecy::{ExposeSecret, SecretString};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
+
+use metascoop::github::{self, GITHUB_HOST};
+use metascoop::backend::IndexBackend;
+use metascoop::metadata::metadata_dir;
+use metascoop::apps::AppsFileError;
+use metascoop::output::{self, Output, ReporterKind};
+use metascoop::{add, apps, credentials, md, notify, prune, release_notes, status, summary, validate, verify};
+use metascoop::{Pipeline, SyncOptions};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to apps.yaml file
-    #[arg(short = 'a', long, default_value = "apps.yaml")]
+    /// Path to apps.yaml file, the apps.d directory next to it is read too
+    #[arg(global = true, short = 'a', long, default_value = "apps.yaml")]
     apps_path: PathBuf,
 
     /// Path to fdroid "repo" directory
-    #[arg(short = 'r', long, default_value = "fdroid/repo")]
+    #[arg(global = true, short = 'r', long, default_value = "fdroid/repo")]
     repo_dir: PathBuf,
 
     /// GitHub personal access token
-    #[arg(short = 'p', long)]
+    #[arg(global = true, short = 'p', long)]
     personal_access_token: Option<String>,
 
-    /// Debug mode won't run the fdroid command
-    #[arg(short = 'd', long)]
-    debug: bool,
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
-    let mut args = Args::parse();
-
-    // If no token provided via CLI, check environment variable
-    if args.personal_access_token.is_none() {
-        args.personal_access_token = std::env::var("GITHUB_TOKEN").ok();
-    }
-
-    println!("::group::Initializing");
-
-    let apps_list = apps::parse_app_file(&args.apps_path)
-        .context("Failed to parse app file")?;
-
-    let github = if let Some(token) = &args.personal_access_token {
-        Octocrab::builder()
-            .personal_token(token.clone())
-            .build()?
-    } else {
-        Octocrab::builder().build()?
-    };
-
-    let mut have_error = false;
-
-    let fdroid_index_path = args.repo_dir.join("index-v1.json");
+    /// Token for another host, as HOST=TOKEN (e.g. codeberg.org=...); can be repeated
+    #[arg(global = true, long = "token", value_name = "HOST=TOKEN", value_parser = parse_host_value)]
+    tokens: Vec<(String, String)>,
 
-    let initial_fdroid_index = apps::RepoIndex::read_index(&fdroid_index_path)
-        .context("Failed to read F-Droid repo index")?;
+    /// YAML file mapping hosts to tokens
+    #[arg(global = true, long)]
+    credentials_file: Option<PathBuf>,
 
-    fs::create_dir_all(&args.repo_dir)
-        .context("Failed to create repo directory")?;
+    /// API URL of a GitHub Enterprise host, as HOST=URL; defaults to https://HOST/api/v3
+    #[arg(global = true, long = "github-api-url", value_name = "HOST=URL", value_parser = parse_host_value)]
+    github_api_urls: Vec<(String, String)>,
 
-    println!("::endgroup::");
+    /// Debug mode won't run the fdroid command
+    #[arg(global = true, short = 'd', long)]
+    debug: bool,
 
-    // Map of apk_name -> AppInfo
-    let mut apk_info_map: HashMap<String, apps::AppInfo> = HashMap::new();
+    /// What generates the repo index: fdroid update, or the built-in generator
+    #[arg(global = true, long, value_enum, default_value_t = IndexBackend::Fdroid)]
+    index_backend: IndexBackend,
 
-    for mut app in apps_list {
-        println!("App: {}/{}", app.author_name(), app.app_name());
+    /// Report what a sync or prune would download, remove and change without touching any files
+    #[arg(global = true, long)]
+    dry_run: bool,
 
-        let repo = apps::repo_info(&app.git)
-            .with_context(|| format!("Failed to get repo info from URL: {}", app.git))?;
+    /// Number of apps to process concurrently
+    #[arg(global = true, short = 'j', long, default_value_t = 4)]
+    jobs: usize,
 
-        info!("Looking up {}/{} on GitHub", repo.author, repo.name);
+    /// Write a JSON summary of what the sync did for every app to this file (sync only)
+    #[arg(global = true, long, value_name = "PATH")]
+    summary_json: Option<PathBuf>,
 
-        match github.repos(&repo.author, &repo.name).get().await {
-            Ok(gh_repo) => {
-                if let Some(desc) = gh_repo.description {
-                    app.summary = desc;
-                }
+    /// Write a commit message describing the versions added and removed by the sync to this file
+    #[arg(global = true, long, value_name = "PATH")]
+    commit_message: Option<PathBuf>,
 
-                if let Some(license) = gh_repo.license {
-                    app.license = license.spdx_id;
-                }
+    /// Add a section describing the versions added and removed by the sync to this changelog
+    #[arg(global = true, long, value_name = "PATH")]
+    changelog: Option<PathBuf>,
 
-                info!("Data from GitHub: summary={:?}, license={:?}", app.summary, app.license);
-            }
-            Err(e) => {
-                error!("Error while looking up repo: {}", e);
-            }
-        }
+    /// Directory of the templates listed in its documents.yaml, rendered after a sync
+    #[arg(global = true, long, default_value = "templates")]
+    templates_dir: PathBuf,
 
-        let releases = match github
-            .repos(&repo.author, &repo.name)
-            .releases()
-            .list()
-            .per_page(100)
-            .send()
-            .await
-        {
-            Ok(page) => {
-                let all_releases = page.items;
-                // For simplicity, we're only getting the first page
-                // In production, you'd want to paginate through all results
-                all_releases
-            }
-            Err(e) => {
-                error!("Error while listing repo releases for {:?}: {}", app.git, e);
-                have_error = true;
-                continue;
-            }
-        };
-
-        info!("Received {} releases", releases.len());
+    /// Generate a static HTML site of the repo in this directory after a sync
+    #[arg(global = true, long, value_name = "PATH")]
+    site_dir: Option<PathBuf>,
 
-        for release in releases {
-            println!("::group::Release {}", release.tag_name);
+    /// YAML file of the webhooks, chat rooms and commands notified of new, removed and failed versions
+    #[arg(global = true, long, default_value = "notifications.yaml")]
+    notifications: PathBuf,
 
-            if release.prerelease {
-                info!("Skipping prerelease {:?}", release.tag_name);
-                println!("::endgroup::");
-                continue;
-            }
+    /// Output format; auto uses github in GitHub Actions and plain otherwise
+    #[arg(global = true, long, value_enum, default_value_t = ReporterKind::Auto)]
+    reporter: ReporterKind,
 
-            if release.draft {
-                info!("Skipping draft {:?}", release.tag_name);
-                println!("::endgroup::");
-                continue;
-            }
+    /// GitHub App id, to authenticate as an app installation instead of with a token
+    #[arg(global = true, long)]
+    github_app_id: Option<u64>,
 
-            if release.tag_name.is_empty() {
-                info!("Skipping release with empty tag name");
-                println!("::endgroup::");
-                continue;
-            }
+    /// Path to the GitHub App's private key (PEM)
+    #[arg(global = true, long)]
+    github_app_key: Option<PathBuf>,
 
-            if app.skip_versions.contains(&release.tag_name) {
-                info!("Skipping version {:?} (configured in skip_versions)", release.tag_name);
-                println!("::endgroup::");
-                continue;
-            }
+    /// GitHub App installation id; the app's first installation is used when omitted
+    #[arg(global = true, long)]
+    github_app_installation_id: Option<u64>,
 
-            info!("Working on release with tag name {:?}", release.tag_name);
+    /// Longest time in seconds to wait for the GitHub rate limit to reset
+    #[arg(global = true, long, default_value_t = 900)]
+    max_rate_limit_wait: u64,
 
-            let apk = match apps::find_apk_release(&release) {
-                Some(asset) => asset,
-                None => {
-                    info!("Couldn't find a release asset with extension \".apk\"");
-                    println!("::endgroup::");
-                    continue;
-                }
-            };
-
-            let app_name = apps::generate_release_filename(app.app_name(), &release.tag_name);
-            info!("Target APK name: {}", app_name);
-
-            let mut app_clone = app.clone();
-            app_clone.release_description = release.body.clone().unwrap_or_default();
-
-            if !app_clone.release_description.is_empty() {
-                info!("Release notes: {}", app_clone.release_description);
-            }
-
-            apk_info_map.insert(app_name.clone(), app_clone);
-
-            let app_target_path = args.repo_dir.join(&app_name);
-
-            if app_target_path.exists() {
-                info!("Already have APK for version {:?} at {:?}", release.tag_name, app_target_path);
-                println!("::endgroup::");
-                continue;
-            }
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
 
-            info!("Downloading APK {:?} from release {:?} to {:?}", apk.name, release.tag_name, app_target_path);
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Download new releases, update the F-Droid repo and fill in metadata (the default)
+    Sync,
+
+    /// Add an app to apps.yaml, using the description, license and topics of its repository
+    Add {
+        /// URL of the app's repository
+        repo_url: String,
+
+        /// Key of the app in apps.yaml; defaults to the repository name
+        #[arg(long)]
+        id: Option<String>,
+    },
+
+    /// Check apps.yaml and the metadata files
+    Validate {
+        /// Skip the checks that need the upstream releases
+        #[arg(long)]
+        offline: bool,
+    },
+
+    /// Print the JSON Schema of apps.yaml
+    Schema,
+
+    /// Show the published and the latest upstream version of every app
+    Status,
+
+    /// Remove old APKs, keeping the newest versions of every package
+    Prune {
+        /// Number of versions to keep per package, unless its app sets a retention
+        #[arg(long, default_value_t = 3)]
+        keep: usize,
+    },
+
+    /// Check that the APKs, screenshots and icons match the index and that its signatures verify
+    Verify,
+
+    /// Notify the targets of the notifications file of what the sync of a summary did, or send them an example
+    Notify {
+        /// Summary written by a sync with --summary-json, to send once the repo is published
+        #[arg(long, value_name = "PATH")]
+        summary: Option<PathBuf>,
+
+        /// Only notify of apps that failed, for syncs whose repo wasn't published
+        #[arg(long, requires = "summary")]
+        only_failures: bool,
+    },
+}
 
-            match download_asset(&github, &repo.author, &repo.name, apk.id.0, &app_target_path).await {
-                Ok(_) => {
-                    info!("Successfully downloaded app for version {:?}", release.tag_name);
-                }
-                Err(e) => {
-                    error!("Error while downloading app: {}", e);
-                    have_error = true;
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    // Everything the commands print goes to stdout, logs to stderr unless
+    // the reporter writes them along
+    let stdout = Output::new(args.reporter.reporter(), std::io::stdout());
+
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| writeln!(buf, "{}", output::format_record(record)))
+        .target(env_logger::Target::Pipe(Box::new(output::LogWriter)))
+        .init();
+
+    stdout.scope(async {
+        if let Err(e) = run(args).await {
+            // Problems in apps.yaml point at the file and line they are at
+            match e.chain().find_map(|cause| cause.downcast_ref::<AppsFileError>()) {
+                Some(cause) => {
+                    // Keep what was being done, e.g. which include failed
+                    let message: Vec<String> = e.chain()
+                        .take_while(|c| !c.is::<AppsFileError>())
+                        .map(|c| c.to_string())
+                        .chain([cause.message.clone()])
+                        .collect();
+                    output::annotate(Level::Error, Some(&cause.location), message.join(": "))
                 }
+                None => output::annotate(Level::Error, None, format!("{:#}", e)),
             }
-
-            println!("::endgroup::");
-        }
-    }
-
-    if !args.debug {
-        println!("::group::F-Droid: Creating metadata stubs");
-
-        let fdroid_dir = args.repo_dir.parent()
-            .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))?;
-
-        let status = Command::new("fdroid")
-            .args(["update", "--pretty", "--create-metadata", "--delete-unknown"])
-            .current_dir(fdroid_dir)
-            .status()
-            .context("Failed to run fdroid update")?;
-
-        if !status.success() {
-            error!("Error while running \"fdroid update -c\"");
-            println!("::endgroup::");
             std::process::exit(1);
         }
+    }).await;
+}
 
-        println!("::endgroup::");
+async fn run(mut args: Args) -> Result<()> {
+    if args.github_app_id.is_none() {
+        args.github_app_id = std::env::var("GITHUB_APP_ID").ok().and_then(|v| v.parse().ok());
+    }
+    if args.github_app_key.is_none() {
+        args.github_app_key = std::env::var("GITHUB_APP_PRIVATE_KEY_PATH").ok().map(PathBuf::from);
+    }
+    if args.github_app_installation_id.is_none() {
+        args.github_app_installation_id = std::env::var("GITHUB_APP_INSTALLATION_ID").ok().and_then(|v| v.parse().ok());
     }
 
-    println!("Filling in metadata");
-
-    let fdroid_index = apps::RepoIndex::read_index(&fdroid_index_path)
-        .context("Failed to read F-Droid repo index after update")?;
-
-    let mut to_remove_paths: Vec<PathBuf> = Vec::new();
-
-    let metadata_dir = args.repo_dir.parent()
-        .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))?
-        .join("metadata");
-
-    for entry in walkdir::WalkDir::new(&metadata_dir).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        
-        if !path.is_file() || !path.extension().map_or(false, |e| e == "yml") {
-            continue;
+    let command = args.command.take().unwrap_or(Commands::Sync);
+    match command {
+        Commands::Sync => run_sync(&args).await,
+        _ if args.summary_json.is_some() => anyhow::bail!("--summary-json only applies to sync"),
+        _ if args.dry_run && !matches!(command, Commands::Prune { .. }) => {
+            anyhow::bail!("--dry-run only applies to sync and prune")
         }
-
-        let pkg_name = path.file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_string();
-
-        println!("::group::{}", pkg_name);
-
-        info!("Working on {:?}", pkg_name);
-
-        let mut meta = match apps::read_meta_file(path) {
-            Ok(m) => m,
-            Err(e) => {
-                error!("Reading meta file {:?}: {}", path, e);
-                println!("::endgroup::");
-                continue;
-            }
-        };
-
-        let latest_package = match fdroid_index.find_latest_package(&pkg_name) {
-            Some(p) => p,
-            None => {
-                println!("::endgroup::");
-                continue;
-            }
-        };
-
-        info!("The latest version is {:?} with versionCode {}", latest_package.version_name, latest_package.version_code);
-
-        let apk_info = match apk_info_map.get(&latest_package.apk_name) {
-            Some(info) => info,
-            None => {
-                info!("Cannot find apk info for {:?}", latest_package.apk_name);
-                println!("::endgroup::");
-                continue;
+        Commands::Add { repo_url, id } => {
+            let (apis, _) = github_apis(&args).await?;
+            add::run(&args.apps_path, &apis, &repo_url, id.as_deref()).await
+        }
+        Commands::Validate { offline } => {
+            let metadata_dir = metadata_dir(&args.repo_dir)?;
+            if offline {
+                validate::run(&args.apps_path, &metadata_dir, None).await
+            } else {
+                let (apis, credentials) = github_apis(&args).await?;
+                validate::run(&args.apps_path, &metadata_dir, Some((&apis, &credentials))).await
             }
-        };
-
-        // Update metadata
-        set_non_empty(&mut meta, "AuthorName", apk_info.author_name());
-        
-        let friendly_name = if !apk_info.name.is_empty() {
-            &apk_info.name
-        } else {
-            apk_info.app_name()
-        };
-        set_non_empty(&mut meta, "Name", friendly_name);
-        set_non_empty(&mut meta, "SourceCode", &apk_info.git);
-        set_non_empty(&mut meta, "License", &apk_info.license);
-        set_non_empty(&mut meta, "Description", &apk_info.description);
-
-        let mut summary = apk_info.summary.clone();
-        const MAX_SUMMARY_LENGTH: usize = 80;
-        if summary.len() > MAX_SUMMARY_LENGTH {
-            summary.truncate(MAX_SUMMARY_LENGTH - 3);
-            summary.push_str("...");
-            info!("Truncated summary to length of {} (max length)", summary.len());
         }
-        set_non_empty(&mut meta, "Summary", &summary);
-
-        if !apk_info.categories.is_empty() {
-            meta.insert("Categories".to_string(), serde_yaml::Value::Sequence(
-                apk_info.categories.iter().map(|s| serde_yaml::Value::String(s.clone())).collect()
-            ));
+        Commands::Schema => {
+            println!("{}", serde_json::to_string_pretty(&apps::apps_schema())?);
+            Ok(())
         }
-
-        if !apk_info.anti_features.is_empty() {
-            let anti_features = apk_info.anti_features.join(",");
-            meta.insert("AntiFeatures".to_string(), serde_yaml::Value::String(anti_features));
+        Commands::Status => {
+            let (apis, credentials) = github_apis(&args).await?;
+            status::run(&args.apps_path, &args.repo_dir, &apis, &credentials, args.jobs).await
         }
-
-        meta.insert("CurrentVersion".to_string(), serde_yaml::Value::String(latest_package.version_name.clone()));
-        meta.insert("CurrentVersionCode".to_string(), serde_yaml::Value::Number(latest_package.version_code.into()));
-
-        info!("Set current version info to versionName={:?}, versionCode={}", latest_package.version_name, latest_package.version_code);
-
-        if let Err(e) = apps::write_meta_file(path, &meta) {
-            error!("Writing meta file {:?}: {}", path, e);
-            println!("::endgroup::");
-            continue;
+        Commands::Prune { keep } => {
+            let apps = if args.apps_path.exists() { apps::parse_app_file(&args.apps_path)? } else { Vec::new() };
+            prune::run(&args.repo_dir, &apps, keep, args.dry_run)
         }
+        Commands::Verify => verify::run(&args.repo_dir, &md::readme_path(&args.repo_dir)?),
+        Commands::Notify { summary: Some(summary), only_failures } => {
+            notify::run(&args.notifications, &summary, &args.repo_dir, only_failures).await
+        }
+        Commands::Notify { summary: None, .. } => notify::send_test(&notify::load(&args.notifications)?).await,
+    }
+}
 
-        info!("Updated metadata file {:?}", path);
+/// Sets up the GitHub API clients with the configured credentials
+async fn github_apis(args: &Args) -> Result<(github::GitHubApis, credentials::Credentials)> {
+    let mut credentials = load_credentials(args)?;
 
-        // Write changelog
-        if !apk_info.release_description.is_empty() {
-            let changelog_path = metadata_dir
-                .join(&latest_package.package_name)
-                .join("en-US")
-                .join("changelogs")
-                .join(format!("{}.txt", latest_package.version_code));
+    let installation = if let (Some(app_id), Some(key_path)) = (args.github_app_id, &args.github_app_key) {
+        let installation = github_app_client(app_id, key_path, args.github_app_installation_id).await?;
+        // Asset downloads and clones use the installation token too
+        credentials.insert(GITHUB_HOST, installation.token.expose_secret());
+        Some(installation)
+    } else {
+        None
+    };
 
-            if let Some(parent) = changelog_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
+    for host in credentials.hosts() {
+        info!("Using a token for {}", host);
+    }
 
-            fs::write(&changelog_path, &apk_info.release_description)?;
-            info!("Wrote release notes to {:?}", changelog_path);
-        }
+    let apis = github::GitHubApis::new(
+        credentials.clone(),
+        args.github_api_urls.iter().cloned().collect(),
+        Duration::from_secs(args.max_rate_limit_wait),
+    );
 
-        // Clone repo and find screenshots
-        info!("Cloning git repository to search for screenshots");
-
-        match git::clone_repo(&apk_info.git) {
-            Ok(git_repo_path) => {
-                match apps::find_metadata(&git_repo_path) {
-                    Ok(repo_metadata) => {
-                        info!("Found {} screenshots", repo_metadata.screenshots.len());
-
-                        let screenshots_path = metadata_dir
-                            .join(&latest_package.package_name)
-                            .join("en-US")
-                            .join("phoneScreenshots");
-
-                        let _ = fs::remove_dir_all(&screenshots_path);
-
-                        let mut counter = 1;
-                        for screenshot in &repo_metadata.screenshots {
-                            if let Some(ext) = screenshot.extension() {
-                                let new_file_path = screenshots_path.join(format!("{}.{}", counter, ext.to_string_lossy()));
-
-                                if let Some(parent) = new_file_path.parent() {
-                                    fs::create_dir_all(parent)?;
-                                }
-
-                                if let Err(e) = file::move_file(screenshot, &new_file_path) {
-                                    error!("Moving screenshot file {:?} to {:?}: {}", screenshot, new_file_path, e);
-                                } else {
-                                    info!("Wrote screenshot to {:?}", new_file_path);
-                                    counter += 1;
-                                }
-                            }
-                        }
-
-                        to_remove_paths.push(screenshots_path);
-                    }
-                    Err(e) => {
-                        error!("Finding metadata in git repo {:?}: {}", git_repo_path, e);
-                    }
-                }
+    if let Some(installation) = installation {
+        let api = github::GitHubApi::with_client(
+            installation.client.clone(),
+            &apis.api_url(GITHUB_HOST)?,
+            credentials.token_for_host(GITHUB_HOST).as_deref(),
+        );
+        apis.insert(api.clone());
+        refresh_installation_token(installation, api, credentials.clone());
+    }
 
-                let _ = fs::remove_dir_all(&git_repo_path);
-            }
+    Ok((apis, credentials))
+}
+
+/// Downloads new releases, runs fdroid and fills in the metadata
+async fn run_sync(args: &Args) -> Result<()> {
+    let (apis, credentials) = github_apis(args).await?;
+
+    let mut options = SyncOptions::new(&args.apps_path, &args.repo_dir);
+    options.skip_fdroid = args.debug;
+    options.index_backend = args.index_backend;
+    options.dry_run = args.dry_run;
+    options.jobs = args.jobs;
+    options.templates_dir = args.templates_dir.clone();
+    options.site_dir = args.site_dir.clone();
+
+    let mut pipeline = Pipeline::new(options, &apis, &credentials);
+    let result = pipeline.run().await;
+
+    if let Some(path) = &args.summary_json {
+        // A failed sync still leaves a summary, with the error and what
+        // happened to the apps before it
+        let failed;
+        let report = match &result {
+            Ok(report) => report,
             Err(e) => {
-                error!("Cloning git repo from {:?}: {}", apk_info.git, e);
+                failed = pipeline.failed_report(e);
+                &failed
             }
-        }
-
-        println!("::endgroup::");
+        };
+        summary::write(path, report)?;
     }
 
-    if !args.debug {
-        println!("::group::F-Droid: Reading updated metadata");
-
-        let fdroid_dir = args.repo_dir.parent()
-            .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))?;
-
-        let status = Command::new("fdroid")
-            .args(["update", "--pretty", "--delete-unknown"])
-            .current_dir(fdroid_dir)
-            .status()
-            .context("Failed to run fdroid update")?;
+    let report = result?;
 
-        if !status.success() {
-            error!("Error while running \"fdroid update\"");
-            println!("::endgroup::");
-            std::process::exit(1);
+    // A dry run doesn't update the index, so there is nothing to describe
+    if !report.dry_run {
+        if let Some(path) = &args.commit_message {
+            fs::write(path, release_notes::commit_message(&report.index_changes))
+                .with_context(|| format!("Failed to write commit message: {}", path.display()))?;
+        }
+        if let Some(path) = &args.changelog {
+            let section = release_notes::changelog_section(&report.index_changes, &release_notes::today());
+            release_notes::prepend_changelog(path, &section)?;
         }
-
-        println!("::endgroup::");
     }
 
-    println!("::group::Assessing changes");
-
-    let fdroid_index = apps::RepoIndex::read_index(&fdroid_index_path)
-        .context("Failed to read F-Droid repo index after final update")?;
-
-    // Remove marked paths
-    for rm_path in to_remove_paths {
-        let _ = fs::remove_dir_all(&rm_path);
+    match report.exit_code() {
+        0 => Ok(()),
+        code => std::process::exit(code),
     }
+}
 
-    // Generate README
-    let readme_path = args.repo_dir.parent()
-        .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))?
-        .parent()
-        .ok_or_else(|| anyhow::anyhow!("Repo dir has no grandparent"))?
-        .join("README.md");
-
-    if let Err(e) = md::regenerate_readme(&readme_path, &fdroid_index) {
-        error!("Error generating {:?}: {}", readme_path, e);
+/// Environment variables holding the token of well-known hosts
+const TOKEN_ENV_VARS: &[(&str, &str)] = &[
+    (GITHUB_HOST, "GITHUB_TOKEN"),
+    ("gitlab.com", "GITLAB_TOKEN"),
+    ("codeberg.org", "CODEBERG_TOKEN"),
+];
+
+fn parse_host_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((host, value)) if !host.is_empty() && !value.is_empty() => {
+            Ok((host.to_string(), value.to_string()))
+        }
+        _ => Err("expected HOST=VALUE".to_string()),
     }
+}
 
-    let (change_path, mut have_significant_changes) = apps::has_significant_changes(&initial_fdroid_index, &fdroid_index);
-    
-    if have_significant_changes {
-        info!("The index {:?} had a significant change at JSON path {:?}", fdroid_index_path, change_path);
-    } else {
-        info!("The index files didn't change significantly");
-
-        match git::get_changed_file_names(&args.repo_dir) {
-            Ok(changed_files) => {
-                for fname in changed_files {
-                    if !fname.contains("index") {
-                        have_significant_changes = true;
-                        info!("File {:?} is a significant change", fname);
-                    }
-                }
+/// Collects the tokens per host. Command line options win over environment
+/// variables, which win over the credentials file.
+fn load_credentials(args: &Args) -> Result<credentials::Credentials> {
+    let mut credentials = credentials::Credentials::default();
 
-                if !have_significant_changes {
-                    info!("It doesn't look like there were any relevant changes");
-                }
-            }
-            Err(e) => {
-                error!("Getting changed files: {}", e);
-            }
-        }
+    for (host, token) in &args.tokens {
+        credentials.insert(host, token);
+    }
+    if let Some(token) = &args.personal_access_token {
+        credentials.insert(GITHUB_HOST, token);
     }
 
-    println!("::endgroup::");
-
-    if have_error {
-        std::process::exit(1);
+    let mut from_env = credentials::Credentials::default();
+    for (host, var) in TOKEN_ENV_VARS {
+        if let Ok(token) = std::env::var(var) {
+            from_env.insert(host, &token);
+        }
     }
+    credentials.merge_missing(from_env);
 
-    if !have_significant_changes {
-        std::process::exit(2);
+    let credentials_file = args.credentials_file.clone()
+        .or_else(|| std::env::var("METASCOOP_CREDENTIALS").ok().map(PathBuf::from));
+    if let Some(path) = credentials_file {
+        credentials.merge_missing(credentials::Credentials::load_file(&path)?);
     }
 
-    Ok(())
+    Ok(credentials)
 }
 
-fn set_non_empty(meta: &mut HashMap<String, serde_yaml::Value>, key: &str, value: &str) {
-    if !value.is_empty() || meta.get(key).and_then(|v| v.as_str()) == Some("Unknown") {
-        meta.insert(key.to_string(), serde_yaml::Value::String(value.to_string()));
-        info!("Set {} to {:?}", key, value);
-    }
+/// A GitHub App installation, with the app's own client to get new tokens
+struct Installation {
+    app: Octocrab,
+    id: u64,
+    /// Client of the installation, which refreshes its token itself
+    client: Octocrab,
+    token: SecretString,
 }
 
-async fn download_asset(
-    _github: &Octocrab,
-    owner: &str,
-    repo: &str,
-    asset_id: u64,
-    target_path: &Path,
-) -> Result<()> {
-    let temp_path = target_path.with_extension("tmp");
-
-    let asset_url = format!(
-        "https://api.github.com/repos/{}/{}/releases/assets/{}",
-        owner, repo, asset_id
-    );
-
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(300))
+/// Authenticates as an installation of a GitHub App
+async fn github_app_client(
+    app_id: u64,
+    key_path: &Path,
+    installation_id: Option<u64>,
+) -> Result<Installation> {
+    let pem = fs::read(key_path)
+        .with_context(|| format!("Failed to read GitHub App key: {}", key_path.display()))?;
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(&pem)
+        .context("Failed to parse GitHub App key")?;
+
+    let app = Octocrab::builder()
+        .app(app_id.into(), key)
         .build()?;
 
-    let mut request = client
-        .get(&asset_url)
-        .header("Accept", "application/octet-stream")
-        .header("User-Agent", "metascoop-rs");
-
-    if let Some(token) = std::env::var("GITHUB_TOKEN").ok() {
-        request = request.header("Authorization", format!("Bearer {}", token));
-    }
-
-    let response = request.send().await?;
+    let installation_id = match installation_id {
+        Some(id) => id,
+        None => {
+            let installations = app.apps().installations().send().await
+                .context("Failed to list GitHub App installations")?;
+            installations.items.first()
+                .map(|i| i.id.0)
+                .ok_or_else(|| anyhow::anyhow!("GitHub App {} has no installations", app_id))?
+        }
+    };
 
-    if !response.status().is_success() {
-        anyhow::bail!("Failed to download asset: HTTP {}", response.status());
-    }
+    info!("Authenticating as installation {} of GitHub App {}", installation_id, app_id);
 
-    let mut file = tokio::fs::File::create(&temp_path).await?;
-    let bytes = response.bytes().await?;
-    file.write_all(&bytes).await?;
-    file.sync_all().await?;
+    let (client, token) = app.installation_and_token(installation_id.into()).await
+        .context("Failed to get a GitHub App installation token")?;
 
-    tokio::fs::rename(&temp_path, target_path).await?;
+    Ok(Installation { app, id: installation_id, client, token })
+}
 
-    Ok(())
+/// Installation tokens expire after an hour, so new ones are fetched well before
+const INSTALLATION_TOKEN_REFRESH: Duration = Duration::from_secs(45 * 60);
+
+/// Keeps the installation token of asset downloads and clones valid for the
+/// rest of the run by replacing it in `api` and `credentials`
+fn refresh_installation_token(installation: Installation, api: github::GitHubApi, mut credentials: credentials::Credentials) {
+    let refresh = async move {
+        loop {
+            tokio::time::sleep(INSTALLATION_TOKEN_REFRESH).await;
+
+            match installation.app.installation_and_token(installation.id.into()).await {
+                Ok((_, token)) => {
+                    credentials.insert(GITHUB_HOST, token.expose_secret());
+                    api.set_token(token.expose_secret());
+                    info!("Refreshed the GitHub App installation token");
+                }
+                Err(e) => warn!("Failed to refresh the GitHub App installation token: {}", e),
+            }
+        }
+    };
+    tokio::spawn(output::current().scope(refresh));
 }
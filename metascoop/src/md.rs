@@ -1,13 +1,23 @@
 use anyhow::{Context, Result};
+use log::info;
+use qrcode::render::svg;
+use qrcode::QrCode;
+use regex::Regex;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tera::{Tera, Context as TeraContext};
 
-use crate::apps::RepoIndex;
+use crate::apps::{self, RepoIndex};
 
 const TABLE_START: &str = "<!-- This table is auto-generated. Do not edit -->";
 const TABLE_END: &str = "<!-- end apps table -->";
 
+const REPO_START: &str = "<!-- This section is auto-generated. Do not edit -->";
+const REPO_END: &str = "<!-- end add repo section -->";
+
+/// QR code of the repo link, relative to the README
+pub const QR_CODE_PATH: &str = ".github/qrcode.svg";
+
 const TABLE_TMPL: &str = r#"
 | Icon | Name | Description | Version |
 | --- | --- | --- | --- |
@@ -16,35 +26,196 @@ const TABLE_TMPL: &str = r#"
 {% endfor -%}
 "#;
 
-pub fn regenerate_readme(readme_path: &Path, index: &RepoIndex) -> Result<()> {
-    let content = fs::read_to_string(readme_path)
-        .with_context(|| format!("Failed to read README: {}", readme_path.display()))?;
+const REPO_TMPL: &str = r#"
+2. Now you can copy the following [link]({{ url }}), then add this repository to your F-Droid client:
+
+    ```
+    {{ url }}
+    ```
+
+    Alternatively, you can also scan this QR code:
+
+    <p align="center">
+      <img src="{{ qr_code }}" alt="F-Droid repo QR code" width="256px" height="256px"/>
+    </p>
 
-    let table_start_idx = content.find(TABLE_START)
-        .ok_or_else(|| anyhow::anyhow!("Cannot find table start in {:?}", readme_path))?;
+    The repo is signed with the key whose SHA-256 fingerprint is `{{ fingerprint }}`.
 
-    let table_end_idx = content.find(TABLE_END)
-        .ok_or_else(|| anyhow::anyhow!("Cannot find table end in {:?}", readme_path))?;
+"#;
+
+/// Rewrites the apps table of the README, listing apps in `package_order`
+pub fn regenerate_readme(readme_path: &Path, index: &RepoIndex, package_order: &[String]) -> Result<()> {
+    let content = fs::read_to_string(readme_path)
+        .with_context(|| format!("Failed to read README: {}", readme_path.display()))?;
 
     // Create Tera template
     let mut tera = Tera::default();
     tera.add_raw_template("table", TABLE_TMPL)?;
 
+    let mut apps = index.apps.clone();
+    apps.sort_by_key(|app| {
+        let package_name = app.get("packageName").and_then(|p| p.as_str()).unwrap_or("");
+        (apps::order_position(package_order, package_name), package_name.to_string())
+    });
+
     let mut context = TeraContext::new();
-    context.insert("apps", &index.apps);
+    context.insert("apps", &apps);
 
     let table_content = tera.render("table", &context)?;
 
-    // Reconstruct the file
-    let mut new_content = String::new();
-    new_content.push_str(&content[..table_start_idx]);
-    new_content.push_str(TABLE_START);
-    new_content.push_str(&table_content);
-    new_content.push_str(TABLE_END);
-    new_content.push_str(&content[table_end_idx + TABLE_END.len()..]);
+    let new_content = replace_section(&content, TABLE_START, TABLE_END, &table_content)
+        .ok_or_else(|| anyhow::anyhow!("Cannot find the apps table markers in {:?}", readme_path))?;
+
+    fs::write(readme_path, new_content)
+        .with_context(|| format!("Failed to write README: {}", readme_path.display()))?;
+
+    Ok(())
+}
+
+/// README of the repository, next to the fdroid directory holding the repo
+/// directory
+pub fn readme_path(repo_dir: &Path) -> Result<PathBuf> {
+    let repo_dir = fs::canonicalize(repo_dir)
+        .with_context(|| format!("Failed to resolve the repo dir {}", repo_dir.display()))?;
+    Ok(repo_dir.parent()
+        .and_then(Path::parent)
+        .ok_or_else(|| anyhow::anyhow!("Repo dir has no grandparent"))?
+        .join("README.md"))
+}
+
+/// Finds the repo fingerprint in the link to add the repo of a README, e.g.
+/// `.../fdroid/repo?fingerprint=D04F...`, in lowercase
+pub fn pinned_fingerprint(content: &str) -> Option<String> {
+    let re = Regex::new(r"fingerprint=([0-9A-Fa-f]{64})").unwrap();
+    re.captures(content).map(|c| c[1].to_lowercase())
+}
+
+/// Whether the README has an add repo section to regenerate
+pub fn has_repo_section(readme_path: &Path) -> Result<bool> {
+    let content = fs::read_to_string(readme_path)
+        .with_context(|| format!("Failed to read README: {}", readme_path.display()))?;
+    Ok(content.contains(REPO_START))
+}
+
+/// Rewrites the section of the README on adding the repo to a client, with
+/// the link to the repo at `address` holding its `fingerprint`, and writes
+/// its QR code to [`QR_CODE_PATH`]. READMEs without the section are left as
+/// they are.
+///
+/// The fingerprint already in the README pins the signing key: a different
+/// one is refused, so a repo signed with another key can't vouch for itself.
+/// Change the link by hand when the key changes on purpose.
+pub fn regenerate_repo_section(readme_path: &Path, address: &str, fingerprint: &str) -> Result<()> {
+    let content = fs::read_to_string(readme_path)
+        .with_context(|| format!("Failed to read README: {}", readme_path.display()))?;
+
+    if !content.contains(REPO_START) {
+        info!("{:?} has no add repo section, not generating it", readme_path);
+        return Ok(());
+    }
+
+    if let Some(pinned) = pinned_fingerprint(&content) {
+        if !pinned.eq_ignore_ascii_case(fingerprint) {
+            anyhow::bail!(
+                "{:?} pins the repo fingerprint {}, but the keystore's is {}",
+                readme_path,
+                pinned.to_uppercase(),
+                fingerprint.to_uppercase()
+            );
+        }
+    }
+
+    let fingerprint = fingerprint.to_uppercase();
+    let url = format!("{}?fingerprint={}", address, fingerprint);
+
+    let qr_code_path = readme_path.parent().unwrap_or(Path::new("")).join(QR_CODE_PATH);
+    write_qr_code(&qr_code_path, &url)?;
+
+    let mut tera = Tera::default();
+    tera.add_raw_template("repo", REPO_TMPL)?;
+
+    let mut context = TeraContext::new();
+    context.insert("url", &url);
+    context.insert("fingerprint", &fingerprint);
+    context.insert("qr_code", QR_CODE_PATH);
+
+    let section = tera.render("repo", &context)?;
+
+    let new_content = replace_section(&content, REPO_START, REPO_END, &section)
+        .ok_or_else(|| anyhow::anyhow!("Cannot find the end of the add repo section in {:?}", readme_path))?;
 
     fs::write(readme_path, new_content)
         .with_context(|| format!("Failed to write README: {}", readme_path.display()))?;
 
     Ok(())
 }
+
+/// Writes a QR code of `url` as an SVG image
+pub fn write_qr_code(path: &Path, url: &str) -> Result<()> {
+    let code = QrCode::new(url.as_bytes())
+        .with_context(|| format!("Failed to encode {:?} as a QR code", url))?;
+    let image = code.render::<svg::Color>()
+        .min_dimensions(256, 256)
+        .build();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, image)
+        .with_context(|| format!("Failed to write QR code: {}", path.display()))
+}
+
+/// Replaces what is between the `start` and `end` markers of `content`
+pub fn replace_section(content: &str, start: &str, end: &str, section: &str) -> Option<String> {
+    let start_idx = content.find(start)?;
+    let end_idx = start_idx + content[start_idx..].find(end)?;
+
+    let mut new_content = String::new();
+    new_content.push_str(&content[..start_idx]);
+    new_content.push_str(start);
+    new_content.push_str(section);
+    new_content.push_str(end);
+    new_content.push_str(&content[end_idx + end.len()..]);
+    Some(new_content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "d04f9c306c0ca32cc89c5d4ea871916330be95c91fb08f2ad101a11cbfaac31a";
+
+    fn readme(dir: &Path, section: &str) -> std::path::PathBuf {
+        let path = dir.join("README.md");
+        fs::write(&path, format!("# Repo\n{}{}{}\n", REPO_START, section, REPO_END)).unwrap();
+        path
+    }
+
+    #[test]
+    fn repo_section_pins_the_first_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = readme(dir.path(), "\n");
+
+        regenerate_repo_section(&path, "https://example.com/fdroid/repo", FINGERPRINT).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(pinned_fingerprint(&content).as_deref(), Some(FINGERPRINT));
+        assert!(content.contains(&format!("https://example.com/fdroid/repo?fingerprint={}", FINGERPRINT.to_uppercase())));
+        assert!(dir.path().join(QR_CODE_PATH).exists());
+
+        // The same key again is fine
+        regenerate_repo_section(&path, "https://example.com/fdroid/repo", &FINGERPRINT.to_uppercase()).unwrap();
+    }
+
+    #[test]
+    fn repo_section_refuses_another_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = readme(dir.path(), &format!("\nrepo?fingerprint={}\n", FINGERPRINT.to_uppercase()));
+        let before = fs::read_to_string(&path).unwrap();
+
+        let other = "ab".repeat(32);
+        let error = regenerate_repo_section(&path, "https://example.com/fdroid/repo", &other).err().unwrap();
+        assert!(error.to_string().contains("pins the repo fingerprint"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+}
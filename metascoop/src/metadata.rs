@@ -0,0 +1,346 @@
+use anyhow::Result;
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::apps::{self, AppInfo, MetaFile, RepoIndex};
+use crate::credentials::Credentials;
+use crate::plan::{FieldChange, MetadataChange, ScreenshotChange};
+use crate::{checksum, file, git, output};
+
+/// Shared, read-only state for filling in metadata files concurrently
+pub struct MetadataContext<'a> {
+    pub metadata_dir: &'a Path,
+    pub repo_dir: &'a Path,
+    pub fdroid_index: &'a RepoIndex,
+    /// Map of apk_name -> AppInfo
+    pub apk_info_map: &'a HashMap<String, AppInfo>,
+    pub credentials: &'a Credentials,
+    /// Only report changes instead of making them
+    pub dry_run: bool,
+}
+
+/// What filling in the metadata of one package did, or would do in dry-run mode
+#[derive(Debug, Default)]
+pub struct PackageOutcome {
+    /// Key of the app the package belongs to
+    pub app_key: Option<String>,
+    /// Screenshots directory to remove once fdroid has picked up the screenshots
+    pub screenshots_dir: Option<PathBuf>,
+    pub changes: Option<MetadataChange>,
+    pub screenshots: Option<ScreenshotChange>,
+}
+
+/// Directory holding F-Droid's metadata files, next to the repo directory
+pub fn metadata_dir(repo_dir: &Path) -> Result<PathBuf> {
+    Ok(repo_dir.parent()
+        .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))?
+        .join("metadata"))
+}
+
+/// Lists the metadata files of all packages, sorted by package name
+pub fn metadata_files(metadata_dir: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(metadata_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == "yml"))
+        .collect()
+}
+
+/// Sorts metadata files in `package_order`, see [`apps::package_order`]
+pub fn sort_by_package_order(files: &mut [PathBuf], package_order: &[String]) {
+    files.sort_by_key(|path| {
+        let package_name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        apps::order_position(package_order, package_name)
+    });
+}
+
+/// Fills in the metadata file at `path` from apps.yaml and the upstream
+/// repository
+pub async fn process_package(ctx: &MetadataContext<'_>, path: &Path) -> Result<PackageOutcome> {
+    let pkg_name = path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string();
+
+    output::group(&pkg_name);
+
+    let result = fill_metadata(ctx, path, &pkg_name).await;
+
+    output::endgroup();
+
+    result
+}
+
+async fn fill_metadata(ctx: &MetadataContext<'_>, path: &Path, pkg_name: &str) -> Result<PackageOutcome> {
+    info!("Working on {:?}", pkg_name);
+
+    let mut outcome = PackageOutcome::default();
+
+    let mut meta = match apps::read_meta_file(path) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Reading meta file {:?}: {}", path, e);
+            return Ok(outcome);
+        }
+    };
+    let original = meta.clone();
+
+    let latest_package = match ctx.fdroid_index.find_latest_package(pkg_name) {
+        Some(p) => p,
+        None => return Ok(outcome),
+    };
+
+    info!("The latest version is {:?} with versionCode {}", latest_package.version_name, latest_package.version_code);
+
+    let apk_info = match ctx.apk_info_map.get(&latest_package.apk_name) {
+        Some(info) => info,
+        None => {
+            info!("Cannot find apk info for {:?}", latest_package.apk_name);
+            return Ok(outcome);
+        }
+    };
+
+    outcome.app_key = Some(apk_info.key_name.clone());
+
+    // Update metadata
+    set_non_empty(&mut meta, "AuthorName", apk_info.author_name());
+
+    let friendly_name = if !apk_info.name.is_empty() {
+        &apk_info.name
+    } else {
+        apk_info.app_name()
+    };
+    set_non_empty(&mut meta, "Name", friendly_name);
+    set_non_empty(&mut meta, "SourceCode", &apk_info.git);
+    set_non_empty(&mut meta, "License", &apk_info.license);
+    set_non_empty(&mut meta, "Description", &apk_info.description);
+
+    let mut summary = apk_info.summary.clone();
+    const MAX_SUMMARY_LENGTH: usize = 80;
+    if summary.len() > MAX_SUMMARY_LENGTH {
+        summary.truncate(MAX_SUMMARY_LENGTH - 3);
+        summary.push_str("...");
+        info!("Truncated summary to length of {} (max length)", summary.len());
+    }
+    set_non_empty(&mut meta, "Summary", &summary);
+
+    if !apk_info.categories.is_empty() {
+        meta.insert("Categories".to_string(), serde_yaml::Value::Sequence(
+            apk_info.categories.iter().map(|s| serde_yaml::Value::String(s.clone())).collect()
+        ));
+    }
+
+    if !apk_info.anti_features.is_empty() {
+        let anti_features = apk_info.anti_features.join(",");
+        meta.insert("AntiFeatures".to_string(), serde_yaml::Value::String(anti_features));
+    }
+
+    meta.insert("CurrentVersion".to_string(), serde_yaml::Value::String(latest_package.version_name.clone()));
+    meta.insert("CurrentVersionCode".to_string(), serde_yaml::Value::Number(latest_package.version_code.into()));
+
+    info!("Set current version info to versionName={:?}, versionCode={}", latest_package.version_name, latest_package.version_code);
+
+    let mut change = MetadataChange {
+        package: pkg_name.to_string(),
+        fields: changed_fields(&original, &meta),
+        changelog: None,
+    };
+
+    if ctx.dry_run {
+        info!("Would change {} fields of {:?}", change.fields.len(), path);
+    } else {
+        if let Err(e) = apps::write_meta_file(path, &meta) {
+            error!("Writing meta file {:?}: {}", path, e);
+            return Ok(outcome);
+        }
+
+        info!("Updated metadata file {:?}", path);
+    }
+
+    // Write changelog
+    if !apk_info.release_description.is_empty() {
+        let changelog_path = ctx.metadata_dir
+            .join(&latest_package.package_name)
+            .join("en-US")
+            .join("changelogs")
+            .join(format!("{}.txt", latest_package.version_code));
+
+        let current = fs::read_to_string(&changelog_path).unwrap_or_default();
+        if current != apk_info.release_description {
+            change.changelog = Some(changelog_path.display().to_string());
+        }
+
+        if !ctx.dry_run {
+            if let Some(parent) = changelog_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            fs::write(&changelog_path, &apk_info.release_description)?;
+            info!("Wrote release notes to {:?}", changelog_path);
+        }
+    }
+
+    if !change.fields.is_empty() || change.changelog.is_some() {
+        outcome.changes = Some(change);
+    }
+
+    // Clone repo and find screenshots
+    info!("Cloning git repository to search for screenshots");
+
+    let git_url = apk_info.git.clone();
+    let token = ctx.credentials.token_for_url(&git_url);
+    let repo_dir = ctx.repo_dir.to_path_buf();
+    let screenshots_path = ctx.metadata_dir
+        .join(&latest_package.package_name)
+        .join("en-US")
+        .join("phoneScreenshots");
+    let package = pkg_name.to_string();
+    let dry_run = ctx.dry_run;
+
+    // Cloning, hashing and moving the screenshots block, so they run on a
+    // blocking thread
+    let (screenshots, screenshots_dir) = output::spawn_blocking(move || {
+        update_screenshots(&git_url, token.as_deref(), &repo_dir, &package, &screenshots_path, dry_run)
+    }).await?;
+    outcome.screenshots = screenshots;
+    outcome.screenshots_dir = screenshots_dir;
+
+    Ok(outcome)
+}
+
+/// Clones the app's repository and moves the screenshots it has to
+/// `screenshots_path`, unless `dry_run`. Returns how they differ from the
+/// published ones, and the directory they were moved to.
+fn update_screenshots(
+    git_url: &str,
+    token: Option<&str>,
+    repo_dir: &Path,
+    pkg_name: &str,
+    screenshots_path: &Path,
+    dry_run: bool,
+) -> Result<(Option<ScreenshotChange>, Option<PathBuf>)> {
+    let git_repo_path = match git::clone_repo(git_url, token) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Cloning git repo from {:?}: {}", git_url, e);
+            return Ok((None, None));
+        }
+    };
+
+    let mut result = (None, None);
+    match apps::find_metadata(&git_repo_path) {
+        Ok(repo_metadata) if dry_run => {
+            info!("Found {} screenshots", repo_metadata.screenshots.len());
+            result.0 = published_screenshot_changes(repo_dir, pkg_name, &repo_metadata.screenshots);
+        }
+        Ok(repo_metadata) => {
+            info!("Found {} screenshots", repo_metadata.screenshots.len());
+            result.0 = published_screenshot_changes(repo_dir, pkg_name, &repo_metadata.screenshots);
+
+            let _ = fs::remove_dir_all(screenshots_path);
+
+            let mut counter = 1;
+            for screenshot in &repo_metadata.screenshots {
+                if let Some(ext) = screenshot.extension() {
+                    let new_file_path = screenshots_path.join(format!("{}.{}", counter, ext.to_string_lossy()));
+
+                    if let Some(parent) = new_file_path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    if let Err(e) = file::move_file(screenshot, &new_file_path) {
+                        error!("Moving screenshot file {:?} to {:?}: {}", screenshot, new_file_path, e);
+                    } else {
+                        info!("Wrote screenshot to {:?}", new_file_path);
+                        counter += 1;
+                    }
+                }
+            }
+
+            result.1 = Some(screenshots_path.to_path_buf());
+        }
+        Err(e) => {
+            error!("Finding metadata in git repo {:?}: {}", git_repo_path, e);
+        }
+    }
+
+    let _ = fs::remove_dir_all(&git_repo_path);
+    Ok(result)
+}
+
+/// Fields whose value differs between the metadata as read and as filled in
+fn changed_fields(
+    original: &MetaFile,
+    meta: &MetaFile,
+) -> Vec<FieldChange> {
+    let mut keys: Vec<&String> = original.keys().chain(meta.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter(|key| original.get(*key) != meta.get(*key))
+        .map(|key| FieldChange {
+            key: key.clone(),
+            old: original.get(key).cloned(),
+            new: meta.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// How the screenshots found upstream differ from the ones in the repo
+fn published_screenshot_changes(repo_dir: &Path, pkg_name: &str, screenshots: &[PathBuf]) -> Option<ScreenshotChange> {
+    let published_path = repo_dir
+        .join(pkg_name)
+        .join("en-US")
+        .join("phoneScreenshots");
+
+    let change = screenshot_changes(pkg_name, screenshots, &published_path);
+    (!change.is_empty()).then_some(change)
+}
+
+/// Compares the screenshots found upstream, numbered as they would be
+/// published, with the published ones
+fn screenshot_changes(pkg_name: &str, screenshots: &[PathBuf], published_path: &Path) -> ScreenshotChange {
+    let mut change = ScreenshotChange {
+        package: pkg_name.to_string(),
+        ..Default::default()
+    };
+
+    let mut names = Vec::new();
+    for (i, screenshot) in screenshots.iter().filter(|s| s.extension().is_some()).enumerate() {
+        let ext = screenshot.extension().unwrap_or_default().to_string_lossy();
+        let name = format!("{}.{}", i + 1, ext);
+        let published = published_path.join(&name);
+
+        if !published.exists() {
+            change.added.push(name.clone());
+        } else if checksum::file_sha256(&published).ok() != checksum::file_sha256(screenshot).ok() {
+            change.changed.push(name.clone());
+        }
+
+        names.push(name);
+    }
+
+    if let Ok(entries) = fs::read_dir(published_path) {
+        let mut removed: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| !names.contains(name))
+            .collect();
+        removed.sort();
+        change.removed = removed;
+    }
+
+    change
+}
+
+fn set_non_empty(meta: &mut MetaFile, key: &str, value: &str) {
+    if !value.is_empty() || meta.get(key).and_then(|v| v.as_str()) == Some("Unknown") {
+        meta.insert(key.to_string(), serde_yaml::Value::String(value.to_string()));
+        info!("Set {} to {:?}", key, value);
+    }
+}
@@ -0,0 +1,712 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tera::{Tera, Context as TeraContext};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::apps::RepoIndex;
+use crate::credentials;
+use crate::metadata;
+use crate::release_notes::PackageChange;
+use crate::sync::{ReleaseOutcome, ReleaseStatus};
+
+/// Something a sync did that targets can be notified of
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A version was published in the repo
+    NewVersion {
+        app: String,
+        package: String,
+        version_name: String,
+        version_code: i32,
+        /// Download link of the APK
+        url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        changelog: Option<String>,
+    },
+    /// A version was removed from the repo
+    RemovedVersion {
+        app: String,
+        package: String,
+        version_name: String,
+        version_code: i32,
+    },
+    /// The releases of an app couldn't be listed, or one of them couldn't be
+    /// published
+    AppFailed {
+        app: String,
+        error: String,
+    },
+}
+
+/// Kinds of events, to choose which ones a target gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    NewVersion,
+    RemovedVersion,
+    AppFailed,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::NewVersion { .. } => EventKind::NewVersion,
+            Event::RemovedVersion { .. } => EventKind::RemovedVersion,
+            Event::AppFailed { .. } => EventKind::AppFailed,
+        }
+    }
+
+    /// Name of the event as in its JSON, e.g. "new_version"
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind() {
+            EventKind::NewVersion => "new_version",
+            EventKind::RemovedVersion => "removed_version",
+            EventKind::AppFailed => "app_failed",
+        }
+    }
+
+    /// One line describing the event, for chat messages
+    pub fn message(&self) -> String {
+        match self {
+            Event::NewVersion { app, version_name, version_code, url, .. } => {
+                format!("{} {} ({}) is available: {}", app, version_name, version_code, url)
+            }
+            Event::RemovedVersion { app, version_name, version_code, .. } => {
+                format!("{} {} ({}) was removed from the repo", app, version_name, version_code)
+            }
+            Event::AppFailed { app, error } => format!("Syncing {} failed: {}", app, error),
+        }
+    }
+}
+
+/// A setting given as it is or read from an environment variable with
+/// `{env: NAME}`, for tokens and webhook URLs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Setting {
+    Value(String),
+    Env { env: String },
+}
+
+impl Setting {
+    /// The value of the setting, hidden in the output from then on
+    fn resolve(&self) -> Result<String> {
+        let value = match self {
+            Setting::Value(value) => value.clone(),
+            Setting::Env { env } => std::env::var(env)
+                .with_context(|| format!("Environment variable {} is not set", env))?,
+        };
+        credentials::register_secret(&value);
+        Ok(value)
+    }
+}
+
+/// Where notifications are sent, an entry of the notifications file
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    #[serde(flatten)]
+    pub kind: TargetKind,
+    /// Events the target gets, all of them when empty
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Tera template of the webhook body or the chat message, rendered with
+    /// `event` and `message`
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TargetKind {
+    /// POSTs the event as JSON, or the rendered template
+    Webhook {
+        url: Setting,
+        #[serde(default)]
+        headers: BTreeMap<String, Setting>,
+    },
+    /// POSTs `{"text": message}`, as Slack, Mattermost and Discord's Slack
+    /// endpoint accept
+    Slack { url: Setting },
+    /// Sends the message to a Matrix room as the user of the access token
+    Matrix {
+        homeserver: String,
+        room: String,
+        token: Setting,
+    },
+    /// Runs a local command with the event as JSON on stdin
+    Command { command: Vec<String> },
+}
+
+impl Target {
+    fn wants(&self, event: &Event) -> bool {
+        self.events.is_empty() || self.events.contains(&event.kind())
+    }
+
+    /// Describes the target in logs. Webhook URLs often hold a secret, so
+    /// only their host is shown.
+    fn name(&self) -> String {
+        match &self.kind {
+            TargetKind::Webhook { url, .. } | TargetKind::Slack { url } => match url {
+                Setting::Value(url) => {
+                    let host = Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_string));
+                    format!("webhook at {}", host.unwrap_or_else(|| "an invalid URL".to_string()))
+                }
+                Setting::Env { env } => format!("webhook of ${}", env),
+            },
+            TargetKind::Matrix { room, .. } => format!("Matrix room {}", room),
+            TargetKind::Command { command } => format!("command {:?}", command.join(" ")),
+        }
+    }
+}
+
+/// What webhooks and commands get: the event and its message
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    message: String,
+}
+
+/// Reads the notification targets from a YAML file listing them:
+///
+/// ```yaml
+/// - type: slack
+///   url:
+///     env: SLACK_WEBHOOK_URL
+///   events: [new_version]
+/// ```
+///
+/// There are no targets when the file doesn't exist.
+pub fn load(path: &Path) -> Result<Vec<Target>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read notifications file: {}", path.display()))?;
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse YAML from {}", path.display()))
+}
+
+/// What events are made of in the run summary written by `--summary-json`
+#[derive(Debug, Default, Deserialize)]
+pub struct Summary {
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub apps: Vec<SummaryApp>,
+    #[serde(default)]
+    pub index_changes: Vec<PackageChange>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SummaryApp {
+    pub key: String,
+    #[serde(default)]
+    pub releases: Vec<ReleaseOutcome>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read summary: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse JSON from {}", path.display()))
+    }
+}
+
+/// Sends the events of the sync whose summary is at `summary_path` to the
+/// targets of `notifications_path`, or only those of apps that failed. Run it
+/// once the repo is published, so the links of new versions work. Problems
+/// are logged and don't fail the command, as the sync itself went through.
+pub async fn run(notifications_path: &Path, summary_path: &Path, repo_dir: &Path, only_failures: bool) -> Result<()> {
+    let targets = match load(notifications_path) {
+        Ok(targets) => targets,
+        Err(e) => {
+            error!("Not sending notifications: {:#}", e);
+            return Ok(());
+        }
+    };
+    if targets.is_empty() {
+        info!("No notification targets in {:?}", notifications_path);
+        return Ok(());
+    }
+
+    let summary = match Summary::read(summary_path) {
+        Ok(summary) => summary,
+        Err(e) => {
+            error!("Not sending notifications: {:#}", e);
+            return Ok(());
+        }
+    };
+    if summary.dry_run {
+        info!("Not sending notifications of a dry run");
+        return Ok(());
+    }
+
+    let mut events = Vec::new();
+    if !only_failures {
+        let index = RepoIndex::read_index(&repo_dir.join("index-v1.json"))
+            .and_then(|index| Ok((index, metadata::metadata_dir(repo_dir)?)));
+        match index {
+            Ok((index, metadata_dir)) => events.extend(version_events(&summary, &index, &metadata_dir)),
+            Err(e) => error!("Not sending notifications of new and removed versions: {:#}", e),
+        }
+    }
+    events.extend(failure_events(&summary));
+
+    dispatch(&targets, &events).await;
+    Ok(())
+}
+
+/// Lists the versions a sync added to and removed from the index, described
+/// with the APKs of the final `index`
+pub fn version_events(summary: &Summary, index: &RepoIndex, metadata_dir: &Path) -> Vec<Event> {
+    let address = index.repo.get("address").and_then(|a| a.as_str()).unwrap_or_default();
+    let mut events = Vec::new();
+
+    for change in &summary.index_changes {
+        for version in &change.added {
+            let apk_name = index.packages.get(&change.package)
+                .and_then(|pkgs| pkgs.iter().find(|p| p.version_code == version.code))
+                .map(|p| p.apk_name.clone())
+                .unwrap_or_default();
+            let changelog_path = metadata_dir
+                .join(&change.package)
+                .join("en-US")
+                .join("changelogs")
+                .join(format!("{}.txt", version.code));
+
+            events.push(Event::NewVersion {
+                app: change.name.clone(),
+                package: change.package.clone(),
+                version_name: version.name.clone(),
+                version_code: version.code,
+                url: format!("{}/{}", address, apk_name),
+                changelog: fs::read_to_string(changelog_path).ok().map(|c| c.trim().to_string()),
+            });
+        }
+        for version in &change.removed {
+            events.push(Event::RemovedVersion {
+                app: change.name.clone(),
+                package: change.package.clone(),
+                version_name: version.name.clone(),
+                version_code: version.code,
+            });
+        }
+    }
+
+    events
+}
+
+/// Lists the apps whose releases couldn't be listed, and their releases
+/// that couldn't be published
+pub fn failure_events(summary: &Summary) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    for app in &summary.apps {
+        if let Some(error) = &app.error {
+            events.push(Event::AppFailed { app: app.key.clone(), error: error.clone() });
+        }
+        for release in &app.releases {
+            if let ReleaseStatus::Failed { error } = &release.status {
+                events.push(Event::AppFailed {
+                    app: app.key.clone(),
+                    error: format!("{}: {}", release.version, error),
+                });
+            }
+        }
+    }
+
+    events
+}
+
+/// Sends every event to the targets that want it. A target that fails is
+/// logged and the others still get the event. Returns the number of
+/// notifications that failed.
+pub async fn dispatch(targets: &[Target], events: &[Event]) -> usize {
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(30)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Not sending notifications: {}", e);
+            return events.len() * targets.len();
+        }
+    };
+
+    let mut failed = 0;
+    let mut sent = 0;
+    for event in events {
+        for target in targets.iter().filter(|t| t.wants(event)) {
+            match send(&client, target, event).await {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    warn!("Notifying {}: {:#}", target.name(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if sent > 0 {
+        info!("Sent {} notifications of {} events", sent, events.len());
+    }
+    failed
+}
+
+/// Sends an example event to every target, whatever events they want, to
+/// try them out. Fails if any of them fails.
+pub async fn send_test(targets: &[Target]) -> Result<()> {
+    if targets.is_empty() {
+        anyhow::bail!("There are no notification targets");
+    }
+
+    let targets: Vec<Target> = targets.iter()
+        .map(|t| Target { events: Vec::new(), ..t.clone() })
+        .collect();
+    let failed = dispatch(&targets, &[test_event()]).await;
+    if failed > 0 {
+        anyhow::bail!("{} of {} targets failed", failed, targets.len());
+    }
+    Ok(())
+}
+
+fn test_event() -> Event {
+    Event::NewVersion {
+        app: "example".to_string(),
+        package: "com.example.app".to_string(),
+        version_name: "1.0.0".to_string(),
+        version_code: 1,
+        url: "https://example.com/fdroid/repo/example_1.0.0.apk".to_string(),
+        changelog: Some("A test notification from metascoop".to_string()),
+    }
+}
+
+async fn send(client: &reqwest::Client, target: &Target, event: &Event) -> Result<()> {
+    let payload = Payload { event, message: event.message() };
+
+    match &target.kind {
+        TargetKind::Webhook { url, headers } => {
+            let body = match &target.template {
+                Some(template) => render(template, &payload)?,
+                None => serde_json::to_string(&payload)?,
+            };
+            let mut request = client.post(url.resolve()?)
+                .header("Content-Type", "application/json")
+                .header("User-Agent", "metascoop-rs")
+                .body(body);
+            for (name, value) in headers {
+                request = request.header(name, value.resolve()?);
+            }
+            check_response(request.send().await.map_err(reqwest::Error::without_url)?).await
+        }
+        TargetKind::Slack { url } => {
+            let text = message(target, &payload)?;
+            let request = client.post(url.resolve()?)
+                .header("Content-Type", "application/json")
+                .header("User-Agent", "metascoop-rs")
+                .body(serde_json::json!({ "text": text }).to_string());
+            check_response(request.send().await.map_err(reqwest::Error::without_url)?).await
+        }
+        TargetKind::Matrix { homeserver, room, token } => {
+            let text = message(target, &payload)?;
+            let mut url = Url::parse(homeserver)
+                .with_context(|| format!("Invalid homeserver URL {:?}", homeserver))?;
+            url.path_segments_mut()
+                .map_err(|_| anyhow::anyhow!("Invalid homeserver URL {:?}", homeserver))?
+                .pop_if_empty()
+                .extend(["_matrix", "client", "v3", "rooms", room, "send", "m.room.message", &transaction_id()]);
+
+            let request = client.put(url)
+                .bearer_auth(token.resolve()?)
+                .header("Content-Type", "application/json")
+                .header("User-Agent", "metascoop-rs")
+                .body(serde_json::json!({ "msgtype": "m.text", "body": text }).to_string());
+            check_response(request.send().await.map_err(reqwest::Error::without_url)?).await
+        }
+        TargetKind::Command { command } => {
+            let (program, args) = command.split_first()
+                .ok_or_else(|| anyhow::anyhow!("The command is empty"))?;
+            let mut child = tokio::process::Command::new(program)
+                .args(args)
+                .env("METASCOOP_EVENT", event.kind_name())
+                .env("METASCOOP_MESSAGE", &payload.message)
+                .stdin(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("Failed to run {:?}", program))?;
+
+            // Commands don't have to read the event
+            if let Some(mut stdin) = child.stdin.take() {
+                match stdin.write_all(serde_json::to_string(&payload)?.as_bytes()).await {
+                    Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            let status = child.wait().await?;
+            if !status.success() {
+                anyhow::bail!("{:?} exited with {}", program, status);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The chat message of an event, from the target's template if it has one
+fn message(target: &Target, payload: &Payload) -> Result<String> {
+    match &target.template {
+        Some(template) => render(template, payload),
+        None => Ok(payload.message.clone()),
+    }
+}
+
+fn render(template: &str, payload: &Payload) -> Result<String> {
+    let mut context = TeraContext::new();
+    context.insert("event", payload.event);
+    context.insert("message", &payload.message);
+    Tera::one_off(template, &context, false).context("Failed to render the notification template")
+}
+
+async fn check_response(response: reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if !status.is_success() {
+        // Error pages can be long, the start of the body is enough
+        let body = response.text().await.unwrap_or_default();
+        let body: String = body.trim().chars().take(200).collect();
+        anyhow::bail!("HTTP {}: {}", status, body);
+    }
+    Ok(())
+}
+
+/// Unique id of a Matrix message, so a retried request isn't sent twice
+fn transaction_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    format!("metascoop-{}-{}", millis, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::SyncReport;
+    use crate::release_notes::Version;
+    use crate::summary::{self, AppSummary};
+    use crate::output::Output;
+    use crate::testserver::{Response, Server};
+
+    fn targets(yaml: &str) -> Vec<Target> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn new_version() -> Event {
+        Event::NewVersion {
+            app: "trampoline".to_string(),
+            package: "com.example.trampoline".to_string(),
+            version_name: "3.2.0".to_string(),
+            version_code: 3002000,
+            url: "https://example.com/fdroid/repo/trampoline_3.2.0.apk".to_string(),
+            changelog: Some("Fixes".to_string()),
+        }
+    }
+
+    fn app_failed() -> Event {
+        Event::AppFailed { app: "notality".to_string(), error: "no releases".to_string() }
+    }
+
+    #[tokio::test]
+    async fn webhook_gets_the_event_as_json() {
+        let server = Server::start(|_| Response::new(200, "ok")).await;
+        let url = &server.url;
+        std::env::set_var("METASCOOP_TEST_HOOK_TOKEN", "hook-token");
+        let targets = targets(&format!(
+            "- type: webhook\n  url: {}/hook\n  headers:\n    X-Token:\n      env: METASCOOP_TEST_HOOK_TOKEN\n",
+            url
+        ));
+
+        assert_eq!(dispatch(&targets, &[new_version()]).await, 0);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/hook");
+        assert_eq!(requests[0].headers["x-token"], "hook-token");
+        assert_eq!(requests[0].headers["content-type"], "application/json");
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["event"], "new_version");
+        assert_eq!(body["version_code"], 3002000);
+        assert_eq!(body["changelog"], "Fixes");
+        assert_eq!(body["message"], "trampoline 3.2.0 (3002000) is available: https://example.com/fdroid/repo/trampoline_3.2.0.apk");
+    }
+
+    #[tokio::test]
+    async fn webhook_body_can_be_a_template() {
+        let server = Server::start(|_| Response::new(200, "ok")).await;
+        let url = &server.url;
+        let targets = targets(&format!(
+            "- type: webhook\n  url: {}/\n  template: '{{\"content\": {{{{ message | json_encode() }}}}, \"app\": \"{{{{ event.app }}}}\"}}'\n",
+            url
+        ));
+
+        assert_eq!(dispatch(&targets, &[app_failed()]).await, 0);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].body, r#"{"content": "Syncing notality failed: no releases", "app": "notality"}"#);
+    }
+
+    #[tokio::test]
+    async fn slack_and_matrix_get_the_message() {
+        let server = Server::start(|_| Response::new(200, "ok")).await;
+        let url = &server.url;
+        let targets = targets(&format!(
+            "- type: slack\n  url: {url}/services/T0/B0/secret\n- type: matrix\n  homeserver: {url}\n  room: '!room:example.org'\n  token: matrix-token\n",
+        ));
+
+        assert_eq!(dispatch(&targets, &[app_failed()]).await, 0);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/services/T0/B0/secret");
+        assert_eq!(requests[0].body, r#"{"text":"Syncing notality failed: no releases"}"#);
+
+        assert_eq!(requests[1].method, "PUT");
+        assert!(requests[1].path.starts_with("/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/metascoop-"), "{}", requests[1].path);
+        assert_eq!(requests[1].headers["authorization"], "Bearer matrix-token");
+        let body: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(body, serde_json::json!({ "msgtype": "m.text", "body": "Syncing notality failed: no releases" }));
+    }
+
+    #[tokio::test]
+    async fn targets_only_get_the_events_they_want() {
+        let server = Server::start(|_| Response::new(200, "ok")).await;
+        let url = &server.url;
+        let targets = targets(&format!("- type: slack\n  url: {}/\n  events: [app_failed]\n", url));
+
+        assert_eq!(dispatch(&targets, &[new_version(), app_failed()]).await, 0);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].body.contains("notality"));
+    }
+
+    #[tokio::test]
+    async fn failing_target_doesnt_stop_the_others() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/failing" => Response::new(500, "down"),
+            _ => Response::new(200, "ok"),
+        }).await;
+        let url = &server.url;
+        let targets = targets(&format!("- type: slack\n  url: {url}/failing\n- type: slack\n  url: {url}/working\n"));
+
+        assert_eq!(dispatch(&targets, &[app_failed()]).await, 1);
+
+        let requests = server.requests();
+        assert_eq!(requests[1].path, "/working");
+    }
+
+    #[tokio::test]
+    async fn webhook_urls_stay_out_of_errors() {
+        // Nothing listens on port 1
+        let url = "http://127.0.0.1:1/services/T0/B0/very-secret-path";
+        let targets = targets(&format!("- type: slack\n  url: {}\n", url));
+        let client = reqwest::Client::new();
+        let output = Output::default();
+
+        let error = output.clone().scope(send(&client, &targets[0], &app_failed())).await.err().unwrap();
+        assert!(!format!("{:#}", error).contains("very-secret-path"), "{:#}", error);
+        assert!(!output.secrets().redact(url).contains("very-secret-path"));
+    }
+
+    #[tokio::test]
+    async fn command_gets_the_event_on_stdin() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("event");
+        let script = format!("echo \"$METASCOOP_EVENT\" > {0}; cat >> {0}", out.display());
+        let targets = vec![Target {
+            kind: TargetKind::Command { command: vec!["sh".to_string(), "-c".to_string(), script] },
+            events: Vec::new(),
+            template: None,
+        }];
+
+        assert_eq!(dispatch(&targets, &[new_version()]).await, 0);
+
+        let content = fs::read_to_string(&out).unwrap();
+        let (kind, json) = content.split_once('\n').unwrap();
+        assert_eq!(kind, "new_version");
+        let body: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(body["package"], "com.example.trampoline");
+    }
+
+    #[test]
+    fn events_come_from_the_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = SyncReport {
+            index_changes: vec![PackageChange {
+                package: "com.a".to_string(),
+                name: "a".to_string(),
+                added: vec![Version { code: 2, name: "2.0".to_string() }],
+                removed: vec![Version { code: 1, name: "1.0".to_string() }],
+            }],
+            apps: vec![
+                AppSummary { key: "b".to_string(), error: Some("no releases".to_string()), ..Default::default() },
+                AppSummary {
+                    key: "c".to_string(),
+                    releases: vec![ReleaseOutcome {
+                        version: "v3".to_string(),
+                        status: ReleaseStatus::Failed { error: "bad APK".to_string() },
+                    }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let summary_path = dir.path().join("summary.json");
+        summary::write(&summary_path, &report).unwrap();
+        let summary = Summary::read(&summary_path).unwrap();
+
+        let index: RepoIndex = serde_json::from_value(serde_json::json!({
+            "repo": { "address": "https://example.com/fdroid/repo" },
+            "requests": {},
+            "apps": [],
+            "packages": { "com.a": [{
+                "added": 0, "apkName": "a_2.0.apk", "hash": "", "hashType": "sha256", "minSdkVersion": 21,
+                "packageName": "com.a", "sig": "", "signer": "", "size": 1, "targetSdkVersion": 33,
+                "versionCode": 2, "versionName": "2.0",
+            }] },
+        })).unwrap();
+        let metadata_dir = dir.path().join("metadata");
+        fs::create_dir_all(metadata_dir.join("com.a/en-US/changelogs")).unwrap();
+        fs::write(metadata_dir.join("com.a/en-US/changelogs/2.txt"), "Fixes\n").unwrap();
+
+        assert_eq!(version_events(&summary, &index, &metadata_dir), [
+            Event::NewVersion {
+                app: "a".to_string(),
+                package: "com.a".to_string(),
+                version_name: "2.0".to_string(),
+                version_code: 2,
+                url: "https://example.com/fdroid/repo/a_2.0.apk".to_string(),
+                changelog: Some("Fixes".to_string()),
+            },
+            Event::RemovedVersion {
+                app: "a".to_string(),
+                package: "com.a".to_string(),
+                version_name: "1.0".to_string(),
+                version_code: 1,
+            },
+        ]);
+        assert_eq!(failure_events(&summary), [
+            Event::AppFailed { app: "b".to_string(), error: "no releases".to_string() },
+            Event::AppFailed { app: "c".to_string(), error: "v3: bad APK".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,445 @@
+use log::Level;
+use serde_json::json;
+use std::cell::RefCell;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::credentials::Secrets;
+
+tokio::task_local! {
+    static BUFFER: RefCell<Captured>;
+    static OUTPUT: Output;
+}
+
+/// Place in a file a message is about
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats everything the tool prints and logs, one line per event
+pub trait Reporter: Send + Sync {
+    /// Starts a collapsible group of lines
+    fn group(&self, title: &str) -> Option<String>;
+
+    fn endgroup(&self) -> Option<String>;
+
+    fn message(&self, text: &str) -> String;
+
+    fn log(&self, level: Level, target: &str, message: &str, location: Option<&Location>) -> String;
+
+    /// Whether logs are written to stdout, along with the other output,
+    /// instead of stderr
+    fn logs_to_stdout(&self) -> bool {
+        false
+    }
+}
+
+/// Terminal output without markup
+pub struct PlainReporter;
+
+impl Reporter for PlainReporter {
+    fn group(&self, title: &str) -> Option<String> {
+        Some(format!("== {} ==", title))
+    }
+
+    fn endgroup(&self) -> Option<String> {
+        None
+    }
+
+    fn message(&self, text: &str) -> String {
+        text.to_string()
+    }
+
+    fn log(&self, level: Level, _target: &str, message: &str, location: Option<&Location>) -> String {
+        let prefix = location.map(|l| format!("{}: ", l)).unwrap_or_default();
+        match level {
+            Level::Error => format!("{}error: {}", prefix, message),
+            Level::Warn => format!("{}warning: {}", prefix, message),
+            Level::Info => format!("{}{}", prefix, message),
+            Level::Debug | Level::Trace => format!("{}debug: {}", prefix, message),
+        }
+    }
+}
+
+/// GitHub Actions workflow commands: collapsible groups, and warnings and
+/// errors as annotations
+pub struct GitHubReporter;
+
+/// Escapes the message of a workflow command
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escapes a property of a workflow command
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+impl Reporter for GitHubReporter {
+    fn group(&self, title: &str) -> Option<String> {
+        Some(format!("::group::{}", title))
+    }
+
+    fn endgroup(&self) -> Option<String> {
+        Some("::endgroup::".to_string())
+    }
+
+    fn message(&self, text: &str) -> String {
+        text.to_string()
+    }
+
+    fn log(&self, level: Level, _target: &str, message: &str, location: Option<&Location>) -> String {
+        let command = match level {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => return message.to_string(),
+            Level::Debug | Level::Trace => "debug",
+        };
+
+        let mut properties = Vec::new();
+        if let Some(location) = location {
+            properties.push(format!("file={}", escape_property(&location.file.to_string_lossy())));
+            if let Some(line) = location.line {
+                properties.push(format!("line={}", line));
+            }
+            if let Some(column) = location.column {
+                properties.push(format!("col={}", column));
+            }
+        }
+
+        if properties.is_empty() {
+            format!("::{}::{}", command, escape_data(message))
+        } else {
+            format!("::{} {}::{}", command, properties.join(","), escape_data(message))
+        }
+    }
+}
+
+/// One JSON object per line, for other programs
+pub struct JsonLinesReporter;
+
+impl Reporter for JsonLinesReporter {
+    fn group(&self, title: &str) -> Option<String> {
+        Some(json!({ "type": "group", "title": title }).to_string())
+    }
+
+    fn endgroup(&self) -> Option<String> {
+        Some(json!({ "type": "endgroup" }).to_string())
+    }
+
+    fn message(&self, text: &str) -> String {
+        json!({ "type": "message", "text": text }).to_string()
+    }
+
+    fn log(&self, level: Level, target: &str, message: &str, location: Option<&Location>) -> String {
+        let mut event = json!({
+            "type": "log",
+            "level": level.as_str().to_lowercase(),
+            "target": target,
+            "message": message,
+        });
+        if let Some(location) = location {
+            event["file"] = json!(location.file);
+            event["line"] = json!(location.line);
+            event["column"] = json!(location.column);
+        }
+        event.to_string()
+    }
+
+    fn logs_to_stdout(&self) -> bool {
+        true
+    }
+}
+
+/// Reporter names for the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum ReporterKind {
+    /// GitHub Actions when running in a workflow, plain otherwise
+    #[default]
+    Auto,
+    Plain,
+    Github,
+    Json,
+}
+
+impl ReporterKind {
+    pub fn reporter(self) -> Box<dyn Reporter> {
+        match self {
+            ReporterKind::Auto if std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true") => Box::new(GitHubReporter),
+            ReporterKind::Auto | ReporterKind::Plain => Box::new(PlainReporter),
+            ReporterKind::Github => Box::new(GitHubReporter),
+            ReporterKind::Json => Box::new(JsonLinesReporter),
+        }
+    }
+}
+
+/// Where the messages and logs of the tasks run in [`Output::scope`] go, how
+/// they are formatted and which tokens are redacted from them. Outside of a
+/// scope, messages and logs go to stderr with [`ReporterKind::Auto`] and
+/// nothing is redacted.
+#[derive(Clone)]
+pub struct Output {
+    reporter: Arc<dyn Reporter>,
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    secrets: Secrets,
+}
+
+impl Output {
+    /// Writes messages to `writer`, and logs too if the reporter
+    /// [logs to stdout](Reporter::logs_to_stdout)
+    pub fn new(reporter: Box<dyn Reporter>, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            reporter: Arc::from(reporter),
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            secrets: Secrets::default(),
+        }
+    }
+
+    pub fn secrets(&self) -> &Secrets {
+        &self.secrets
+    }
+
+    /// Runs `f` with this output
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        OUTPUT.scope(self, f).await
+    }
+
+    fn write(&self, text: &str) {
+        let _ = self.writer.lock().unwrap().write_all(text.as_bytes());
+    }
+
+    /// Writes logs to stderr, or along with the messages if the reporter
+    /// [logs to stdout](Reporter::logs_to_stdout)
+    fn write_log(&self, text: &str) -> io::Result<()> {
+        if self.reporter.logs_to_stdout() {
+            self.write(text);
+            Ok(())
+        } else {
+            io::stderr().write_all(text.as_bytes())
+        }
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new(ReporterKind::Auto.reporter(), io::stderr())
+    }
+}
+
+/// The output of the current [`Output::scope`]
+pub fn current() -> Output {
+    OUTPUT.try_with(Output::clone).unwrap_or_default()
+}
+
+/// What a task printed and logged in [`capture`], in order and redacted
+#[derive(Debug, Default)]
+pub struct Captured {
+    /// Text written, with whether it was logged
+    chunks: Vec<(bool, String)>,
+}
+
+impl Captured {
+    fn push(&mut self, logged: bool, text: &str) {
+        match self.chunks.last_mut() {
+            Some((last_logged, last)) if *last_logged == logged => last.push_str(text),
+            _ => self.chunks.push((logged, text.to_string())),
+        }
+    }
+
+    /// Writes the messages to the current output and the logs where logs go
+    pub fn write(self) {
+        let output = current();
+        for (logged, text) in self.chunks {
+            if logged {
+                let _ = output.write_log(&text);
+            } else {
+                output.write(&text);
+            }
+        }
+    }
+}
+
+/// Runs `f` while capturing everything it prints and logs, so that work done
+/// concurrently can be printed as one readable block afterwards
+pub async fn capture<F: Future>(f: F) -> (F::Output, Captured) {
+    BUFFER
+        .scope(RefCell::new(Captured::default()), async move {
+            let result = f.await;
+            let captured = BUFFER.with(|b| b.take());
+            (result, captured)
+        })
+        .await
+}
+
+/// Runs `f` on tokio's blocking threads, for file and CPU heavy work that
+/// would hold up the other tasks. It runs in the current [`Output::scope`],
+/// and what it prints and logs is captured with the caller's output.
+pub async fn spawn_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    let output = current();
+    let capturing = BUFFER.try_with(|_| ()).is_ok();
+
+    let blocking = tokio::task::spawn_blocking(move || {
+        OUTPUT.sync_scope(output, || {
+            if !capturing {
+                return (f(), Captured::default());
+            }
+            BUFFER.sync_scope(RefCell::new(Captured::default()), || {
+                let result = f();
+                (result, BUFFER.with(|b| b.take()))
+            })
+        })
+    });
+
+    let (result, captured) = match blocking.await {
+        Ok(done) => done,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    };
+    let _ = BUFFER.try_with(|b| b.borrow_mut().chunks.extend(captured.chunks));
+    result
+}
+
+fn write_line(line: &str) {
+    let output = current();
+    let line = format!("{}\n", output.secrets.redact(line));
+    let captured = BUFFER.try_with(|b| b.borrow_mut().push(false, &line));
+
+    if captured.is_err() {
+        output.write(&line);
+    }
+}
+
+pub fn print(message: impl Display) {
+    write_line(&current().reporter.message(&message.to_string()));
+}
+
+pub fn group(title: impl Display) {
+    if let Some(line) = current().reporter.group(&title.to_string()) {
+        write_line(&line);
+    }
+}
+
+pub fn endgroup() {
+    if let Some(line) = current().reporter.endgroup() {
+        write_line(&line);
+    }
+}
+
+/// Formats a log record with the reporter, for `env_logger`'s `format`
+pub fn format_record(record: &log::Record) -> String {
+    current().reporter.log(record.level(), record.target(), &record.args().to_string(), None)
+}
+
+/// Reports a problem at `location`, such as an annotation on GitHub
+pub fn annotate(level: Level, location: Option<&Location>, message: impl Display) {
+    let line = current().reporter.log(level, "", &message.to_string(), location);
+    let _ = LogWriter.write_all(format!("{}\n", line).as_bytes());
+}
+
+/// Log target that writes into the capture buffer of the current task, or to
+/// stderr outside of [`capture`], or with the messages if the reporter
+/// [logs to stdout](Reporter::logs_to_stdout). Known tokens are redacted.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let output = current();
+        let redacted = output.secrets.redact(&String::from_utf8_lossy(buf));
+        let captured = BUFFER.try_with(|b| b.borrow_mut().push(true, &redacted));
+
+        if captured.is_err() {
+            output.write_log(&redacted)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writer whose content can be read after it was handed to an [`Output`]
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    async fn print_and_log_in_blocking_task() -> Captured {
+        let (_, captured) = capture(async {
+            print("before");
+            spawn_blocking(|| {
+                print("blocking");
+                LogWriter.write_all(b"logged\n").unwrap();
+            }).await;
+            print("after");
+        }).await;
+        captured
+    }
+
+    #[tokio::test]
+    async fn blocking_tasks_are_captured_in_order() {
+        let writer = Shared::default();
+        let output = Output::new(Box::new(JsonLinesReporter), writer.clone());
+
+        output.scope(async { print_and_log_in_blocking_task().await.write() }).await;
+
+        // Logs are formatted before they reach the writer, so this one is as written
+        let text = writer.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines, [
+            r#"{"text":"before","type":"message"}"#,
+            r#"{"text":"blocking","type":"message"}"#,
+            "logged",
+            r#"{"text":"after","type":"message"}"#,
+        ]);
+    }
+
+    #[tokio::test]
+    async fn captured_logs_stay_out_of_the_messages() {
+        let writer = Shared::default();
+        let output = Output::new(Box::new(PlainReporter), writer.clone());
+        output.secrets().register("before");
+
+        output.scope(async { print_and_log_in_blocking_task().await.write() }).await;
+
+        assert_eq!(writer.text(), "***\nblocking\nafter\n");
+    }
+}
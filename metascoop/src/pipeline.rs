@@ -0,0 +1,786 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use log::{error, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::apps::{self, AppInfo, RepoIndex};
+use crate::credentials::Credentials;
+use crate::github::{GitHubApis, GITHUB_HOST};
+use crate::backend::{FdroidBackend, FdroidWarning, IndexBackend, UpdateOptions};
+use crate::metadata::{self, PackageOutcome};
+use crate::plan::Plan;
+use crate::published::PublishedFiles;
+use crate::release_notes::{self, PackageChange};
+use crate::summary::{AppSummary, PackageSummary};
+use crate::sync::{self, AppOutcome};
+use crate::templates::{self, TemplateContext};
+use crate::{atom, git, index, md, output, ratelimit, site};
+
+/// Settings of a sync, see [`SyncOptions::new`] for the defaults
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SyncOptions {
+    /// Path to apps.yaml
+    pub apps_path: PathBuf,
+    /// Path to the fdroid "repo" directory
+    pub repo_dir: PathBuf,
+    /// Don't run the fdroid command
+    pub skip_fdroid: bool,
+    /// What generates the repo index
+    pub index_backend: IndexBackend,
+    /// Report what a sync would change without touching any files
+    pub dry_run: bool,
+    /// Number of apps to process concurrently
+    pub jobs: usize,
+    /// Directory of the templates listed in its documents.yaml, rendered after a sync
+    pub templates_dir: PathBuf,
+    /// Where to generate the static site of the repo, if anywhere
+    pub site_dir: Option<PathBuf>,
+}
+
+impl SyncOptions {
+    /// Options to sync the apps of `apps_path` into `repo_dir` with fdroid,
+    /// 4 apps at a time, rendering the templates of the templates directory
+    /// next to apps.yaml
+    pub fn new(apps_path: impl Into<PathBuf>, repo_dir: impl Into<PathBuf>) -> Self {
+        let apps_path = apps_path.into();
+        let templates_dir = apps_path.parent().unwrap_or(Path::new("")).join("templates");
+        Self {
+            apps_path,
+            repo_dir: repo_dir.into(),
+            skip_fdroid: false,
+            index_backend: IndexBackend::default(),
+            dry_run: false,
+            jobs: 4,
+            templates_dir,
+            site_dir: None,
+        }
+    }
+}
+
+/// Callbacks at the steps of a sync, all of which do nothing by default
+pub trait Hook: Send {
+    /// Called with the parsed apps before any is processed; apps removed
+    /// here are skipped
+    fn apps_loaded(&mut self, _apps: &mut Vec<AppInfo>) {}
+
+    /// Called once the releases of an app have been processed
+    fn app_processed(&mut self, _app_key: &str, _outcome: &AppOutcome) {}
+
+    /// Called before the index is updated with `options`
+    fn before_fdroid(&mut self, _options: &UpdateOptions) {}
+
+    /// Called once the metadata of a package has been filled in
+    fn package_processed(&mut self, _package: &str, _outcome: &PackageOutcome) {}
+
+    /// Called with the index after the final `fdroid update`
+    fn index_updated(&mut self, _index: &RepoIndex) {}
+
+    /// Called at the end of the sync
+    fn finished(&mut self, _report: &SyncReport) {}
+}
+
+/// Result of a sync
+#[derive(Debug, Default, Serialize)]
+#[non_exhaustive]
+pub struct SyncReport {
+    pub dry_run: bool,
+    pub have_error: bool,
+    /// Error that stopped the sync
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Whether the repo changed in a way worth publishing
+    pub significant_changes: bool,
+    /// The first significant change found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub significant_change: Option<String>,
+    /// What happened to every app, in apps.yaml order
+    pub apps: Vec<AppSummary>,
+    /// Versions added to and removed from the index
+    pub index_changes: Vec<PackageChange>,
+    /// Warnings of the index updates that concern no particular app
+    pub warnings: Vec<FdroidWarning>,
+    /// What the sync would do, for dry runs
+    #[serde(skip)]
+    pub plan: Option<Plan>,
+}
+
+impl SyncReport {
+    /// Report of a sync stopped by `error`
+    pub fn failed(dry_run: bool, error: &anyhow::Error) -> Self {
+        Self {
+            dry_run,
+            have_error: true,
+            error: Some(format!("{:#}", error)),
+            ..Default::default()
+        }
+    }
+
+    /// 1 on errors, 2 if nothing changed significantly and 0 otherwise
+    pub fn exit_code(&self) -> i32 {
+        match &self.plan {
+            Some(plan) => plan.exit_code(),
+            None if self.have_error => 1,
+            None if !self.significant_changes => 2,
+            None => 0,
+        }
+    }
+}
+
+/// Downloads new releases, runs fdroid and fills in the metadata
+pub struct Pipeline<'a> {
+    options: SyncOptions,
+    apis: &'a GitHubApis,
+    credentials: &'a Credentials,
+    hooks: Vec<Box<dyn Hook + 'a>>,
+    backend: Box<dyn FdroidBackend + 'a>,
+    /// What happened to the apps so far, for the report of a failed sync
+    summaries: Vec<AppSummary>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(options: SyncOptions, apis: &'a GitHubApis, credentials: &'a Credentials) -> Self {
+        let backend = options.index_backend.backend();
+        Self {
+            options,
+            apis,
+            credentials,
+            hooks: Vec::new(),
+            backend,
+            summaries: Vec::new(),
+        }
+    }
+
+    /// Adds a hook, hooks are called in the order they were added
+    pub fn add_hook(&mut self, hook: impl Hook + 'a) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Replaces the backend chosen by [`SyncOptions::index_backend`], for
+    /// example with a [`FakeBackend`](crate::backend::FakeBackend)
+    pub fn set_backend(&mut self, backend: impl FdroidBackend + 'a) {
+        self.backend = Box::new(backend);
+    }
+
+    fn fdroid_dir(&self) -> Result<&Path> {
+        self.options.repo_dir.parent()
+            .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))
+    }
+
+    /// Writes the link to add the repo, with the fingerprint of its signing
+    /// key, and its QR code to the README
+    fn regenerate_repo_section(&self, readme_path: &Path, fdroid_index: &RepoIndex) -> Result<()> {
+        // The fingerprint needs the keystore, which READMEs without the section may not have
+        if !md::has_repo_section(readme_path)? {
+            return Ok(());
+        }
+
+        let address = fdroid_index.repo.get("address")
+            .and_then(|a| a.as_str())
+            .ok_or_else(|| anyhow::anyhow!("The index has no repo address"))?;
+        let fingerprint = index::repo_fingerprint(self.fdroid_dir()?)?;
+
+        md::regenerate_repo_section(readme_path, address, &fingerprint)
+    }
+
+    /// Updates the index with the backend. A failure is logged and counted
+    /// as an error, so the rest of the sync still runs.
+    fn update_index(&mut self, title: &str, options: UpdateOptions, have_error: &mut bool) -> Vec<FdroidWarning> {
+        for hook in &mut self.hooks {
+            hook.before_fdroid(&options);
+        }
+
+        output::group(title);
+
+        let result = self.fdroid_dir()
+            .map(Path::to_path_buf)
+            .and_then(|fdroid_dir| self.backend.update(&fdroid_dir, &options));
+
+        output::endgroup();
+
+        match result {
+            Ok(warnings) => warnings,
+            Err(e) => {
+                error!("Updating the index: {:#}", e);
+                *have_error = true;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Runs the sync in the current [`output::Output::scope`], or in one
+    /// printing to stderr when there is none. Either way the tokens of the
+    /// credentials are redacted from the output.
+    pub async fn run(&mut self) -> Result<SyncReport> {
+        let output = output::current();
+        for host in self.credentials.hosts() {
+            if let Some(token) = self.credentials.token_for_host(&host) {
+                output.secrets().register(&token);
+            }
+        }
+
+        let report = output.scope(self.sync()).await?;
+
+        for hook in &mut self.hooks {
+            hook.finished(&report);
+        }
+
+        Ok(report)
+    }
+
+    /// Report of a sync that [`run`](Self::run) stopped with `error`, with
+    /// what happened to the apps processed before
+    pub fn failed_report(&mut self, error: &anyhow::Error) -> SyncReport {
+        let mut report = SyncReport::failed(self.options.dry_run, error);
+        report.apps = std::mem::take(&mut self.summaries);
+        report
+    }
+
+    async fn sync(&mut self) -> Result<SyncReport> {
+        self.summaries.clear();
+
+        let options = self.options.clone();
+        let repo_dir = options.repo_dir.as_path();
+
+        output::group("Initializing");
+
+        let mut apps_list = apps::parse_app_file(&options.apps_path)
+            .context("Failed to parse app file")?;
+
+        for hook in &mut self.hooks {
+            hook.apps_loaded(&mut apps_list);
+        }
+
+        let github_apps = apps_list
+            .iter()
+            .filter(|app| app.feed.is_none() && app.api_url.is_empty())
+            .filter(|app| apps::repo_info(&app.git).is_ok_and(|repo| repo.host == GITHUB_HOST))
+            .count();
+        if github_apps > 0 {
+            ratelimit::print_budget(&self.apis.github_com()?, github_apps).await;
+        }
+
+        let mut have_error = false;
+
+        let fdroid_index_path = repo_dir.join("index-v1.json");
+
+        let published_path = self.fdroid_dir()?.join("published.json");
+
+        let mut published = PublishedFiles::load(&published_path)
+            .context("Failed to read published files record")?;
+
+        let initial_fdroid_index = RepoIndex::read_index(&fdroid_index_path)
+            .context("Failed to read F-Droid repo index")?;
+
+        if !options.dry_run {
+            fs::create_dir_all(repo_dir)
+                .context("Failed to create repo directory")?;
+        }
+
+        output::endgroup();
+
+        // Map of apk_name -> AppInfo
+        let mut apk_info_map: HashMap<String, AppInfo> = HashMap::new();
+
+        let sync_ctx = sync::SyncContext {
+            apis: self.apis,
+            credentials: self.credentials,
+            repo_dir,
+            published: &published,
+            dry_run: options.dry_run,
+        };
+
+        // Apps are processed concurrently, but their output and results are
+        // handled in apps.yaml order
+        let app_keys: Vec<String> = apps_list.iter().map(|app| app.key_name.clone()).collect();
+
+        let mut app_results = futures::stream::iter(apps_list)
+            .map(|app| output::capture(sync::process_app(&sync_ctx, app)))
+            .buffered(options.jobs.max(1));
+
+        let mut published_updates = Vec::new();
+        let mut old_nightly_apks = Vec::new();
+        let mut plan = Plan::default();
+        let mut app_keys_iter = app_keys.iter();
+
+        while let Some((result, captured)) = app_results.next().await {
+            captured.write();
+
+            let app_key = app_keys_iter.next();
+            let outcome = match result {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    if let Some(app_key) = app_key {
+                        self.summaries.push(AppSummary {
+                            key: app_key.clone(),
+                            error: Some(format!("{:#}", e)),
+                            ..Default::default()
+                        });
+                    }
+                    return Err(e);
+                }
+            };
+            if let Some(app_key) = app_key {
+                for hook in &mut self.hooks {
+                    hook.app_processed(app_key, &outcome);
+                }
+
+                self.summaries.push(AppSummary {
+                    key: app_key.clone(),
+                    releases: outcome.releases.clone(),
+                    error: outcome.error.clone(),
+                    packages: Vec::new(),
+                    warnings: Vec::new(),
+                });
+            }
+
+            have_error |= outcome.have_error;
+            apk_info_map.extend(outcome.apk_infos);
+            published_updates.extend(outcome.published);
+            plan.downloads.extend(outcome.planned_downloads);
+            plan.replacements.extend(outcome.planned_replacements);
+            old_nightly_apks.extend(outcome.planned_removals);
+        }
+
+        drop(app_results);
+
+        for (apk_name, file) in published_updates {
+            published.record(&apk_name, file);
+        }
+
+        let mut warnings = Vec::new();
+        if !options.skip_fdroid && !options.dry_run {
+            warnings = self.update_index(
+                "F-Droid: Creating metadata stubs",
+                UpdateOptions { create_metadata: true, delete_unknown: true, final_update: false },
+                &mut have_error,
+            );
+        }
+
+        output::print("Filling in metadata");
+
+        let fdroid_index = RepoIndex::read_index(&fdroid_index_path)
+            .context("Failed to read F-Droid repo index after update")?;
+
+        let metadata_dir = metadata::metadata_dir(repo_dir)?;
+
+        let metadata_ctx = metadata::MetadataContext {
+            metadata_dir: &metadata_dir,
+            repo_dir,
+            fdroid_index: &fdroid_index,
+            apk_info_map: &apk_info_map,
+            credentials: self.credentials,
+            dry_run: options.dry_run,
+        };
+
+        // Packages are handled in the order of their apps in apps.yaml
+        let mut metadata_files = {
+            let metadata_dir = metadata_dir.clone();
+            output::spawn_blocking(move || metadata::metadata_files(&metadata_dir)).await
+        };
+        metadata::sort_by_package_order(&mut metadata_files, &apps::package_order(&app_keys, &fdroid_index, &apk_info_map));
+
+        let mut package_results = futures::stream::iter(&metadata_files)
+            .map(|path| output::capture(metadata::process_package(&metadata_ctx, path)))
+            .buffered(options.jobs.max(1));
+
+        let mut to_remove_paths: Vec<PathBuf> = Vec::new();
+        let mut metadata_files_iter = metadata_files.iter();
+
+        while let Some((result, captured)) = package_results.next().await {
+            captured.write();
+
+            let outcome = result?;
+            if let Some(package) = metadata_files_iter.next().and_then(|p| p.file_stem()) {
+                for hook in &mut self.hooks {
+                    hook.package_processed(&package.to_string_lossy(), &outcome);
+                }
+            }
+
+            let summary = outcome.app_key.as_ref()
+                .and_then(|key| self.summaries.iter_mut().find(|s| &s.key == key));
+            if let (Some(summary), Some(package)) = (summary, package_summary(&outcome)) {
+                summary.packages.push(package);
+            }
+
+            to_remove_paths.extend(outcome.screenshots_dir);
+            plan.metadata.extend(outcome.changes);
+            plan.screenshots.extend(outcome.screenshots);
+        }
+
+        drop(package_results);
+
+        if options.dry_run {
+            plan.have_error = have_error;
+            plan.removals = unlisted_apks(repo_dir, &initial_fdroid_index, &apk_info_map);
+            plan.removals.extend(old_nightly_apks);
+            plan.removals.sort();
+            plan.print();
+
+            return Ok(SyncReport {
+                dry_run: true,
+                have_error,
+                significant_changes: !plan.is_empty(),
+                apps: std::mem::take(&mut self.summaries),
+                plan: Some(plan),
+                ..Default::default()
+            });
+        }
+
+        if !options.skip_fdroid {
+            warnings.extend(self.update_index(
+                "F-Droid: Reading updated metadata",
+                UpdateOptions { create_metadata: false, delete_unknown: true, final_update: true },
+                &mut have_error,
+            ));
+        }
+
+        output::group("Assessing changes");
+
+        let fdroid_index = RepoIndex::read_index(&fdroid_index_path)
+            .context("Failed to read F-Droid repo index after final update")?;
+
+        for hook in &mut self.hooks {
+            hook.index_updated(&fdroid_index);
+        }
+
+        published.retain_existing(repo_dir);
+        if let Err(e) = published.save(&published_path) {
+            error!("Error writing {:?}: {:#}", published_path, e);
+            have_error = true;
+        }
+
+        // Remove marked paths
+        for rm_path in to_remove_paths {
+            let _ = fs::remove_dir_all(&rm_path);
+        }
+
+        // Generate README
+        let readme_path = md::readme_path(repo_dir)?;
+
+        let package_order = apps::package_order(&app_keys, &fdroid_index, &apk_info_map);
+        if let Err(e) = md::regenerate_readme(&readme_path, &fdroid_index, &package_order) {
+            error!("Error generating {:?}: {}", readme_path, e);
+            have_error = true;
+        }
+        if let Err(e) = self.regenerate_repo_section(&readme_path, &fdroid_index) {
+            error!("Error generating the add repo section of {:?}: {:#}", readme_path, e);
+            have_error = true;
+        }
+
+        let index_changes = release_notes::index_changes(&initial_fdroid_index, &fdroid_index, &apk_info_map, &package_order);
+
+        if let Err(e) = atom::update_feeds(repo_dir, &metadata_dir, &initial_fdroid_index, &fdroid_index) {
+            error!("Error updating the feeds of new versions: {:#}", e);
+        }
+
+        let templates_dir = &self.options.templates_dir;
+        let templates_root = templates_dir.parent().unwrap_or(Path::new(""));
+        let repo_path = repo_dir.strip_prefix(templates_root).unwrap_or(repo_dir);
+        let context = TemplateContext::new(
+            &fdroid_index,
+            &repo_path.to_string_lossy(),
+            &metadata_dir,
+            &apk_info_map,
+            &package_order,
+            index_changes.clone(),
+            release_notes::today(),
+        );
+        if let Err(e) = templates::render_documents(templates_dir, &context) {
+            error!("Error generating documents from {:?}: {:#}", templates_dir, e);
+        }
+        if let Some(site_dir) = &self.options.site_dir {
+            let fingerprint = index::repo_fingerprint(self.fdroid_dir()?)
+                .map_err(|e| error!("Reading the repo fingerprint for the site: {:#}", e))
+                .ok();
+            if let Err(e) = site::generate(site_dir, templates_dir, &context, fingerprint.as_deref()) {
+                error!("Error generating the site in {:?}: {:#}", site_dir, e);
+                have_error = true;
+            }
+        }
+
+        let warnings = attach_warnings(warnings, &mut self.summaries, &[&initial_fdroid_index, &fdroid_index], &apk_info_map);
+
+        let (change_path, mut have_significant_changes) = apps::has_significant_changes(&initial_fdroid_index, &fdroid_index);
+        let mut significant_change = None;
+
+        if have_significant_changes {
+            info!("The index {:?} had a significant change at JSON path {:?}", fdroid_index_path, change_path);
+            significant_change = Some(format!("index changed at {}", change_path));
+        } else {
+            info!("The index files didn't change significantly");
+
+            match git::get_changed_file_names(repo_dir) {
+                Ok(changed_files) => {
+                    for fname in changed_files {
+                        if !fname.contains("index") {
+                            have_significant_changes = true;
+                            info!("File {:?} is a significant change", fname);
+                            significant_change.get_or_insert(format!("{} changed", fname));
+                        }
+                    }
+
+                    if !have_significant_changes {
+                        info!("It doesn't look like there were any relevant changes");
+                    }
+                }
+                Err(e) => {
+                    error!("Getting changed files: {}", e);
+                }
+            }
+        }
+
+        output::endgroup();
+
+        Ok(SyncReport {
+            dry_run: false,
+            have_error,
+            error: None,
+            significant_changes: have_significant_changes,
+            significant_change,
+            apps: std::mem::take(&mut self.summaries),
+            index_changes,
+            warnings,
+            plan: None,
+        })
+    }
+}
+
+/// Adds the warnings of the index updates to the summary of the app whose APK
+/// or package they name, returning the other ones
+fn attach_warnings(
+    warnings: Vec<FdroidWarning>,
+    summaries: &mut [AppSummary],
+    indexes: &[&RepoIndex],
+    apk_info_map: &HashMap<String, AppInfo>,
+) -> Vec<FdroidWarning> {
+    let package_app = |package: &str| -> Option<&String> {
+        indexes.iter()
+            .filter_map(|index| index.packages.get(package))
+            .flatten()
+            .find_map(|p| apk_info_map.get(&p.apk_name))
+            .map(|app| &app.key_name)
+    };
+
+    let mut unattached = Vec::new();
+    for warning in warnings {
+        let app_key = warning.apk_name.as_ref()
+            .and_then(|apk_name| apk_info_map.get(apk_name).map(|app| &app.key_name))
+            .or_else(|| warning.package.as_deref().and_then(package_app))
+            .or_else(|| {
+                // fdroidserver names packages in the message
+                indexes.iter()
+                    .flat_map(|index| index.packages.keys())
+                    .filter(|package| warning.message.contains(package.as_str()))
+                    .find_map(|package| package_app(package))
+            });
+
+        match app_key.and_then(|key| summaries.iter_mut().find(|s| &s.key == key)) {
+            Some(summary) => summary.warnings.push(warning),
+            None => unattached.push(warning),
+        }
+    }
+
+    unattached
+}
+
+/// The metadata changes of a package for the summary, if there are any
+fn package_summary(outcome: &PackageOutcome) -> Option<PackageSummary> {
+    let package = outcome.changes.as_ref().map(|c| c.package.clone())
+        .or_else(|| outcome.screenshots.as_ref().map(|s| s.package.clone()))?;
+
+    Some(PackageSummary {
+        package,
+        fields: outcome.changes.as_ref().map(|c| c.fields.clone()).unwrap_or_default(),
+        changelog: outcome.changes.as_ref().and_then(|c| c.changelog.clone()),
+        screenshots: outcome.screenshots.clone(),
+    })
+}
+
+/// APKs in the repo directory that belong to no app in apps.yaml, which
+/// `fdroid update --delete-unknown` would remove
+fn unlisted_apks(repo_dir: &Path, index: &RepoIndex, apk_info_map: &HashMap<String, AppInfo>) -> Vec<String> {
+    let package_of = |apk_name: &str| {
+        index.packages.iter()
+            .find(|(_, packages)| packages.iter().any(|p| p.apk_name == apk_name))
+            .map(|(package_name, _)| package_name.clone())
+    };
+
+    let listed_packages: Vec<String> = apk_info_map.keys()
+        .filter_map(|apk_name| package_of(apk_name))
+        .collect();
+
+    let mut apks: Vec<String> = fs::read_dir(repo_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".apk") && !apk_info_map.contains_key(name))
+        .filter(|name| package_of(name).is_none_or(|p| !listed_packages.contains(&p)))
+        .collect();
+    apks.sort();
+    apks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apps::PackageInfo;
+    use crate::backend::FakeBackend;
+    use crate::sync::ReleaseStatus;
+    use crate::testserver::{Response, Server};
+    use std::time::Duration;
+
+    const APK: &[u8] = b"stands in for an APK";
+
+    /// Records the apps and packages the hooks are called for
+    #[derive(Default)]
+    struct Calls(Vec<String>);
+
+    impl Hook for &mut Calls {
+        fn app_processed(&mut self, app_key: &str, _outcome: &AppOutcome) {
+            self.0.push(format!("app {}", app_key));
+        }
+
+        fn before_fdroid(&mut self, options: &UpdateOptions) {
+            self.0.push(format!("fdroid final={}", options.final_update));
+        }
+
+        fn index_updated(&mut self, index: &RepoIndex) {
+            self.0.push(format!("index {} packages", index.packages.len()));
+        }
+
+        fn finished(&mut self, report: &SyncReport) {
+            self.0.push(format!("finished with error={}", report.have_error));
+        }
+    }
+
+    fn index(packages: Vec<PackageInfo>) -> RepoIndex {
+        RepoIndex {
+            repo: serde_json::json!({ "timestamp": packages.len() }),
+            requests: serde_json::json!({}),
+            apps: Vec::new(),
+            packages: packages.into_iter().map(|p| (p.package_name.clone(), vec![p])).collect(),
+        }
+    }
+
+    fn package(apk_name: &str) -> PackageInfo {
+        PackageInfo {
+            added: 0,
+            apk_name: apk_name.to_string(),
+            hash: checksum_of_apk(),
+            hash_type: "sha256".to_string(),
+            min_sdk_version: 21,
+            nativecode: Vec::new(),
+            package_name: "org.example.app".to_string(),
+            sig: String::new(),
+            signer: String::new(),
+            size: APK.len() as i64,
+            target_sdk_version: 34,
+            version_code: 1,
+            version_name: "1.0".to_string(),
+        }
+    }
+
+    fn checksum_of_apk() -> String {
+        crate::checksum::sha256_hex(APK)
+    }
+
+    /// Serves the feed and APK of an app
+    async fn feed_server() -> Server {
+        Server::start(|request| match request.path.as_str() {
+            "/app.json" => Response::json(serde_json::json!({ "version": "1.0" })),
+            "/app.apk" => Response::new(200, APK),
+            _ => Response::new(404, "Not Found"),
+        }).await
+    }
+
+    /// An apps.yaml listing the app of `server` and an empty repo, returning
+    /// their paths
+    fn repo_with_feed_app(dir: &Path, server: &Server) -> (PathBuf, PathBuf) {
+        let apps_path = dir.join("apps.yaml");
+        let repo_dir = dir.join("fdroid").join("repo");
+        fs::create_dir_all(&repo_dir).unwrap();
+        fs::write(&apps_path, format!(
+            "app:\n  git: https://example.org/team/app\n  feed:\n    url: {url}/app.json\n    apk_url_template: {url}/app.apk\n",
+            url = server.url,
+        )).unwrap();
+        fs::write(repo_dir.join("index-v1.json"), serde_json::to_string(&index(Vec::new())).unwrap()).unwrap();
+        fs::write(dir.join("README.md"), "# Apps\n<!-- This table is auto-generated. Do not edit --><!-- end apps table -->\n").unwrap();
+        (apps_path, repo_dir)
+    }
+
+    #[tokio::test]
+    async fn sync_runs_with_a_fake_backend() {
+        let server = feed_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let (apps_path, repo_dir) = repo_with_feed_app(dir.path(), &server);
+
+        let credentials = Credentials::default();
+        let apis = GitHubApis::new(credentials.clone(), HashMap::new(), Duration::from_secs(60));
+        let mut backend = FakeBackend { index: Some(index(vec![package("app_1.0.apk")])), ..Default::default() };
+        let mut calls = Calls::default();
+
+        let report = {
+            let mut pipeline = Pipeline::new(SyncOptions::new(&apps_path, &repo_dir), &apis, &credentials);
+            pipeline.set_backend(&mut backend);
+            pipeline.add_hook(&mut calls);
+            pipeline.run().await.unwrap()
+        };
+
+        assert_eq!(fs::read(repo_dir.join("app_1.0.apk")).unwrap(), APK);
+        assert!(
+            matches!(&report.apps[0].releases[0].status, ReleaseStatus::Downloaded { apk_name, .. } if apk_name == "app_1.0.apk"),
+            "{:?}", report.apps
+        );
+        assert_eq!(
+            backend.updates.iter().map(|u| u.final_update).collect::<Vec<_>>(),
+            [false, true]
+        );
+        assert_eq!(report.index_changes[0].to_string(), "app 1.0 (1)");
+        assert!(report.significant_changes);
+        assert_eq!(calls.0, [
+            "app app",
+            "fdroid final=false",
+            "fdroid final=true",
+            "index 1 packages",
+            "finished with error=false",
+        ]);
+    }
+
+    /// Breaks the index, as a crashed fdroid could
+    struct BreakingBackend;
+
+    impl FdroidBackend for BreakingBackend {
+        fn update(&mut self, fdroid_dir: &Path, _options: &UpdateOptions) -> Result<Vec<FdroidWarning>> {
+            fs::write(fdroid_dir.join("repo").join("index-v1.json"), "{")?;
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_sync_reports_the_apps_processed_before() {
+        let server = feed_server().await;
+        let dir = tempfile::tempdir().unwrap();
+        let (apps_path, repo_dir) = repo_with_feed_app(dir.path(), &server);
+
+        let credentials = Credentials::default();
+        let apis = GitHubApis::new(credentials.clone(), HashMap::new(), Duration::from_secs(60));
+        let mut pipeline = Pipeline::new(SyncOptions::new(&apps_path, &repo_dir), &apis, &credentials);
+        pipeline.set_backend(BreakingBackend);
+
+        let error = pipeline.run().await.unwrap_err();
+        let report = pipeline.failed_report(&error);
+
+        assert!(report.have_error);
+        assert!(report.error.unwrap().contains("Failed to read F-Droid repo index after update"));
+        assert_eq!(report.apps.len(), 1);
+        assert!(
+            matches!(&report.apps[0].releases[0].status, ReleaseStatus::Downloaded { apk_name, .. } if apk_name == "app_1.0.apk"),
+            "{:?}", report.apps
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::output;
+
+/// An APK a sync would download
+#[derive(Debug, Clone)]
+pub struct PlannedDownload {
+    pub apk_name: String,
+    pub source: String,
+}
+
+/// A metadata field a sync would change
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+    pub key: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// Changes a sync would make to the metadata of one package
+#[derive(Debug, Clone, Default)]
+pub struct MetadataChange {
+    pub package: String,
+    pub fields: Vec<FieldChange>,
+    /// Changelog file that would be written
+    pub changelog: Option<String>,
+}
+
+/// Screenshots a sync would add, replace or remove for one package
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScreenshotChange {
+    #[serde(skip)]
+    pub package: String,
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ScreenshotChange {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Everything a sync would change, collected by a dry run
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub downloads: Vec<PlannedDownload>,
+    /// Published APKs whose replaced release asset would be downloaded again
+    pub replacements: Vec<PlannedDownload>,
+    /// APKs that no app in apps.yaml produces any more
+    pub removals: Vec<String>,
+    pub metadata: Vec<MetadataChange>,
+    pub screenshots: Vec<ScreenshotChange>,
+    pub have_error: bool,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.downloads.is_empty()
+            && self.replacements.is_empty()
+            && self.removals.is_empty()
+            && self.metadata.is_empty()
+            && self.screenshots.is_empty()
+    }
+
+    /// The exit code the sync would have
+    pub fn exit_code(&self) -> i32 {
+        if self.have_error {
+            1
+        } else if self.is_empty() {
+            2
+        } else {
+            0
+        }
+    }
+
+    pub fn print(&self) {
+        output::group("Plan");
+
+        if !self.downloads.is_empty() {
+            output::print(format!("Download {} APKs:", self.downloads.len()));
+            for download in &self.downloads {
+                output::print(format!("  + {} from {}", download.apk_name, download.source));
+            }
+        }
+
+        if !self.replacements.is_empty() {
+            output::print(format!("Replace {} APKs:", self.replacements.len()));
+            for download in &self.replacements {
+                output::print(format!("  ~ {} from {}", download.apk_name, download.source));
+            }
+        }
+
+        if !self.removals.is_empty() {
+            output::print(format!("Remove {} APKs:", self.removals.len()));
+            for apk_name in &self.removals {
+                output::print(format!("  - {}", apk_name));
+            }
+        }
+
+        for change in &self.metadata {
+            output::print(format!("Metadata of {}:", change.package));
+            for field in &change.fields {
+                print_field_change(field);
+            }
+            if let Some(changelog) = &change.changelog {
+                output::print(format!("  write changelog {}", changelog));
+            }
+        }
+
+        for change in &self.screenshots {
+            output::print(format!("Screenshots of {}:", change.package));
+            for name in &change.added {
+                output::print(format!("  + {}", name));
+            }
+            for name in &change.changed {
+                output::print(format!("  ~ {}", name));
+            }
+            for name in &change.removed {
+                output::print(format!("  - {}", name));
+            }
+        }
+
+        if self.is_empty() {
+            output::print("Nothing would change");
+        }
+
+        output::print(format!("Expected exit code: {}", self.exit_code()));
+
+        output::endgroup();
+    }
+}
+
+fn to_text(value: &Option<Value>) -> String {
+    match value {
+        None => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => serde_yaml::to_string(v).unwrap_or_default().trim_end().to_string(),
+    }
+}
+
+fn print_field_change(field: &FieldChange) {
+    let old = to_text(&field.old);
+    let new = to_text(&field.new);
+
+    if !old.contains('\n') && !new.contains('\n') {
+        output::print(format!("  {}: {:?} -> {:?}", field.key, old, new));
+        return;
+    }
+
+    output::print(format!("  {}:", field.key));
+    for line in diff::lines(old.trim_end_matches('\n'), new.trim_end_matches('\n')) {
+        match line {
+            diff::Result::Left(l) => output::print(format!("    -{}", l)),
+            diff::Result::Both(l, _) => output::print(format!("     {}", l)),
+            diff::Result::Right(r) => output::print(format!("    +{}", r)),
+        }
+    }
+}
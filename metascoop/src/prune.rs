@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use log::{error, info};
+use std::fs;
+use std::path::Path;
+
+use crate::apps::{self, AppInfo, RepoIndex};
+use crate::published::PublishedFiles;
+
+/// Files F-Droid may keep next to an APK
+const SIDECAR_EXTENSIONS: &[&str] = &["asc", "idsig", "sig"];
+
+/// Removes all but the newest APKs of every package from the repo
+/// directory, and records them as pruned in the published files record so
+/// syncs don't download them again. Packages keep the `retention` of their
+/// app in `apps`, or `keep` versions. A dry run only lists the APKs it would
+/// remove.
+pub fn run(repo_dir: &Path, apps: &[AppInfo], keep: usize, dry_run: bool) -> Result<()> {
+    if keep == 0 {
+        anyhow::bail!("--keep must be at least 1");
+    }
+    if let Some(app) = apps.iter().find(|app| app.retention == Some(0)) {
+        anyhow::bail!("{}: retention of {} must be at least 1", app.source, app.key_name);
+    }
+
+    let index = RepoIndex::read_index(&repo_dir.join("index-v1.json"))
+        .context("Failed to read F-Droid repo index")?;
+
+    let mut package_names: Vec<&String> = index.packages.keys().collect();
+    package_names.sort();
+
+    let mut removed = Vec::new();
+
+    for package_name in package_names {
+        let mut packages = index.packages[package_name].clone();
+        packages.sort_by(|a, b| b.version_code.cmp(&a.version_code).then_with(|| b.version_name.cmp(&a.version_name)));
+
+        let keep = packages.iter()
+            .find_map(|p| app_of(apps, &p.apk_name))
+            .and_then(|app| app.retention)
+            .unwrap_or(keep);
+
+        for package in packages.iter().skip(keep) {
+            let apk_path = repo_dir.join(&package.apk_name);
+            if !apk_path.exists() {
+                continue;
+            }
+
+            if dry_run {
+                info!("Would remove {} {} ({:?})", package_name, package.version_name, package.apk_name);
+                removed.push(package.apk_name.clone());
+                continue;
+            }
+
+            info!("Removing {} {} ({:?})", package_name, package.version_name, package.apk_name);
+
+            if let Err(e) = fs::remove_file(&apk_path) {
+                error!("Removing {:?}: {}", apk_path, e);
+                continue;
+            }
+            removed.push(package.apk_name.clone());
+
+            for extension in SIDECAR_EXTENSIONS {
+                let sidecar = repo_dir.join(format!("{}.{}", package.apk_name, extension));
+                let _ = fs::remove_file(sidecar);
+            }
+        }
+    }
+
+    if dry_run {
+        info!("Dry run: would remove {} APKs, no files were changed", removed.len());
+        return Ok(());
+    }
+
+    if removed.is_empty() {
+        info!("Nothing to remove, no package has more versions than it keeps");
+        return Ok(());
+    }
+
+    let published_path = repo_dir.parent()
+        .ok_or_else(|| anyhow::anyhow!("Repo dir has no parent"))?
+        .join("published.json");
+
+    let mut published = PublishedFiles::load(&published_path)
+        .context("Failed to read published files record")?;
+    for apk_name in &removed {
+        published.prune(apk_name);
+    }
+    published.retain_existing(repo_dir);
+    published.save(&published_path)?;
+
+    info!("Removed {} APKs, run the sync command to update the index", removed.len());
+
+    Ok(())
+}
+
+/// The app an APK was downloaded for, found by the app name its file name
+/// starts with
+fn app_of<'a>(apps: &'a [AppInfo], apk_name: &str) -> Option<&'a AppInfo> {
+    apps.iter()
+        .filter(|app| {
+            let prefix = apps::generate_release_filename(app.app_name(), "");
+            apk_name.starts_with(prefix.trim_end_matches(".apk"))
+        })
+        .max_by_key(|app| app.app_name().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo_with_versions(dir: &Path, codes: &[i32]) -> std::path::PathBuf {
+        let repo_dir = dir.join("repo");
+        fs::create_dir_all(&repo_dir).unwrap();
+
+        let packages: Vec<serde_json::Value> = codes.iter()
+            .map(|code| {
+                let apk_name = format!("a_{}.apk", code);
+                fs::write(repo_dir.join(&apk_name), "apk").unwrap();
+                serde_json::json!({
+                    "added": 0, "apkName": apk_name, "hash": "", "hashType": "sha256", "minSdkVersion": 21,
+                    "packageName": "com.a", "sig": "", "signer": "", "size": 3, "targetSdkVersion": 33,
+                    "versionCode": code, "versionName": code.to_string(),
+                })
+            })
+            .collect();
+        let index = serde_json::json!({"repo": {}, "requests": {}, "apps": [], "packages": {"com.a": packages}});
+        fs::write(repo_dir.join("index-v1.json"), index.to_string()).unwrap();
+        repo_dir
+    }
+
+    #[test]
+    fn prune_records_removed_apks() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_dir = repo_with_versions(dir.path(), &[1, 2, 3]);
+
+        run(&repo_dir, &[], 1, false).unwrap();
+
+        assert!(!repo_dir.join("a_1.apk").exists());
+        assert!(!repo_dir.join("a_2.apk").exists());
+        assert!(repo_dir.join("a_3.apk").exists());
+        let published = PublishedFiles::load(&dir.path().join("published.json")).unwrap();
+        assert!(published.is_pruned("a_1.apk"));
+        assert!(published.is_pruned("a_2.apk"));
+        assert!(!published.is_pruned("a_3.apk"));
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_dir = repo_with_versions(dir.path(), &[1, 2]);
+
+        run(&repo_dir, &[], 1, true).unwrap();
+
+        assert!(repo_dir.join("a_1.apk").exists());
+        assert!(!dir.path().join("published.json").exists());
+    }
+
+    #[test]
+    fn nothing_removed_leaves_the_record_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_dir = repo_with_versions(dir.path(), &[1, 2]);
+
+        run(&repo_dir, &[], 2, false).unwrap();
+
+        assert!(repo_dir.join("a_1.apk").exists());
+        assert!(!dir.path().join("published.json").exists());
+    }
+
+    #[test]
+    fn apps_keep_their_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_dir = repo_with_versions(dir.path(), &[1, 2, 3]);
+        let mut app: AppInfo = serde_yaml::from_str("git: https://github.com/a/a\nretention: 2").unwrap();
+        app.key_name = "a".to_string();
+
+        run(&repo_dir, &[app], 1, false).unwrap();
+
+        assert!(!repo_dir.join("a_1.apk").exists());
+        assert!(repo_dir.join("a_2.apk").exists());
+        assert!(repo_dir.join("a_3.apk").exists());
+    }
+}
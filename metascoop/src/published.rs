@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use octocrab::models::repos::Asset;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// What we know about an APK we have published
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct PublishedFile {
+    pub sha256: String,
+    /// Where the file was downloaded from
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source: String,
+    /// GitHub release asset the file was downloaded from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+}
+
+impl PublishedFile {
+    pub fn from_asset(sha256: String, asset: &Asset) -> Self {
+        Self {
+            sha256,
+            source: asset.browser_download_url.to_string(),
+            asset_id: Some(asset.id.0),
+            size: Some(asset.size),
+            updated_at: Some(asset.updated_at.to_rfc3339()),
+        }
+    }
+
+    pub fn has_asset_details(&self) -> bool {
+        self.asset_id.is_some()
+    }
+
+    /// Describes how `asset` differs from the asset this file was downloaded
+    /// from, or returns None if it is the same upload
+    pub fn asset_changes(&self, asset: &Asset) -> Option<String> {
+        let mut changes = Vec::new();
+
+        if let Some(id) = self.asset_id.filter(|&id| id != asset.id.0) {
+            changes.push(format!("id {} -> {}", id, asset.id.0));
+        }
+        if let Some(size) = self.size.filter(|&size| size != asset.size) {
+            changes.push(format!("size {} -> {}", size, asset.size));
+        }
+        let updated_at = asset.updated_at.to_rfc3339();
+        if let Some(previous) = self.updated_at.as_ref().filter(|&u| *u != updated_at) {
+            changes.push(format!("updated_at {} -> {}", previous, updated_at));
+        }
+
+        (!changes.is_empty()).then(|| changes.join(", "))
+    }
+
+    /// Describes how the APK a feed now lists for the version differs from
+    /// this file: its checksum if the feed has one, its link otherwise.
+    /// Returns None if it is the same.
+    pub fn feed_changes(&self, source: &str, sha256: &str) -> Option<String> {
+        if !sha256.is_empty() {
+            return (!self.sha256.is_empty() && !self.sha256.eq_ignore_ascii_case(sha256))
+                .then(|| format!("sha256 {} -> {}", self.sha256, sha256.to_lowercase()));
+        }
+
+        (!self.source.is_empty() && self.source != source)
+            .then(|| format!("source {} -> {}", self.source, source))
+    }
+}
+
+/// Record of every published APK, keyed by file name in the repo directory.
+/// It lets later runs notice when a file changes without a new version.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PublishedFiles {
+    pub files: BTreeMap<String, PublishedFile>,
+    /// APKs removed by `prune`, which syncs don't download again
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub pruned: BTreeSet<String>,
+}
+
+impl PublishedFiles {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read published files record: {}", path.display()))?;
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse JSON from {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("json.tmp");
+
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        fs::write(&tmp_path, content)
+            .with_context(|| format!("Failed to write to temp file: {}", tmp_path.display()))?;
+
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to rename {} to {}", tmp_path.display(), path.display()))?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, apk_name: &str) -> Option<&PublishedFile> {
+        self.files.get(apk_name)
+    }
+
+    pub fn record(&mut self, apk_name: &str, file: PublishedFile) {
+        self.files.insert(apk_name.to_string(), file);
+    }
+
+    /// Records that an APK was pruned, so it isn't published again
+    pub fn prune(&mut self, apk_name: &str) {
+        self.files.remove(apk_name);
+        self.pruned.insert(apk_name.to_string());
+    }
+
+    pub fn is_pruned(&self, apk_name: &str) -> bool {
+        self.pruned.contains(apk_name)
+    }
+
+    /// Forgets files that are no longer in the repo directory
+    pub fn retain_existing(&mut self, repo_dir: &Path) {
+        self.files.retain(|name, _| repo_dir.join(name).exists());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_changes_compare_the_checksum_then_the_link() {
+        let file = PublishedFile {
+            sha256: "ab".repeat(32),
+            source: "https://example.com/app-1.0.apk".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(file.feed_changes("https://cdn.example.com/app-1.0.apk", &"AB".repeat(32)), None);
+        assert_eq!(
+            file.feed_changes("https://example.com/app-1.0.apk", &"cd".repeat(32)),
+            Some(format!("sha256 {} -> {}", "ab".repeat(32), "cd".repeat(32)))
+        );
+        assert_eq!(file.feed_changes("https://example.com/app-1.0.apk", ""), None);
+        assert_eq!(
+            file.feed_changes("https://example.com/app-1.0-fixed.apk", ""),
+            Some("source https://example.com/app-1.0.apk -> https://example.com/app-1.0-fixed.apk".to_string())
+        );
+    }
+}
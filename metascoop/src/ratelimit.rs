@@ -0,0 +1,359 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::github::GitHubApi;
+
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait used for secondary rate limits when GitHub doesn't send `Retry-After`
+const SECONDARY_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
+
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(900);
+
+#[derive(Debug)]
+struct State {
+    remaining: Option<u64>,
+    /// Unix time at which the primary limit resets
+    reset: Option<u64>,
+    /// Set after hitting a secondary limit
+    blocked_until: Option<Instant>,
+    max_wait: Duration,
+}
+
+/// API budget as last reported by the `X-RateLimit-*` headers of one API
+/// URL. The clones of a [`GitHubApi`] share it, so concurrent requests to
+/// the same API pause together while other APIs keep going.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                remaining: None,
+                reset: None,
+                blocked_until: None,
+                max_wait: DEFAULT_MAX_WAIT,
+            })),
+        }
+    }
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl RateLimit {
+    /// Longest we are willing to sleep for a rate limit before giving up on a request
+    pub fn set_max_wait(&self, max_wait: Duration) {
+        self.state.lock().unwrap().max_wait = max_wait;
+    }
+
+    /// Records the budget reported by an API response
+    fn observe(&self, headers: &http::HeaderMap) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(remaining) = header_u64(headers, "x-ratelimit-remaining") {
+            state.remaining = Some(remaining);
+        }
+        if let Some(reset) = header_u64(headers, "x-ratelimit-reset") {
+            state.reset = Some(reset);
+        }
+    }
+
+    fn set_budget(&self, remaining: u64, reset: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.remaining = Some(remaining);
+        state.reset = Some(reset);
+    }
+
+    /// Pauses all requests for `delay`, as GitHub asks after a secondary limit
+    fn block_for(&self, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + delay;
+        if state.blocked_until.is_none_or(|b| b < until) {
+            state.blocked_until = Some(until);
+        }
+    }
+
+    /// Waits until the budget allows another request, failing if that would
+    /// take longer than the configured maximum wait
+    async fn wait_for_budget(&self) -> Result<()> {
+        let (delay, max_wait) = {
+            let state = self.state.lock().unwrap();
+            let now = Instant::now();
+
+            let secondary = state
+                .blocked_until
+                .filter(|&until| until > now)
+                .map(|until| until - now);
+
+            let primary = match (state.remaining, state.reset) {
+                (Some(0), Some(reset)) if reset > now_unix() => {
+                    Some(Duration::from_secs(reset - now_unix() + 1))
+                }
+                _ => None,
+            };
+
+            (secondary.max(primary), state.max_wait)
+        };
+
+        let delay = match delay {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        if delay > max_wait {
+            anyhow::bail!(
+                "GitHub API rate limit exhausted for another {} minutes (longer than the maximum wait of {} minutes)",
+                delay.as_secs().div_ceil(60),
+                max_wait.as_secs().div_ceil(60)
+            );
+        }
+
+        warn!("GitHub API rate limit reached, waiting {} seconds", delay.as_secs());
+        tokio::time::sleep(delay).await;
+
+        // Assume the budget was refreshed; the next response corrects this
+        let mut state = self.state.lock().unwrap();
+        if state.remaining == Some(0) {
+            state.remaining = None;
+        }
+
+        Ok(())
+    }
+}
+
+fn header_u64(headers: &http::HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// How long to wait before retrying a request that GitHub rejected, or None
+/// if the rejection isn't caused by a rate limit
+fn retry_delay(status: http::StatusCode, headers: &http::HeaderMap, message: &str) -> Option<Duration> {
+    if status != http::StatusCode::FORBIDDEN && status != http::StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    if let Some(seconds) = header_u64(headers, "retry-after") {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    if header_u64(headers, "x-ratelimit-remaining") == Some(0) {
+        let reset = header_u64(headers, "x-ratelimit-reset").unwrap_or_else(now_unix);
+        return Some(Duration::from_secs(reset.saturating_sub(now_unix()) + 1));
+    }
+
+    if message.to_lowercase().contains("secondary rate limit") {
+        return Some(SECONDARY_LIMIT_BACKOFF);
+    }
+
+    None
+}
+
+/// Fetches `route` from the GitHub API and deserializes the JSON response,
+/// waiting out primary and secondary rate limits
+pub async fn get_json<T: DeserializeOwned>(github: &GitHubApi, route: &str) -> Result<T> {
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        github.rate_limit.wait_for_budget().await?;
+
+        let response = github.client._get(route).await
+            .with_context(|| format!("Request to {} failed", route))?;
+
+        github.rate_limit.observe(response.headers());
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = github.client.body_to_string(response).await?;
+
+        if status.is_success() {
+            return serde_json::from_str(&body)
+                .with_context(|| format!("Failed to parse response from {}", route));
+        }
+
+        let message = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(str::to_string))
+            .unwrap_or(body);
+
+        match retry_delay(status, &headers, &message) {
+            Some(delay) if attempt < MAX_ATTEMPTS => {
+                warn!("GitHub rate limited {} ({}), retrying in {} seconds", route, message, delay.as_secs());
+                github.rate_limit.block_for(delay);
+            }
+            _ => anyhow::bail!("GitHub API returned HTTP {} for {}: {}", status, route, message),
+        }
+    }
+}
+
+/// Sends a request to the GitHub API outside of octocrab (e.g. an asset
+/// download), waiting out rate limits
+pub async fn send(github: &GitHubApi, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        github.rate_limit.wait_for_budget().await?;
+
+        let response = request
+            .try_clone()
+            .ok_or_else(|| anyhow::anyhow!("Request can't be retried"))?
+            .send()
+            .await?;
+
+        github.rate_limit.observe(response.headers());
+
+        match retry_delay(response.status(), response.headers(), "") {
+            Some(delay) if attempt < MAX_ATTEMPTS => {
+                warn!("GitHub rate limited {}, retrying in {} seconds", response.url(), delay.as_secs());
+                github.rate_limit.block_for(delay);
+            }
+            _ => return Ok(response),
+        }
+    }
+}
+
+/// Runs an octocrab request, retrying it when GitHub answers with a rate limit error
+pub async fn retry<T, F, Fut>(github: &GitHubApi, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = octocrab::Result<T>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        github.rate_limit.wait_for_budget().await?;
+
+        let error = match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+
+        let message = match &error {
+            octocrab::Error::GitHub { source, .. }
+                if (source.status_code == http::StatusCode::FORBIDDEN
+                    || source.status_code == http::StatusCode::TOO_MANY_REQUESTS)
+                    && source.message.to_lowercase().contains("rate limit") =>
+            {
+                source.message.clone()
+            }
+            _ => return Err(error.into()),
+        };
+
+        if attempt >= MAX_ATTEMPTS {
+            return Err(error.into());
+        }
+
+        if message.to_lowercase().contains("secondary") {
+            // Waits longer after every hit
+            let wait = SECONDARY_LIMIT_BACKOFF * attempt;
+            warn!("GitHub secondary rate limit hit ({}), retrying in {} seconds", message, wait.as_secs());
+            github.rate_limit.block_for(wait);
+        } else {
+            // octocrab doesn't expose the response headers, ask for the budget instead
+            refresh(github).await;
+        }
+    }
+}
+
+/// Updates the budget from GitHub's `/rate_limit` endpoint, which doesn't count against it
+async fn refresh(github: &GitHubApi) -> Option<octocrab::models::Rate> {
+    let limits = github.client.ratelimit().get().await.ok()?;
+    let core = limits.resources.core;
+
+    github.rate_limit.set_budget(core.remaining as u64, core.reset);
+
+    Some(core)
+}
+
+/// Prints the remaining GitHub API budget and warns when it probably won't
+/// cover the run
+pub async fn print_budget(github: &GitHubApi, app_count: usize) {
+    let core = match refresh(github).await {
+        Some(core) => core,
+        None => {
+            warn!("Couldn't read the GitHub API rate limit");
+            return;
+        }
+    };
+
+    let resets_in = core.reset.saturating_sub(now_unix()).div_ceil(60);
+
+    info!(
+        "GitHub API budget: {} of {} requests remaining, resets in {} minutes",
+        core.remaining, core.limit, resets_in
+    );
+
+    // Looking up the repo and listing its releases, plus a few downloads
+    let estimate = app_count * 4;
+
+    if core.limit <= 60 {
+        warn!("Not authenticated with GitHub, only {} requests per hour are allowed. Pass --personal-access-token or a GitHub App to raise the limit", core.limit);
+    }
+
+    if core.remaining < estimate {
+        warn!(
+            "The {} apps may need about {} requests but only {} remain; requests will wait up to the maximum wait for the budget to reset",
+            app_count, estimate, core.remaining
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testserver::{Response, Server};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn apis_have_their_own_budget() {
+        let reset = (now_unix() + 3600).to_string();
+        let limited = Server::start(move |_| {
+            Response::new(403, r#"{"message": "API rate limit exceeded"}"#)
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", &reset)
+        }).await;
+        let other = Server::start(|_| Response::json(serde_json::json!({ "ok": true }))).await;
+        let limited_api = GitHubApi::new(&limited.url, None).unwrap();
+        let other_api = GitHubApi::new(&other.url, None).unwrap();
+
+        let error = get_json::<serde_json::Value>(&limited_api, "/first").await.unwrap_err();
+        assert!(error.to_string().contains("rate limit exhausted"), "{:#}", error);
+
+        // Clones share the budget, so they don't ask again
+        assert!(get_json::<serde_json::Value>(&limited_api.clone(), "/second").await.is_err());
+        assert_eq!(limited.requests().len(), 1);
+
+        let value: serde_json::Value = get_json(&other_api, "/third").await.unwrap();
+        assert_eq!(value["ok"], true);
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_waited_out() {
+        let count = AtomicUsize::new(0);
+        let server = Server::start(move |_| match count.fetch_add(1, Ordering::Relaxed) {
+            0 => Response::new(429, "").header("Retry-After", "1"),
+            _ => Response::new(200, "fine"),
+        }).await;
+        let api = GitHubApi::new(&server.url, None).unwrap();
+
+        let start = Instant::now();
+        let response = send(&api, reqwest::Client::new().get(&server.url)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(server.requests().len(), 2);
+        assert!(start.elapsed() >= Duration::from_secs(1), "{:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::apps::{self, AppInfo, RepoIndex};
+
+/// Longest commit subject listing the changes themselves, longer ones are
+/// summarized and the changes listed in the body
+const MAX_SUBJECT_LENGTH: usize = 72;
+
+const CHANGELOG_TITLE: &str = "# Changelog";
+
+/// A version of a package in the index
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version {
+    pub code: i32,
+    pub name: String,
+}
+
+/// Versions of a package added to and removed from the index by a sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageChange {
+    pub package: String,
+    /// Key of the app in apps.yaml, or the package name when there is none
+    pub name: String,
+    pub added: Vec<Version>,
+    pub removed: Vec<Version>,
+}
+
+impl Display for PackageChange {
+    /// For example "trampoline 3.2.0 (3002000)" or "notality removed 1.0.1"
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+
+        let added: Vec<String> = self.added.iter()
+            .map(|v| format!("{} ({})", v.name, v.code))
+            .collect();
+        if !added.is_empty() {
+            write!(f, " {}", added.join(", "))?;
+        }
+
+        let removed: Vec<&str> = self.removed.iter().map(|v| v.name.as_str()).collect();
+        if !removed.is_empty() {
+            let separator = if added.is_empty() { " " } else { ", " };
+            write!(f, "{}removed {}", separator, removed.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compares the versions of every package in two indexes, listing changed
+/// packages in `package_order`, see [`apps::package_order`]
+pub fn index_changes(
+    old: &RepoIndex,
+    new: &RepoIndex,
+    apk_info_map: &HashMap<String, AppInfo>,
+    package_order: &[String],
+) -> Vec<PackageChange> {
+    let packages: BTreeSet<&String> = old.packages.keys().chain(new.packages.keys()).collect();
+
+    let mut changes: Vec<PackageChange> = packages.into_iter()
+        .filter_map(|package| {
+            let old_versions = versions(old, package);
+            let new_versions = versions(new, package);
+
+            let change = PackageChange {
+                package: package.clone(),
+                name: app_key(old, new, package, apk_info_map).unwrap_or_else(|| package.clone()),
+                added: newest_first(new_versions.difference(&old_versions)),
+                removed: newest_first(old_versions.difference(&new_versions)),
+            };
+            (!change.added.is_empty() || !change.removed.is_empty()).then_some(change)
+        })
+        .collect();
+
+    changes.sort_by_key(|c| apps::order_position(package_order, &c.package));
+    changes
+}
+
+fn versions(index: &RepoIndex, package: &str) -> BTreeSet<Version> {
+    index.packages.get(package)
+        .map(|pkgs| pkgs.iter()
+            .map(|p| Version { code: p.version_code, name: p.version_name.clone() })
+            .collect())
+        .unwrap_or_default()
+}
+
+fn newest_first<'a>(versions: impl Iterator<Item = &'a Version>) -> Vec<Version> {
+    let mut versions: Vec<Version> = versions.cloned().collect();
+    versions.reverse();
+    versions
+}
+
+/// Key of the app one of the APKs of `package` was downloaded for
+fn app_key(old: &RepoIndex, new: &RepoIndex, package: &str, apk_info_map: &HashMap<String, AppInfo>) -> Option<String> {
+    [new, old].into_iter()
+        .filter_map(|index| index.packages.get(package))
+        .flatten()
+        .find_map(|p| apk_info_map.get(&p.apk_name))
+        .map(|app| app.key_name.clone())
+}
+
+/// Commit message for a sync, whose subject lists the changes when they fit
+pub fn commit_message(changes: &[PackageChange]) -> String {
+    let items: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+
+    let subject = items.join("; ");
+    if items.is_empty() {
+        return "Update app metadata\n".to_string();
+    }
+    if subject.len() <= MAX_SUBJECT_LENGTH {
+        return format!("{}\n", subject);
+    }
+
+    let apps = if changes.len() == 1 { "app" } else { "apps" };
+    let mut message = format!("Update {} {}\n\n", changes.len(), apps);
+    for item in items {
+        message.push_str(&format!("- {}\n", item));
+    }
+    message
+}
+
+/// CHANGELOG.md section listing the changes of a sync made on `date`
+pub fn changelog_section(changes: &[PackageChange], date: &str) -> String {
+    let mut section = format!("## {}\n\n", date);
+    if changes.is_empty() {
+        section.push_str("- Updated app metadata\n");
+    }
+    for change in changes {
+        section.push_str(&format!("- {}\n", change));
+    }
+    section
+}
+
+/// Adds `section` to the top of the changelog at `path`, below its title,
+/// creating the file if needed
+pub fn prepend_changelog(path: &Path, section: &str) -> Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read changelog: {}", path.display())),
+    };
+
+    let rest = content.strip_prefix(CHANGELOG_TITLE).unwrap_or(&content).trim_start();
+    let mut new_content = format!("{}\n\n{}", CHANGELOG_TITLE, section);
+    if !rest.is_empty() {
+        new_content.push('\n');
+        new_content.push_str(rest);
+    }
+
+    fs::write(path, new_content)
+        .with_context(|| format!("Failed to write changelog: {}", path.display()))
+}
+
+/// Today's date in UTC, as YYYY-MM-DD
+pub fn today() -> String {
+    let (year, month, day) = civil_date(now_secs());
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// A time in seconds since the epoch as an RFC 3339 timestamp in UTC, e.g.
+/// "2025-06-03T08:30:00Z"
+pub fn rfc3339(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs);
+    let seconds_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Year, month and day of a time in seconds since the epoch
+fn civil_date(secs: u64) -> (i64, i64, i64) {
+    // Days since 1970-01-01 to a civil date, see
+    // <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
@@ -0,0 +1,247 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use tera::{Tera, Context as TeraContext, Value};
+use url::Url;
+
+use crate::md;
+use crate::templates::TemplateContext;
+
+/// Templates of the site, which files of the same name in the `site`
+/// directory of the templates directory replace
+const SITE_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", BASE_TMPL),
+    ("index.html", INDEX_TMPL),
+    ("app.html", APP_TMPL),
+    ("style.css", STYLE_CSS),
+];
+
+const BASE_TMPL: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{% block title %}{{ repo.name }}{% endblock title %}</title>
+<link rel="stylesheet" href="{{ root }}style.css">
+<link rel="alternate" type="application/atom+xml" title="New versions" href="{{ repo.address }}/feed.xml">
+</head>
+<body>
+<header><a href="{{ root }}index.html">{{ repo.name }}</a></header>
+<main>
+{% block content %}{% endblock content %}
+</main>
+<footer>Updated {{ repo.timestamp / 1000 | date(format="%Y-%m-%d") }}</footer>
+</body>
+</html>
+"#;
+
+const INDEX_TMPL: &str = r#"{% extends "base.html" %}
+{% block content %}
+<h1>{{ repo.name }}</h1>
+<p class="text">{{ repo.description }}</p>
+
+<section class="add-repo">
+<h2>Add the repo</h2>
+<p>Install <a href="https://f-droid.org/">F-Droid</a>, then open this link on your device or scan the QR code:</p>
+<p><a href="{{ url }}"><code>{{ url }}</code></a></p>
+{% if qr_code %}<img src="{{ qr_code }}" alt="F-Droid repo QR code" width="256" height="256">{% endif %}
+{% if fingerprint %}<p>Fingerprint of the signing key: <code>{{ fingerprint }}</code></p>{% endif %}
+</section>
+
+<h2>Apps</h2>
+<ul class="apps">
+{% for app in apps %}
+<li>
+<a href="apps/{{ app.package_name }}.html">
+{% if app.icon %}<img src="{{ repo.address }}/{{ app.icon }}" alt="" width="48" height="48">{% endif %}
+<strong>{{ app.name }}</strong></a>
+{% if app.suggested_version %}<span class="current-version">{{ app.suggested_version.name }}</span>{% endif %}
+<p>{{ app.summary }}</p>
+</li>
+{% endfor %}
+</ul>
+{% endblock content %}
+"#;
+
+const APP_TMPL: &str = r#"{% extends "base.html" %}
+{% block title %}{{ app.name }} - {{ repo.name }}{% endblock title %}
+{% block content %}
+<h1>{% if app.icon %}<img src="{{ repo.address }}/{{ app.icon }}" alt="" width="64" height="64"> {% endif %}{{ app.name }}</h1>
+<p><strong>{{ app.summary }}</strong></p>
+<p class="text">{{ app.description }}</p>
+
+<dl>
+<dt>Package</dt><dd><code>{{ app.package_name }}</code></dd>
+<dt>License</dt><dd>{{ app.license }}</dd>
+{% if app.source_code is http_url %}<dt>Source code</dt><dd><a href="{{ app.source_code }}">{{ app.source_code }}</a></dd>{% endif %}
+{% if app.web_site is http_url %}<dt>Website</dt><dd><a href="{{ app.web_site }}">{{ app.web_site }}</a></dd>{% endif %}
+{% if app.author_name %}<dt>Author</dt><dd>{{ app.author_name }}</dd>{% endif %}
+{% if app.categories %}<dt>Categories</dt><dd>{{ app.categories | join(sep=", ") }}</dd>{% endif %}
+</dl>
+
+{% for locale, screenshots in app.screenshots %}
+{% if loop.first %}<h2>Screenshots</h2><div class="screenshots">{% endif %}
+{% for screenshot in screenshots %}<a href="{{ repo.address }}/{{ screenshot }}"><img src="{{ repo.address }}/{{ screenshot }}" alt="Screenshot" loading="lazy"></a>{% endfor %}
+{% if loop.last %}</div>{% endif %}
+{% endfor %}
+
+<h2>Versions</h2>
+{% for version in app.versions %}
+<article class="version">
+<h3>{{ version.name }} <small>({{ version.code }})</small></h3>
+<p>
+<a href="{{ repo.address }}/{{ version.apk_name }}">Download APK</a> ({{ version.size | filesizeformat }}),
+added {{ version.added / 1000 | date(format="%Y-%m-%d") }}, Android API {{ version.min_sdk_version }}+{% if version.nativecode %}, {{ version.nativecode | join(sep=", ") }}{% endif %}
+</p>
+<p class="hash">SHA-256 <code>{{ version.sha256 }}</code></p>
+{% if version.changelog %}<div class="text">{{ version.changelog }}</div>{% endif %}
+</article>
+{% endfor %}
+{% endblock content %}
+"#;
+
+const STYLE_CSS: &str = r#"body { font-family: system-ui, sans-serif; max-width: 50rem; margin: 0 auto; padding: 1rem; line-height: 1.5; color: #222; }
+header { font-weight: bold; margin-bottom: 1rem; }
+header a { color: inherit; text-decoration: none; }
+footer { margin-top: 2rem; color: #666; font-size: 0.9rem; }
+code { word-break: break-all; }
+.text { white-space: pre-line; }
+.apps { list-style: none; padding: 0; }
+.apps li { padding: 0.5rem 0; border-bottom: 1px solid #ddd; }
+.apps img, h1 img { vertical-align: middle; }
+.apps p { margin: 0.25rem 0 0; }
+.current-version { color: #666; }
+.version { border-top: 1px solid #ddd; }
+.hash { font-size: 0.8rem; }
+.screenshots { display: flex; gap: 0.5rem; overflow-x: auto; }
+.screenshots img { height: 20rem; }
+dt { font-weight: bold; }
+"#;
+
+/// Writes a static site of the repo to `site_dir`: a landing page with the
+/// link to add the repo and its QR code, and a page per app. APKs, icons and
+/// screenshots are linked at the repo address. Templates in `site/` of
+/// `templates_dir` replace the built-in ones of the same name.
+pub fn generate(site_dir: &Path, templates_dir: &Path, context: &TemplateContext, fingerprint: Option<&str>) -> Result<()> {
+    let tera = load_templates(&templates_dir.join("site"))?;
+
+    let fingerprint = fingerprint.map(str::to_uppercase);
+    let url = match &fingerprint {
+        Some(fingerprint) => format!("{}?fingerprint={}", context.repo.address, fingerprint),
+        None => context.repo.address.clone(),
+    };
+
+    let apps_dir = site_dir.join("apps");
+    fs::create_dir_all(&apps_dir)
+        .with_context(|| format!("Failed to create {}", apps_dir.display()))?;
+
+    let qr_code = match md::write_qr_code(&site_dir.join("qrcode.svg"), &url) {
+        Ok(()) => Some("qrcode.svg"),
+        Err(e) => {
+            warn!("Not adding a QR code to the site: {:#}", e);
+            None
+        }
+    };
+
+    let mut tera_context = TeraContext::from_serialize(context)?;
+    tera_context.insert("url", &url);
+    tera_context.insert("fingerprint", &fingerprint);
+    tera_context.insert("qr_code", &qr_code);
+    tera_context.insert("root", "");
+
+    write_page(&tera, "index.html", &tera_context, &site_dir.join("index.html"))?;
+    write_page(&tera, "style.css", &tera_context, &site_dir.join("style.css"))?;
+    // Served as it is by GitHub Pages, without Jekyll
+    fs::write(site_dir.join(".nojekyll"), "")
+        .with_context(|| format!("Failed to write {}", site_dir.display()))?;
+
+    tera_context.insert("root", "../");
+    for app in &context.apps {
+        tera_context.insert("app", app);
+        let path = apps_dir.join(format!("{}.html", app.package_name));
+        write_page(&tera, "app.html", &tera_context, &path)?;
+    }
+
+    remove_stale_pages(&apps_dir, context)?;
+
+    info!("Generated the site in {:?} with {} app pages", site_dir, context.apps.len());
+    Ok(())
+}
+
+fn load_templates(overrides_dir: &Path) -> Result<Tera> {
+    let mut tera = Tera::default();
+    tera.register_tester("http_url", is_http_url);
+    tera.add_raw_templates(SITE_TEMPLATES.to_vec())?;
+
+    let mut overrides = Vec::new();
+    for (name, _) in SITE_TEMPLATES {
+        let path = overrides_dir.join(name);
+        if path.exists() {
+            overrides.push((path, Some(name.to_string())));
+        }
+    }
+    if !overrides.is_empty() {
+        tera.add_template_files(overrides)
+            .with_context(|| format!("Failed to load the site templates of {}", overrides_dir.display()))?;
+    }
+
+    Ok(tera)
+}
+
+/// Tera test for links taken from the metadata, which only allows http and
+/// https URLs so that a `javascript:` one can't run in the page
+fn is_http_url(value: Option<&Value>, _args: &[Value]) -> tera::Result<bool> {
+    Ok(value
+        .and_then(Value::as_str)
+        .and_then(|url| Url::parse(url).ok())
+        .is_some_and(|url| matches!(url.scheme(), "http" | "https")))
+}
+
+fn write_page(tera: &Tera, template: &str, context: &TeraContext, path: &Path) -> Result<()> {
+    let content = tera.render(template, context)
+        .with_context(|| format!("Failed to render {}", template))?;
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Removes the pages of apps no longer in the index
+fn remove_stale_pages(apps_dir: &Path, context: &TemplateContext) -> Result<()> {
+    for entry in fs::read_dir(apps_dir).with_context(|| format!("Failed to read {}", apps_dir.display()))? {
+        let path = entry?.path();
+        let Some(package_name) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_suffix(".html")) else {
+            continue;
+        };
+        if !context.apps.iter().any(|app| app.package_name == package_name) {
+            info!("Removing the page of {}, which is no longer in the index", package_name);
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_http_links_are_urls() {
+        let mut tera = load_templates(Path::new("")).unwrap();
+        let template = "{% if link is http_url %}<a href=\"{{ link }}\">{% endif %}";
+
+        for (link, expected) in [
+            ("https://example.org/app", "<a href=\"https://example.org/app\">"),
+            ("http://example.org", "<a href=\"http://example.org\">"),
+            ("javascript:alert(1)", ""),
+            ("JavaScript:alert(1)", ""),
+            ("data:text/html,<script>", ""),
+            ("example.org", ""),
+        ] {
+            let mut context = TeraContext::new();
+            context.insert("link", link);
+            assert_eq!(tera.render_str(template, &context).unwrap(), expected, "{}", link);
+        }
+        assert_eq!(tera.render_str(template, &TeraContext::new()).unwrap(), "");
+    }
+}
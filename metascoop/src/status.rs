@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use octocrab::models::repos::Release;
+use std::path::Path;
+
+use crate::apps::{self, AppInfo};
+use crate::credentials::Credentials;
+use crate::github::GitHubApis;
+use crate::{feed, output, ratelimit};
+
+/// Published and upstream version of one app
+struct AppStatus {
+    name: String,
+    published: Option<String>,
+    upstream: Result<Option<String>>,
+}
+
+impl AppStatus {
+    fn state(&self) -> String {
+        match (&self.upstream, &self.published) {
+            (Err(e), _) => format!("error: {:#}", e),
+            (Ok(None), _) => "no releases".to_string(),
+            (Ok(Some(_)), None) => "not published".to_string(),
+            (Ok(Some(upstream)), Some(published)) if upstream == published => "up to date".to_string(),
+            (Ok(Some(_)), Some(_)) => "behind".to_string(),
+        }
+    }
+}
+
+/// Prints the newest published and upstream version of every app
+pub async fn run(apps_path: &Path, repo_dir: &Path, apis: &GitHubApis, credentials: &Credentials, jobs: usize) -> Result<()> {
+    let apps_list = apps::parse_app_file(apps_path)
+        .context("Failed to parse app file")?;
+
+    let statuses: Vec<AppStatus> = futures::stream::iter(&apps_list)
+        .map(|app| app_status(app, repo_dir, apis, credentials))
+        .buffered(jobs.max(1))
+        .collect()
+        .await;
+
+    let rows: Vec<[String; 4]> = statuses
+        .iter()
+        .map(|s| [
+            s.name.clone(),
+            s.published.clone().unwrap_or_else(|| "-".to_string()),
+            s.upstream.as_ref().ok().cloned().flatten().unwrap_or_else(|| "-".to_string()),
+            s.state(),
+        ])
+        .collect();
+
+    let header = ["App", "Published", "Upstream", "Status"].map(str::to_string);
+    let widths: Vec<usize> = (0..3)
+        .map(|i| rows.iter().chain([&header]).map(|r| r[i].len()).max().unwrap_or(0))
+        .collect();
+
+    for row in [&header].into_iter().chain(&rows) {
+        output::print(format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0], row[1], row[2], row[3],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2]
+        ));
+    }
+
+    Ok(())
+}
+
+async fn app_status(app: &AppInfo, repo_dir: &Path, apis: &GitHubApis, credentials: &Credentials) -> AppStatus {
+    // Versions the upstream offers, newest first
+    let versions = upstream_versions(app, apis, credentials).await;
+
+    let published = versions.as_ref().ok().and_then(|versions| {
+        versions
+            .iter()
+            .find(|v| repo_dir.join(apps::generate_release_filename(app.app_name(), v)).exists())
+            .cloned()
+    });
+
+    AppStatus {
+        name: app.key_name.clone(),
+        published,
+        upstream: versions.map(|v| v.into_iter().next()),
+    }
+}
+
+async fn upstream_versions(app: &AppInfo, apis: &GitHubApis, credentials: &Credentials) -> Result<Vec<String>> {
+    if let Some(source) = &app.feed {
+        let releases = feed::fetch_releases(credentials, source).await?;
+
+        return Ok(releases
+            .into_iter()
+            .map(|r| r.version)
+            .filter(|v| !app.skip_versions.contains(v))
+            .collect());
+    }
+
+    let repo = apps::repo_info(&app.git)
+        .with_context(|| format!("Failed to get repo info from URL: {}", app.git))?;
+    let github = apis.for_repo(&repo, &app.api_url)?;
+
+    let route = format!("/repos/{}/{}/releases?per_page=100", repo.author, repo.name);
+    let releases: Vec<Release> = ratelimit::get_json(&github, &route).await?;
+
+    Ok(releases
+        .into_iter()
+        .filter(|r| !r.prerelease && !r.draft && !r.tag_name.is_empty())
+        .filter(|r| !app.skip_versions.contains(&r.tag_name))
+        .filter(|r| apps::find_apk_release(r).is_some())
+        .map(|r| r.tag_name)
+        .collect())
+}
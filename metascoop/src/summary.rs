@@ -0,0 +1,56 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::backend::FdroidWarning;
+use crate::pipeline::SyncReport;
+use crate::plan::{FieldChange, ScreenshotChange};
+use crate::sync::ReleaseOutcome;
+
+/// What a sync did for one app
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AppSummary {
+    /// Key of the app in apps.yaml
+    pub key: String,
+    pub releases: Vec<ReleaseOutcome>,
+    /// Error that stopped the releases of the app from being listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Metadata of the app's packages that changed
+    pub packages: Vec<PackageSummary>,
+    /// Warnings of the index updates about the app's APKs and packages
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<FdroidWarning>,
+}
+
+/// What a sync changed in the metadata of one package
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PackageSummary {
+    pub package: String,
+    pub fields: Vec<FieldChange>,
+    /// Changelog file that was written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changelog: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshots: Option<ScreenshotChange>,
+}
+
+#[derive(Serialize)]
+struct SummaryFile<'a> {
+    exit_code: i32,
+    #[serde(flatten)]
+    report: &'a SyncReport,
+}
+
+/// Writes `report` as JSON to `path`, for other tools to act on
+pub fn write(path: &Path, report: &SyncReport) -> Result<()> {
+    let summary = SummaryFile {
+        exit_code: report.exit_code(),
+        report,
+    };
+
+    let json = serde_json::to_string_pretty(&summary)?;
+    fs::write(path, json + "\n")
+        .with_context(|| format!("Failed to write summary: {}", path.display()))
+}
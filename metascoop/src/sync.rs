@@ -0,0 +1,858 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use octocrab::models::repos::{Asset, Release};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::apps::{self, AppInfo, ArtifactSource, FeedSource, Repo};
+use crate::credentials::Credentials;
+use crate::github::{GitHubApi, GitHubApis};
+use crate::plan::PlannedDownload;
+use crate::published::{PublishedFile, PublishedFiles};
+use crate::{apk, artifacts, checksum, download, feed, output, ratelimit};
+
+/// Shared, read-only state for processing apps concurrently
+pub struct SyncContext<'a> {
+    pub apis: &'a GitHubApis,
+    pub credentials: &'a Credentials,
+    pub repo_dir: &'a Path,
+    pub published: &'a PublishedFiles,
+    /// Only plan downloads instead of making them
+    pub dry_run: bool,
+}
+
+/// What happened to one release, build or feed entry of an app
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseOutcome {
+    pub version: String,
+    #[serde(flatten)]
+    pub status: ReleaseStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ReleaseStatus {
+    /// The APK was downloaded and published
+    Downloaded { apk_name: String, sha256: String },
+    /// A replaced release asset was published over the previous APK
+    Replaced { apk_name: String, sha256: String },
+    /// The APK was published by an earlier run
+    Published { apk_name: String },
+    /// A dry run would download the APK
+    Planned { apk_name: String },
+    Skipped { reason: String },
+    Failed { error: String },
+}
+
+/// Everything processing one app produced, merged by the caller in apps.yaml order
+#[derive(Debug, Default)]
+pub struct AppOutcome {
+    /// Outcome of every release, in the order they were processed
+    pub releases: Vec<ReleaseOutcome>,
+    /// Error that stopped the releases of the app from being listed
+    pub error: Option<String>,
+    /// Pairs of apk_name -> AppInfo for every release of the app
+    pub apk_infos: Vec<(String, AppInfo)>,
+    /// Updates for the published files record
+    pub published: Vec<(String, PublishedFile)>,
+    /// Downloads skipped in dry-run mode
+    pub planned_downloads: Vec<PlannedDownload>,
+    /// Replacements of published APKs skipped in dry-run mode
+    pub planned_replacements: Vec<PlannedDownload>,
+    /// Nightly APKs beyond `keep` left in place in dry-run mode
+    pub planned_removals: Vec<String>,
+    pub have_error: bool,
+}
+
+impl AppOutcome {
+    fn record(&mut self, apk_name: &str, file: PublishedFile) {
+        self.published.push((apk_name.to_string(), file));
+    }
+
+    fn plan_download(&mut self, version: &str, apk_name: &str, source: &str) {
+        info!("Would download {:?} from {}", apk_name, source);
+        self.planned_downloads.push(PlannedDownload {
+            apk_name: apk_name.to_string(),
+            source: source.to_string(),
+        });
+        self.release(version, ReleaseStatus::Planned { apk_name: apk_name.to_string() });
+    }
+
+    fn release(&mut self, version: &str, status: ReleaseStatus) {
+        self.releases.push(ReleaseOutcome {
+            version: version.to_string(),
+            status,
+        });
+    }
+
+    fn skip(&mut self, version: &str, reason: &str) {
+        self.release(version, ReleaseStatus::Skipped { reason: reason.to_string() });
+    }
+
+    /// Records a release that failed, which fails the run
+    fn fail(&mut self, version: &str, error: String) {
+        self.have_error = true;
+        self.release(version, ReleaseStatus::Failed { error });
+    }
+
+    /// Records an error that stopped the app from being processed
+    fn fail_app(&mut self, error: String) {
+        self.have_error = true;
+        self.error = Some(error);
+    }
+}
+
+pub async fn process_app(ctx: &SyncContext<'_>, mut app: AppInfo) -> Result<AppOutcome> {
+    let mut outcome = AppOutcome::default();
+
+    output::print(format!("App: {}/{}", app.author_name(), app.app_name()));
+
+    if let Some(source) = app.feed.clone() {
+        process_feed(ctx, &app, &source, &mut outcome).await;
+        return Ok(outcome);
+    }
+
+    let repo = apps::repo_info(&app.git)
+        .with_context(|| format!("Failed to get repo info from URL: {}", app.git))?;
+
+    let github = ctx.apis.for_repo(&repo, &app.api_url)
+        .with_context(|| format!("Failed to set up the GitHub API for {}", app.git))?;
+
+    info!("Looking up {}/{} on {}", repo.author, repo.name, github.base_url);
+
+    let repo_route = format!("/repos/{}/{}", repo.author, repo.name);
+
+    match ratelimit::get_json::<octocrab::models::Repository>(&github, &repo_route).await {
+        Ok(gh_repo) => {
+            if let Some(desc) = gh_repo.description {
+                app.summary = desc;
+            }
+
+            if let Some(license) = gh_repo.license {
+                app.license = license.spdx_id;
+            }
+
+            info!("Data from GitHub: summary={:?}, license={:?}", app.summary, app.license);
+        }
+        Err(e) => {
+            error!("Error while looking up repo: {:#}", e);
+        }
+    }
+
+    let releases_route = format!("{}/releases?per_page=100", repo_route);
+
+    // For simplicity, we're only getting the first page
+    // In production, you'd want to paginate through all results
+    let releases = match ratelimit::get_json::<Vec<Release>>(&github, &releases_route).await {
+        Ok(releases) => releases,
+        Err(e) => {
+            error!("Error while listing repo releases for {:?}: {:#}", app.git, e);
+            outcome.fail_app(format!("{:#}", e));
+            return Ok(outcome);
+        }
+    };
+
+    info!("Received {} releases", releases.len());
+
+    for release in releases {
+        output::group(format!("Release {}", release.tag_name));
+        process_release(ctx, &github, &app, &repo, &release, &mut outcome).await;
+        output::endgroup();
+    }
+
+    if let Some(source) = app.artifacts.clone() {
+        output::group(format!("Nightly builds from branch {}", source.branch));
+        process_artifacts(ctx, &github, &app, &repo, &source, &mut outcome).await;
+        output::endgroup();
+    }
+
+    Ok(outcome)
+}
+
+async fn process_feed(ctx: &SyncContext<'_>, app: &AppInfo, source: &FeedSource, outcome: &mut AppOutcome) {
+    info!("Reading release feed {}", source.url);
+
+    let releases = match feed::fetch_releases(ctx.credentials, source).await {
+        Ok(releases) => releases,
+        Err(e) => {
+            error!("Error while reading release feed for {:?}: {:#}", app.git, e);
+            outcome.fail_app(format!("{:#}", e));
+            return;
+        }
+    };
+
+    info!("Received {} releases", releases.len());
+
+    for release in releases {
+        output::group(format!("Release {}", release.version));
+        process_feed_release(ctx, app, &release, outcome).await;
+        output::endgroup();
+    }
+}
+
+async fn process_feed_release(
+    ctx: &SyncContext<'_>,
+    app: &AppInfo,
+    release: &feed::FeedRelease,
+    outcome: &mut AppOutcome,
+) {
+    if app.skip_versions.contains(&release.version) {
+        info!("Skipping version {:?} (configured in skip_versions)", release.version);
+        outcome.skip(&release.version, "skip_versions");
+        return;
+    }
+
+    info!("Working on release {:?} published {:?}", release.version, release.date);
+
+    let app_name = apps::generate_release_filename(app.app_name(), &release.version);
+    info!("Target APK name: {}", app_name);
+
+    if skip_pruned(ctx, &app_name, &release.version, outcome) {
+        return;
+    }
+
+    let mut app_clone = app.clone();
+    app_clone.release_description = release.notes.clone();
+
+    outcome.apk_infos.push((app_name.clone(), app_clone));
+
+    let app_target_path = ctx.repo_dir.join(&app_name);
+
+    if app_target_path.exists() {
+        info!("Already have APK for version {:?} at {:?}", release.version, app_target_path);
+
+        let update = check_published_file(ctx.published, &app_name, &release.apk_url, &app_target_path).await;
+        let record = update.clone().or_else(|| ctx.published.get(&app_name).cloned());
+        if let Some(update) = update {
+            outcome.record(&app_name, update);
+        }
+
+        if let Some(changes) = record.and_then(|r| r.feed_changes(&release.apk_url, &release.sha256)) {
+            warn!("APK of {:?} was replaced upstream ({})", release.version, changes);
+
+            match app.on_asset_change {
+                apps::AssetChangePolicy::Error => {
+                    error!("Refusing to keep serving {:?}, set on_asset_change: replace to publish the new APK", app_name);
+                    outcome.fail(&release.version, format!("APK was replaced upstream ({})", changes));
+                }
+                apps::AssetChangePolicy::Replace if ctx.dry_run => {
+                    info!("Would replace {:?} with the new APK", app_target_path);
+                    outcome.planned_replacements.push(PlannedDownload {
+                        apk_name: app_name.clone(),
+                        source: release.apk_url.clone(),
+                    });
+                    outcome.release(&release.version, ReleaseStatus::Planned { apk_name: app_name });
+                }
+                apps::AssetChangePolicy::Replace => {
+                    match replace_feed_apk(ctx, release, &app_target_path).await {
+                        Ok(sha256) => {
+                            info!("Replaced {:?} with the new APK (sha256 {})", app_target_path, sha256);
+                            outcome.record(&app_name, PublishedFile {
+                                sha256: sha256.clone(),
+                                source: release.apk_url.clone(),
+                                ..Default::default()
+                            });
+                            outcome.release(&release.version, ReleaseStatus::Replaced { apk_name: app_name, sha256 });
+                        }
+                        Err(e) => {
+                            error!("Error while replacing {:?}: {:#}", app_target_path, e);
+                            outcome.fail(&release.version, format!("{:#}", e));
+                        }
+                    }
+                }
+            }
+            return;
+        }
+
+        outcome.release(&release.version, ReleaseStatus::Published { apk_name: app_name });
+        return;
+    }
+
+    if ctx.dry_run {
+        outcome.plan_download(&release.version, &app_name, &release.apk_url);
+        return;
+    }
+
+    info!("Downloading APK {:?} to {:?}", release.apk_url, app_target_path);
+
+    let expected_sha256 = (!release.sha256.is_empty()).then_some(release.sha256.as_str());
+    if expected_sha256.is_none() {
+        warn!("The feed doesn't provide a checksum for {:?}", release.apk_url);
+    }
+
+    match download::download_url(ctx.credentials, &release.apk_url, &app_target_path, expected_sha256).await {
+        Ok(sha256) => {
+            info!("Successfully downloaded app for version {:?} (sha256 {})", release.version, sha256);
+            outcome.record(&app_name, PublishedFile {
+                sha256: sha256.clone(),
+                source: release.apk_url.clone(),
+                ..Default::default()
+            });
+            outcome.release(&release.version, ReleaseStatus::Downloaded { apk_name: app_name, sha256 });
+        }
+        Err(e) => {
+            error!("Error while downloading app: {:#}", e);
+            outcome.fail(&release.version, format!("{:#}", e));
+        }
+    }
+}
+
+async fn process_release(
+    ctx: &SyncContext<'_>,
+    github: &GitHubApi,
+    app: &AppInfo,
+    repo: &Repo,
+    release: &Release,
+    outcome: &mut AppOutcome,
+) {
+    let version = release.tag_name.as_str();
+
+    if release.prerelease {
+        info!("Skipping prerelease {:?}", version);
+        outcome.skip(version, "prerelease");
+        return;
+    }
+
+    if release.draft {
+        info!("Skipping draft {:?}", version);
+        outcome.skip(version, "draft");
+        return;
+    }
+
+    if version.is_empty() {
+        info!("Skipping release with empty tag name");
+        outcome.skip(version, "empty tag name");
+        return;
+    }
+
+    if app.skip_versions.iter().any(|v| v == version) {
+        info!("Skipping version {:?} (configured in skip_versions)", version);
+        outcome.skip(version, "skip_versions");
+        return;
+    }
+
+    info!("Working on release with tag name {:?}", release.tag_name);
+
+    let apk = match apps::find_apk_release(release) {
+        Some(asset) => asset,
+        None => {
+            info!("Couldn't find a release asset with extension \".apk\"");
+            outcome.skip(version, "no APK asset");
+            return;
+        }
+    };
+
+    let app_name = apps::generate_release_filename(app.app_name(), &release.tag_name);
+    info!("Target APK name: {}", app_name);
+
+    if skip_pruned(ctx, &app_name, version, outcome) {
+        return;
+    }
+
+    let mut app_clone = app.clone();
+    app_clone.release_description = release.body.clone().unwrap_or_default();
+
+    if !app_clone.release_description.is_empty() {
+        info!("Release notes: {}", app_clone.release_description);
+    }
+
+    outcome.apk_infos.push((app_name.clone(), app_clone));
+
+    let app_target_path = ctx.repo_dir.join(&app_name);
+
+    if app_target_path.exists() {
+        info!("Already have APK for version {:?} at {:?}", release.tag_name, app_target_path);
+
+        let update = check_published_file(ctx.published, &app_name, apk.browser_download_url.as_str(), &app_target_path).await;
+        let record = update
+            .or_else(|| ctx.published.get(&app_name).cloned())
+            .unwrap_or_default();
+
+        let changes = if record.has_asset_details() {
+            record.asset_changes(&apk)
+        } else {
+            unrecorded_asset_changes(github, repo, release, &apk, &record, &app_target_path).await
+        };
+
+        if let Some(changes) = changes {
+            warn!("Release asset {:?} of {:?} was replaced upstream ({})", apk.name, release.tag_name, changes);
+
+            match app.on_asset_change {
+                apps::AssetChangePolicy::Error => {
+                    error!("Refusing to keep serving {:?}, set on_asset_change: replace to publish the new asset", app_name);
+                    outcome.fail(version, format!("release asset was replaced upstream ({})", changes));
+                }
+                apps::AssetChangePolicy::Replace if ctx.dry_run => {
+                    info!("Would replace {:?} with the new release asset", app_target_path);
+                    outcome.planned_replacements.push(PlannedDownload {
+                        apk_name: app_name.clone(),
+                        source: apk.browser_download_url.to_string(),
+                    });
+                    outcome.release(version, ReleaseStatus::Planned { apk_name: app_name });
+                }
+                apps::AssetChangePolicy::Replace => {
+                    match replace_release_asset(github, repo, release, &apk, &app_target_path).await {
+                        Ok(sha256) => {
+                            info!("Replaced {:?} with the new release asset (sha256 {})", app_target_path, sha256);
+                            outcome.record(&app_name, PublishedFile::from_asset(sha256.clone(), &apk));
+                            outcome.release(version, ReleaseStatus::Replaced { apk_name: app_name, sha256 });
+                        }
+                        Err(e) => {
+                            error!("Error while replacing {:?}: {:#}", app_target_path, e);
+                            outcome.fail(version, format!("{:#}", e));
+                        }
+                    }
+                }
+            }
+            return;
+        }
+
+        if !record.has_asset_details() {
+            outcome.record(&app_name, PublishedFile::from_asset(record.sha256, &apk));
+        }
+        outcome.release(version, ReleaseStatus::Published { apk_name: app_name });
+        return;
+    }
+
+    if ctx.dry_run {
+        outcome.plan_download(version, &app_name, apk.browser_download_url.as_str());
+        return;
+    }
+
+    let expected_sha256 = match checksum::expected_sha256(github, repo, release, &apk).await {
+        Ok(Some(hash)) => {
+            info!("Publisher provided sha256 {} for {:?}", hash, apk.name);
+            Some(hash)
+        }
+        Ok(None) => {
+            warn!("Release {:?} doesn't provide a checksum for {:?}", release.tag_name, apk.name);
+            None
+        }
+        Err(e) => {
+            error!("Error while looking up the checksum of {:?}: {:#}", apk.name, e);
+            outcome.fail(version, format!("{:#}", e));
+            return;
+        }
+    };
+
+    info!("Downloading APK {:?} from release {:?} to {:?}", apk.name, release.tag_name, app_target_path);
+
+    match download::download_asset(github, &repo.author, &repo.name, apk.id.0, &app_target_path, expected_sha256.as_deref()).await {
+        Ok(sha256) => {
+            info!("Successfully downloaded app for version {:?} (sha256 {})", version, sha256);
+            outcome.record(&app_name, PublishedFile::from_asset(sha256.clone(), &apk));
+            outcome.release(version, ReleaseStatus::Downloaded { apk_name: app_name, sha256 });
+        }
+        Err(e) => {
+            error!("Error while downloading app: {}", e);
+            outcome.fail(version, format!("{:#}", e));
+        }
+    }
+}
+
+async fn process_artifacts(
+    ctx: &SyncContext<'_>,
+    github: &GitHubApi,
+    app: &AppInfo,
+    repo: &Repo,
+    source: &ArtifactSource,
+    outcome: &mut AppOutcome,
+) {
+    let builds = match artifacts::list_builds(github, repo, source).await {
+        Ok(builds) => builds,
+        Err(e) => {
+            error!("Error while listing workflow artifacts for {:?}: {:#}", app.git, e);
+            outcome.fail_app(format!("{:#}", e));
+            return;
+        }
+    };
+
+    info!("Received {} nightly builds", builds.len());
+
+    // APKs of the listed builds that are in the repo dir, or would be in a dry run
+    let mut kept = Vec::new();
+    let mut download_failed = false;
+
+    for build in builds {
+        info!("Working on run {} ({}) with artifact {:?}", build.run_id, build.label, build.artifact_name);
+
+        let app_name = apps::generate_release_filename(app.app_name(), &build.label);
+        info!("Target APK name: {}", app_name);
+
+        if skip_pruned(ctx, &app_name, &build.label, outcome) {
+            continue;
+        }
+
+        let mut app_clone = app.clone();
+        app_clone.release_description = build.changelog.clone();
+
+        outcome.apk_infos.push((app_name.clone(), app_clone));
+
+        let app_target_path = ctx.repo_dir.join(&app_name);
+
+        let source_url = format!("{}/actions/runs/{}", app.git.trim_end_matches('/'), build.run_id);
+
+        if app_target_path.exists() {
+            info!("Already have APK for {:?} at {:?}", build.label, app_target_path);
+            if let Some(update) = check_published_file(ctx.published, &app_name, &source_url, &app_target_path).await {
+                outcome.record(&app_name, update);
+            }
+            kept.push(app_name.clone());
+            outcome.release(&build.label, ReleaseStatus::Published { apk_name: app_name });
+            continue;
+        }
+
+        if ctx.dry_run {
+            kept.push(app_name.clone());
+            outcome.plan_download(&build.label, &app_name, &source_url);
+            continue;
+        }
+
+        info!("Downloading artifact {:?} from run {} to {:?}", build.artifact_name, build.run_id, app_target_path);
+
+        match artifacts::download_artifact_apk(github, repo, build.artifact_id, &app_target_path).await {
+            Ok(sha256) => {
+                info!("Successfully downloaded app for {:?} (sha256 {})", build.label, sha256);
+                outcome.record(&app_name, PublishedFile {
+                    sha256: sha256.clone(),
+                    source: source_url,
+                    ..Default::default()
+                });
+                kept.push(app_name.clone());
+                outcome.release(&build.label, ReleaseStatus::Downloaded { apk_name: app_name, sha256 });
+            }
+            Err(e) => {
+                error!("Error while downloading artifact: {:#}", e);
+                download_failed = true;
+                outcome.fail(&build.label, format!("{:#}", e));
+            }
+        }
+    }
+
+    if kept.is_empty() || download_failed {
+        info!("Keeping the older nightly APKs, as no complete set of builds is published");
+        return;
+    }
+
+    remove_old_nightly_apks(ctx, app, &kept, outcome);
+}
+
+/// Removes the nightly APKs of an app whose runs are no longer among the
+/// `keep` newest ones. `fdroid` keeps them otherwise, as their package is
+/// still listed.
+fn remove_old_nightly_apks(ctx: &SyncContext<'_>, app: &AppInfo, kept: &[String], outcome: &mut AppOutcome) {
+    for apk_name in old_nightly_apks(ctx.repo_dir, app.app_name(), kept) {
+        if ctx.dry_run {
+            info!("Would remove old nightly APK {:?}", apk_name);
+            outcome.planned_removals.push(apk_name);
+            continue;
+        }
+
+        info!("Removing old nightly APK {:?}", apk_name);
+        if let Err(e) = fs::remove_file(ctx.repo_dir.join(&apk_name)) {
+            error!("Error while removing {:?}: {}", apk_name, e);
+            outcome.have_error = true;
+        }
+    }
+}
+
+/// Nightly APKs of the app in the repo directory other than the kept ones,
+/// sorted by name
+fn old_nightly_apks(repo_dir: &Path, app_name: &str, kept: &[String]) -> Vec<String> {
+    let prefix = apps::generate_release_filename(app_name, "nightly-");
+    let prefix = prefix.trim_end_matches(".apk");
+
+    let mut apks: Vec<String> = fs::read_dir(repo_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| {
+            name.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(".apk"))
+                .is_some_and(|run| !run.is_empty() && run.chars().all(|c| c.is_ascii_digit()))
+        })
+        .filter(|name| !kept.contains(name))
+        .collect();
+    apks.sort();
+    apks
+}
+
+/// Skips a release whose APK `prune` removed, so it isn't downloaded again
+fn skip_pruned(ctx: &SyncContext<'_>, apk_name: &str, version: &str, outcome: &mut AppOutcome) -> bool {
+    if !ctx.published.is_pruned(apk_name) || ctx.repo_dir.join(apk_name).exists() {
+        return false;
+    }
+
+    info!("Skipping {:?}, its APK {:?} was pruned", version, apk_name);
+    outcome.skip(version, "pruned");
+    true
+}
+
+/// Compares an already published APK with its recorded checksum, to notice
+/// changes to the local file. Returns the record to add for files published
+/// before checksums were kept. Changes upstream are found by comparing the
+/// record with the release asset or feed entry.
+async fn check_published_file(published: &PublishedFiles, apk_name: &str, source: &str, path: &Path) -> Option<PublishedFile> {
+    let hashed_path = path.to_path_buf();
+    let sha256 = match output::spawn_blocking(move || checksum::file_sha256(&hashed_path)).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Error while hashing {:?}: {:#}", path, e);
+            return None;
+        }
+    };
+
+    match published.get(apk_name) {
+        Some(record) if record.sha256 != sha256 => {
+            warn!("{:?} changed since it was published: recorded sha256 {}, now {}", path, record.sha256, sha256);
+            None
+        }
+        Some(_) => None,
+        None => {
+            info!("Recording sha256 {} for {:?}", sha256, apk_name);
+            Some(PublishedFile {
+                sha256,
+                source: source.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+}
+
+/// Compares an APK whose record has no release asset details, as it was
+/// published before they were kept, with the release asset: its size, and
+/// the checksum the publisher provides if any. This happens once, as the
+/// asset details are recorded when they match.
+async fn unrecorded_asset_changes(
+    github: &GitHubApi,
+    repo: &Repo,
+    release: &Release,
+    asset: &Asset,
+    record: &PublishedFile,
+    path: &Path,
+) -> Option<String> {
+    let mut changes = Vec::new();
+
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() as i64 != asset.size => {
+            changes.push(format!("size {} -> {}", metadata.len(), asset.size));
+        }
+        Ok(_) => {}
+        Err(e) => error!("Error while reading {:?}: {}", path, e),
+    }
+
+    if !record.sha256.is_empty() {
+        match checksum::expected_sha256(github, repo, release, asset).await {
+            Ok(Some(sha256)) if sha256 != record.sha256 => {
+                changes.push(format!("sha256 {} -> {}", record.sha256, sha256));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Can't compare {:?} with the checksum of its release asset: {:#}", path, e),
+        }
+    }
+
+    (!changes.is_empty()).then(|| changes.join(", "))
+}
+
+/// Downloads the new APK of a feed entry next to the published one and moves
+/// it into place, as [`replace_release_asset`] does
+async fn replace_feed_apk(ctx: &SyncContext<'_>, release: &feed::FeedRelease, target_path: &Path) -> Result<String> {
+    let candidate_path = target_path.with_extension("apk.new");
+
+    let expected_sha256 = (!release.sha256.is_empty()).then_some(release.sha256.as_str());
+    let sha256 = download::download_url(ctx.credentials, &release.apk_url, &candidate_path, expected_sha256).await?;
+
+    replace_with_candidate(target_path, &candidate_path).await?;
+    Ok(sha256)
+}
+
+/// Downloads a replaced release asset next to the published APK and moves it
+/// into place, see [`replace_with_candidate`]
+async fn replace_release_asset(
+    github: &GitHubApi,
+    repo: &Repo,
+    release: &Release,
+    asset: &Asset,
+    target_path: &Path,
+) -> Result<String> {
+    let candidate_path = target_path.with_extension("apk.new");
+
+    let expected_sha256 = checksum::expected_sha256(github, repo, release, asset).await?;
+
+    let sha256 = download::download_asset(
+        github, &repo.author, &repo.name, asset.id.0, &candidate_path, expected_sha256.as_deref(),
+    ).await?;
+
+    replace_with_candidate(target_path, &candidate_path).await?;
+    Ok(sha256)
+}
+
+/// Moves the APK downloaded at `candidate_path` over the published one if it
+/// is the same package, signed by the same key and not a lower versionCode.
+/// The candidate is removed otherwise. Reading the APKs runs on a blocking
+/// thread.
+async fn replace_with_candidate(target_path: &Path, candidate_path: &Path) -> Result<()> {
+    let target_path = target_path.to_path_buf();
+    let candidate_path = candidate_path.to_path_buf();
+    output::spawn_blocking(move || replace_apk(&target_path, &candidate_path)).await
+}
+
+fn replace_apk(target_path: &Path, candidate_path: &Path) -> Result<()> {
+    let check = || -> Result<()> {
+        let old = apk::read_apk(target_path)?;
+        let new = apk::read_apk(candidate_path)?;
+
+        if new.package_name != old.package_name {
+            anyhow::bail!("package name changed from {:?} to {:?}", old.package_name, new.package_name);
+        }
+        if new.signer != old.signer {
+            anyhow::bail!("signer changed from {} to {}", old.signer, new.signer);
+        }
+        if new.version_code < old.version_code {
+            anyhow::bail!("versionCode went down from {} to {}", old.version_code, new.version_code);
+        }
+
+        info!("New asset is versionCode {} (was {}), signed by {}", new.version_code, old.version_code, new.signer);
+        Ok(())
+    };
+
+    if let Err(e) = check() {
+        let _ = fs::remove_file(candidate_path);
+        return Err(e);
+    }
+
+    fs::rename(candidate_path, target_path)
+        .with_context(|| format!("Failed to rename {} to {}", candidate_path.display(), target_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testserver::{Response, Server};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    const APK: &[u8] = b"stands in for an APK";
+
+    fn releases() -> serde_json::Value {
+        let base = "https://git.example.com/api/v3/repos/team/app/releases";
+        serde_json::json!([{
+            "url": format!("{}/1", base),
+            "html_url": "https://git.example.com/team/app/releases/tag/v1.0",
+            "assets_url": format!("{}/1/assets", base),
+            "upload_url": format!("{}/1/assets", base),
+            "id": 1,
+            "node_id": "R_1",
+            "tag_name": "v1.0",
+            "target_commitish": "main",
+            "draft": false,
+            "prerelease": false,
+            "assets": [{
+                "url": format!("{}/assets/7", base),
+                "browser_download_url": "https://git.example.com/team/app/releases/download/v1.0/app.apk",
+                "id": 7,
+                "node_id": "RA_7",
+                "name": "app.apk",
+                "state": "uploaded",
+                "content_type": "application/vnd.android.package-archive",
+                "size": APK.len(),
+                "download_count": 0,
+                "created_at": "2026-01-01T00:00:00Z",
+                "updated_at": "2026-01-01T00:00:00Z",
+            }],
+        }])
+    }
+
+    #[test]
+    fn only_nightly_apks_beyond_keep_are_old() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["app_nightly-40.apk", "app_nightly-41.apk", "app_nightly-42.apk", "app_v1.0.apk", "app_nightly-x.apk", "other_nightly-1.apk"] {
+            fs::write(dir.path().join(name), APK).unwrap();
+        }
+
+        let old = old_nightly_apks(dir.path(), "app", &["app_nightly-42.apk".to_string()]);
+        assert_eq!(old, ["app_nightly-40.apk", "app_nightly-41.apk"]);
+    }
+
+    #[tokio::test]
+    async fn nightly_apks_stay_without_builds() {
+        let server = Server::start(|request| {
+            match request.path.as_str() {
+                "/api/v3/repos/team/app/releases?per_page=100" => Response::json(serde_json::json!([])),
+                path if path.starts_with("/api/v3/repos/team/app/actions/runs") => {
+                    Response::json(serde_json::json!({ "total_count": 0, "workflow_runs": [] }))
+                }
+                _ => Response::new(404, r#"{"message": "Not Found"}"#),
+            }
+        }).await;
+
+        let credentials = Credentials::default();
+        let api_urls = HashMap::from([("git.example.com".to_string(), format!("{}/api/v3", server.url))]);
+        let apis = GitHubApis::new(credentials.clone(), api_urls, Duration::from_secs(60));
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app_nightly-41.apk"), APK).unwrap();
+        let published = PublishedFiles::default();
+        let ctx = SyncContext {
+            apis: &apis,
+            credentials: &credentials,
+            repo_dir: dir.path(),
+            published: &published,
+            dry_run: false,
+        };
+
+        let mut app: AppInfo = serde_yaml::from_str("git: https://git.example.com/team/app\nartifacts: {}").unwrap();
+        app.key_name = "app".to_string();
+        let outcome = process_app(&ctx, app).await.unwrap();
+
+        assert!(!outcome.have_error, "{:?}", outcome.releases);
+        assert!(server.requests().iter().any(|r| r.path.contains("/actions/runs")));
+        assert!(dir.path().join("app_nightly-41.apk").exists());
+    }
+
+    #[tokio::test]
+    async fn enterprise_app_is_synced_from_its_own_api() {
+        let server = Server::start(|request| {
+            let download = request.headers.get("accept").is_some_and(|a| a == "application/octet-stream");
+            match request.path.as_str() {
+                "/api/v3/repos/team/app/releases?per_page=100" => Response::json(releases()),
+                "/api/v3/repos/team/app/releases/assets/7" if download => Response::new(200, APK),
+                "/api/v3/repos/team/app/releases/assets/7" => {
+                    Response::json(serde_json::json!({ "digest": format!("sha256:{}", checksum::sha256_hex(APK)) }))
+                }
+                _ => Response::new(404, r#"{"message": "Not Found"}"#),
+            }
+        }).await;
+
+        let mut credentials = Credentials::default();
+        credentials.insert("git.example.com", "enterprise-token");
+        let api_urls = HashMap::from([("git.example.com".to_string(), format!("{}/api/v3", server.url))]);
+        let apis = GitHubApis::new(credentials.clone(), api_urls, Duration::from_secs(60));
+        let dir = tempfile::tempdir().unwrap();
+        let published = PublishedFiles::default();
+        let ctx = SyncContext {
+            apis: &apis,
+            credentials: &credentials,
+            repo_dir: dir.path(),
+            published: &published,
+            dry_run: false,
+        };
+
+        let mut app: AppInfo = serde_yaml::from_str("git: https://git.example.com/team/app").unwrap();
+        app.key_name = "app".to_string();
+        let outcome = process_app(&ctx, app).await.unwrap();
+
+        assert!(
+            matches!(&outcome.releases[0].status, ReleaseStatus::Downloaded { apk_name, .. } if apk_name == "app_v1.0.apk"),
+            "{:?}", outcome.releases
+        );
+        assert_eq!(fs::read(dir.path().join("app_v1.0.apk")).unwrap(), APK);
+
+        let requests = server.requests();
+        assert!(requests.iter().any(|r| r.path == "/api/v3/repos/team/app"));
+        for request in requests {
+            assert_eq!(request.headers.get("authorization").map(String::as_str), Some("Bearer enterprise-token"), "{}", request.path);
+        }
+    }
+}
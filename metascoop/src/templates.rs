@@ -0,0 +1,331 @@
+use anyhow::{Context, Result};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tera::{Tera, Context as TeraContext};
+
+use crate::apps::{AppInfo, RepoIndex};
+use crate::md;
+use crate::release_notes::PackageChange;
+
+/// File of the templates directory listing the documents to generate
+pub const DOCUMENTS_FILE: &str = "documents.yaml";
+
+/// A document generated from a template, as listed in `documents.yaml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Document {
+    /// Template file, relative to the templates directory
+    pub template: String,
+    /// File to write, relative to the parent of the templates directory.
+    /// For `per_app` documents it is a template itself, e.g. `docs/{{ app.key }}.md`
+    pub target: String,
+    /// Markers between which the rendered template goes, the whole file is
+    /// written without them
+    #[serde(default)]
+    pub start: Option<String>,
+    #[serde(default)]
+    pub end: Option<String>,
+    /// Render the template once for every app, which it gets as `app`
+    #[serde(default)]
+    pub per_app: bool,
+}
+
+/// What templates are rendered with
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateContext {
+    pub repo: RepoContext,
+    /// Apps of the index, in apps.yaml order
+    pub apps: Vec<AppContext>,
+    /// Versions the sync added to and removed from the index
+    pub changes: Vec<PackageChange>,
+    /// Day of the sync in UTC, as YYYY-MM-DD
+    pub date: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepoContext {
+    pub name: String,
+    pub description: String,
+    pub address: String,
+    /// Time the index was generated, in milliseconds since the epoch
+    pub timestamp: i64,
+    /// Repo directory, relative to the parent of the templates directory,
+    /// which the paths of icons, screenshots and APKs are relative to
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AppContext {
+    /// Key of the app in apps.yaml, or the package name when there is none
+    pub key: String,
+    pub package_name: String,
+    pub name: String,
+    pub summary: String,
+    pub description: String,
+    pub license: String,
+    pub author_name: Option<String>,
+    pub source_code: Option<String>,
+    pub web_site: Option<String>,
+    pub categories: Vec<String>,
+    /// Times in milliseconds since the epoch
+    pub added: i64,
+    pub last_updated: i64,
+    pub icon: Option<String>,
+    pub suggested_version: Option<VersionContext>,
+    /// Versions in the index, newest first
+    pub versions: Vec<VersionContext>,
+    /// Screenshots of every kind, such as `phoneScreenshots`, per locale
+    pub screenshots: BTreeMap<String, Vec<String>>,
+    /// Localized texts of the index per locale, such as `name`, `summary`
+    /// and `whatsNew`
+    pub localized: Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VersionContext {
+    pub name: String,
+    pub code: i32,
+    pub apk_name: String,
+    pub size: i64,
+    pub sha256: String,
+    pub added: i64,
+    pub min_sdk_version: i32,
+    pub target_sdk_version: i32,
+    pub nativecode: Vec<String>,
+    /// Release notes written to the metadata for this version
+    pub changelog: Option<String>,
+}
+
+impl TemplateContext {
+    /// Gathers what templates can use from the final index, the metadata
+    /// directory and the changes of the sync
+    pub fn new(
+        index: &RepoIndex,
+        repo_path: &str,
+        metadata_dir: &Path,
+        apk_info_map: &HashMap<String, AppInfo>,
+        package_order: &[String],
+        changes: Vec<PackageChange>,
+        date: String,
+    ) -> Self {
+        let repo = RepoContext {
+            name: str_field(&index.repo, "name").unwrap_or_default(),
+            description: str_field(&index.repo, "description").unwrap_or_default(),
+            address: str_field(&index.repo, "address").unwrap_or_default(),
+            timestamp: index.repo.get("timestamp").and_then(Value::as_i64).unwrap_or_default(),
+            path: repo_path.to_string(),
+        };
+
+        let mut apps: Vec<AppContext> = index.apps.iter()
+            .map(|app| app_context(app, index, metadata_dir, apk_info_map))
+            .collect();
+        apps.sort_by_key(|app| {
+            (crate::apps::order_position(package_order, &app.package_name), app.package_name.clone())
+        });
+
+        Self { repo, apps, changes, date }
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+fn app_context(app: &Value, index: &RepoIndex, metadata_dir: &Path, apk_info_map: &HashMap<String, AppInfo>) -> AppContext {
+    let package_name = str_field(app, "packageName").unwrap_or_default();
+    let localized = app.get("localized").cloned().unwrap_or(Value::Object(Default::default()));
+
+    // Texts from fastlane metadata are only in `localized`
+    let text = |key: &str| str_field(app, key)
+        .or_else(|| localized.get("en-US").and_then(|l| str_field(l, key)))
+        .unwrap_or_default();
+
+    let mut packages = index.packages.get(&package_name).cloned().unwrap_or_default();
+    packages.sort_by_key(|p| std::cmp::Reverse(p.version_code));
+
+    let versions: Vec<VersionContext> = packages.iter()
+        .map(|p| VersionContext {
+            name: p.version_name.clone(),
+            code: p.version_code,
+            apk_name: p.apk_name.clone(),
+            size: p.size,
+            sha256: p.hash.clone(),
+            added: p.added,
+            min_sdk_version: p.min_sdk_version,
+            target_sdk_version: p.target_sdk_version,
+            nativecode: p.nativecode.clone(),
+            changelog: fs::read_to_string(
+                metadata_dir.join(&package_name).join("en-US").join("changelogs").join(format!("{}.txt", p.version_code)),
+            ).ok(),
+        })
+        .collect();
+
+    let suggested_code = app.get("suggestedVersionCode")
+        .and_then(|c| c.as_str().and_then(|s| s.parse().ok()).or_else(|| c.as_i64()));
+    let suggested_version = versions.iter()
+        .find(|v| Some(i64::from(v.code)) == suggested_code)
+        .or(versions.first())
+        .cloned();
+
+    let mut screenshots = BTreeMap::new();
+    if let Some(locales) = localized.as_object() {
+        for (locale, fields) in locales {
+            let Some(fields) = fields.as_object() else { continue };
+            let package_name = &package_name;
+            let files: Vec<String> = fields.iter()
+                .filter(|(kind, _)| kind.ends_with("Screenshots"))
+                .flat_map(|(kind, files)| {
+                    files.as_array().into_iter().flatten()
+                        .filter_map(Value::as_str)
+                        .map(move |file| format!("{}/{}/{}/{}", package_name, locale, kind, file))
+                })
+                .collect();
+            if !files.is_empty() {
+                screenshots.insert(locale.clone(), files);
+            }
+        }
+    }
+
+    let key = packages.iter()
+        .find_map(|p| apk_info_map.get(&p.apk_name))
+        .map(|info| info.key_name.clone())
+        .unwrap_or_else(|| package_name.clone());
+
+    AppContext {
+        key,
+        name: text("name"),
+        summary: text("summary"),
+        description: text("description"),
+        license: str_field(app, "license").unwrap_or_default(),
+        author_name: str_field(app, "authorName"),
+        source_code: str_field(app, "sourceCode"),
+        web_site: str_field(app, "webSite"),
+        categories: app.get("categories")
+            .and_then(Value::as_array)
+            .map(|c| c.iter().filter_map(Value::as_str).map(str::to_string).collect())
+            .unwrap_or_default(),
+        added: app.get("added").and_then(Value::as_i64).unwrap_or_default(),
+        last_updated: app.get("lastUpdated").and_then(Value::as_i64).unwrap_or_default(),
+        icon: str_field(app, "icon").map(|icon| format!("icons/{}", icon)),
+        suggested_version,
+        versions,
+        screenshots,
+        localized,
+        package_name,
+    }
+}
+
+/// Renders the documents listed in `documents.yaml` of `templates_dir`,
+/// doing nothing when there is no such file. A document that fails is
+/// logged and the others are still written.
+pub fn render_documents(templates_dir: &Path, context: &TemplateContext) -> Result<()> {
+    let documents_path = templates_dir.join(DOCUMENTS_FILE);
+    if !documents_path.exists() {
+        return Ok(());
+    }
+
+    let content = fs::read_to_string(&documents_path)
+        .with_context(|| format!("Failed to read {}", documents_path.display()))?;
+    let documents: Vec<Document> = serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse YAML from {}", documents_path.display()))?;
+
+    let tera = load_templates(templates_dir)?;
+    let root = templates_dir.parent().unwrap_or(Path::new(""));
+    let tera_context = TeraContext::from_serialize(context)?;
+
+    let mut failed = 0;
+    for document in &documents {
+        if let Err(e) = render_document(&tera, &tera_context, context, root, document) {
+            error!("Error generating {} from {}: {:#}", document.target, document.template, e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} documents failed", failed, documents.len());
+    }
+
+    Ok(())
+}
+
+/// Loads every file of the templates directory but `documents.yaml`, named
+/// by their path in it, so templates can include and extend each other
+fn load_templates(templates_dir: &Path) -> Result<Tera> {
+    let mut files: Vec<(PathBuf, Option<String>)> = Vec::new();
+    for entry in walkdir::WalkDir::new(templates_dir).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to read {}", templates_dir.display()))?;
+        let relative = entry.path().strip_prefix(templates_dir)?;
+        if !entry.file_type().is_file() || relative == Path::new(DOCUMENTS_FILE) {
+            continue;
+        }
+
+        let name = relative.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push((entry.into_path(), Some(name)));
+    }
+
+    let mut tera = Tera::default();
+    tera.add_template_files(files)
+        .with_context(|| format!("Failed to load the templates of {}", templates_dir.display()))?;
+    Ok(tera)
+}
+
+fn render_document(
+    tera: &Tera,
+    tera_context: &TeraContext,
+    context: &TemplateContext,
+    root: &Path,
+    document: &Document,
+) -> Result<()> {
+    let markers = match (&document.start, &document.end) {
+        (Some(start), Some(end)) => Some((start.as_str(), end.as_str())),
+        (None, None) => None,
+        _ => anyhow::bail!("Give both the start and the end marker, or neither"),
+    };
+
+    if !document.per_app {
+        let content = tera.render(&document.template, tera_context)?;
+        return write_target(&root.join(&document.target), &content, markers);
+    }
+
+    for app in &context.apps {
+        let mut app_context = tera_context.clone();
+        app_context.insert("app", app);
+
+        let target = Tera::one_off(&document.target, &app_context, false)
+            .with_context(|| format!("Failed to render the target {:?}", document.target))?;
+        let content = tera.render(&document.template, &app_context)?;
+        write_target(&root.join(target.trim()), &content, markers)?;
+    }
+
+    Ok(())
+}
+
+/// Writes `content` to `path`, or between the markers of the existing file
+fn write_target(path: &Path, content: &str, markers: Option<(&str, &str)>) -> Result<()> {
+    let new_content = match markers {
+        Some((start, end)) => {
+            let current = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            md::replace_section(&current, start, end, content)
+                .ok_or_else(|| anyhow::anyhow!("Cannot find the markers in {:?}", path))?
+        }
+        None => content.to_string(),
+    };
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, new_content)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    info!("Generated {:?}", path);
+    Ok(())
+}
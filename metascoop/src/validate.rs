@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use log::{info, Level};
+use octocrab::models::repos::Release;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use url::Url;
+
+use crate::apps::{self, AppInfo};
+use crate::credentials::Credentials;
+use crate::github::GitHubApis;
+use crate::output::{self, Location};
+use crate::{feed, metadata, ratelimit};
+
+/// Problems found while validating, with the place they were found at
+#[derive(Debug, Default)]
+struct Report {
+    errors: Vec<(Location, String)>,
+    warnings: Vec<(Location, String)>,
+}
+
+impl Report {
+    fn error(&mut self, location: &Location, message: String) {
+        self.errors.push((location.clone(), message));
+    }
+
+    fn warning(&mut self, location: &Location, message: String) {
+        self.warnings.push((location.clone(), message));
+    }
+}
+
+/// Checks apps.yaml and the metadata files, failing if there are errors.
+/// Without `apis`, checks that need the upstream releases are skipped.
+pub async fn run(
+    apps_path: &Path,
+    metadata_dir: &Path,
+    apis: Option<(&GitHubApis, &Credentials)>,
+) -> Result<()> {
+    let apps_list = apps::parse_app_file(apps_path)
+        .context("Failed to parse app file")?;
+
+    let mut report = Report::default();
+
+    validate_apps(&apps_list, &mut report);
+    if let Some((apis, credentials)) = apis {
+        for app in &apps_list {
+            validate_skip_versions(app, apis, credentials, &mut report).await;
+        }
+    }
+    validate_metadata(metadata_dir, &mut report);
+
+    for (location, warning) in &report.warnings {
+        output::annotate(Level::Warn, Some(location), warning);
+    }
+    for (location, problem) in &report.errors {
+        output::annotate(Level::Error, Some(location), problem);
+    }
+
+    if !report.errors.is_empty() {
+        anyhow::bail!("Found {} errors and {} warnings", report.errors.len(), report.warnings.len());
+    }
+
+    info!(
+        "{} apps and their metadata are valid ({} warnings)",
+        apps_list.len(),
+        report.warnings.len()
+    );
+
+    Ok(())
+}
+
+fn validate_apps(apps_list: &[AppInfo], report: &mut Report) {
+    let mut seen_git: HashMap<String, String> = HashMap::new();
+
+    for app in apps_list {
+        let key = &app.key_name;
+        let at = &app.source;
+
+        check_url(report, at, key, "git", &app.git);
+
+        match apps::repo_info(&app.git) {
+            Ok(_) => {
+                let git = app.git.trim_end_matches('/').to_lowercase();
+                if let Some(other) = seen_git.insert(git, key.clone()) {
+                    report.error(at, format!("{}: git {:?} is also used by {:?}", key, app.git, other));
+                }
+            }
+            Err(e) if app.feed.is_none() => {
+                report.error(at, format!("{}: git {:?} is not a repository URL: {}", key, app.git, e));
+            }
+            Err(_) => {}
+        }
+
+        if !app.api_url.is_empty() {
+            check_url(report, at, key, "api_url", &app.api_url);
+        }
+
+        if let Some(feed) = &app.feed {
+            check_url(report, at, key, "feed.url", &feed.url);
+            if !feed.apk_url_template.is_empty() && !feed.apk_url_template.contains("{version}") {
+                report.warning(at, format!("{}: feed.apk_url_template doesn't contain {{version}}", key));
+            }
+            if app.artifacts.is_some() {
+                report.warning(at, format!("{}: artifacts are ignored for apps with a feed", key));
+            }
+        }
+
+        if let Some(artifacts) = &app.artifacts {
+            if artifacts.keep == 0 {
+                report.error(at, format!("{}: artifacts.keep must be at least 1", key));
+            }
+        }
+
+        for category in &app.categories {
+            if !apps::FDROID_CATEGORIES.contains(&category.as_str()) {
+                report.warning(at, format!("{}: {:?} is not one of F-Droid's categories", key, category));
+            }
+        }
+
+        for anti_feature in &app.anti_features {
+            if !apps::FDROID_ANTI_FEATURES.contains(&anti_feature.as_str()) {
+                report.error(at, format!(
+                    "{}: {:?} is not an F-Droid anti-feature, use one of {}",
+                    key, anti_feature, apps::FDROID_ANTI_FEATURES.join(", ")
+                ));
+            }
+        }
+
+        if app.skip_versions.iter().any(|v| v.is_empty()) {
+            report.error(at, format!("{}: skip_versions contains an empty version", key));
+        }
+    }
+}
+
+/// Checks that `url` is an http(s) URL with a host
+fn check_url(report: &mut Report, at: &Location, key: &str, field: &str, url: &str) {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            report.error(at, format!("{}: {} {:?} is not a URL: {}", key, field, url, e));
+            return;
+        }
+    };
+
+    match parsed.scheme() {
+        "https" => {}
+        "http" => report.warning(at, format!("{}: {} {:?} doesn't use https", key, field, url)),
+        scheme => report.error(at, format!("{}: {} {:?} has unsupported scheme {:?}", key, field, url, scheme)),
+    }
+
+    match parsed.host_str() {
+        None | Some("") => report.error(at, format!("{}: {} {:?} has no host", key, field, url)),
+        Some(host) if !host.contains('.') && host != "localhost" => {
+            report.warning(at, format!("{}: {} {:?} has an unusual host {:?}", key, field, url, host))
+        }
+        Some(_) => {}
+    }
+}
+
+/// Checks that every skip_versions entry names an upstream release, so a typo
+/// doesn't silently publish the version it was meant to skip
+async fn validate_skip_versions(app: &AppInfo, apis: &GitHubApis, credentials: &Credentials, report: &mut Report) {
+    if app.skip_versions.is_empty() {
+        return;
+    }
+
+    let at = &app.source;
+
+    let versions = match upstream_versions(app, apis, credentials).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            report.warning(at, format!("{}: couldn't list the releases to check skip_versions: {:#}", app.key_name, e));
+            return;
+        }
+    };
+
+    for version in &app.skip_versions {
+        if !versions.contains(version) {
+            report.error(at, format!("{}: skip_versions entry {:?} matches no release", app.key_name, version));
+        }
+    }
+}
+
+async fn upstream_versions(app: &AppInfo, apis: &GitHubApis, credentials: &Credentials) -> Result<Vec<String>> {
+    if let Some(source) = &app.feed {
+        let releases = feed::fetch_releases(credentials, source).await?;
+        return Ok(releases.into_iter().map(|r| r.version).collect());
+    }
+
+    let repo = apps::repo_info(&app.git)?;
+    let github = apis.for_repo(&repo, &app.api_url)?;
+
+    let mut versions = Vec::new();
+    for page in 1.. {
+        let route = format!("/repos/{}/{}/releases?per_page=100&page={}", repo.author, repo.name, page);
+        let releases: Vec<Release> = ratelimit::get_json(&github, &route).await?;
+
+        let last_page = releases.len() < 100;
+        versions.extend(releases.into_iter().map(|r| r.tag_name));
+        if last_page {
+            break;
+        }
+    }
+
+    Ok(versions)
+}
+
+fn validate_metadata(metadata_dir: &Path, report: &mut Report) {
+    for path in metadata::metadata_files(metadata_dir) {
+        let at = &Location {
+            file: path.clone(),
+            ..Default::default()
+        };
+
+        let meta = match apps::read_meta_file(&path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                report.error(at, format!("{:#}", e));
+                continue;
+            }
+        };
+
+        for key in ["Name", "Summary", "Description", "License", "AuthorName", "SourceCode", "CurrentVersion"] {
+            if let Some(value) = meta.get(key) {
+                if !value.is_string() && !value.is_null() {
+                    report.error(at, format!("{} must be a string", key));
+                }
+            }
+        }
+
+        if let Some(summary) = meta.get("Summary").and_then(Value::as_str) {
+            if summary.len() > 80 {
+                report.warning(at, "Summary is longer than 80 characters".to_string());
+            }
+        }
+
+        if let Some(categories) = meta.get("Categories") {
+            let is_list_of_strings = categories
+                .as_sequence()
+                .is_some_and(|c| c.iter().all(Value::is_string));
+            if !is_list_of_strings {
+                report.error(at, "Categories must be a list of strings".to_string());
+            }
+        }
+
+        if let Some(code) = meta.get("CurrentVersionCode") {
+            if code.as_i64().is_none() {
+                report.error(at, "CurrentVersionCode must be an integer".to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testserver::{Response, Server};
+    use std::time::Duration;
+
+    fn releases(tags: impl Iterator<Item = usize>) -> serde_json::Value {
+        let base = "https://git.example.com/api/v3/repos/team/app/releases";
+        tags.map(|i| serde_json::json!({
+            "url": format!("{}/{}", base, i),
+            "html_url": format!("https://git.example.com/team/app/releases/tag/v{}", i),
+            "assets_url": format!("{}/{}/assets", base, i),
+            "upload_url": format!("{}/{}/assets", base, i),
+            "id": i,
+            "node_id": format!("R_{}", i),
+            "tag_name": format!("v{}", i),
+            "target_commitish": "main",
+            "draft": false,
+            "prerelease": false,
+            "assets": [],
+        })).collect()
+    }
+
+    #[tokio::test]
+    async fn skip_versions_are_looked_up_on_every_page() {
+        let server = Server::start(|request| {
+            match request.path.as_str() {
+                "/api/v3/repos/team/app/releases?per_page=100&page=1" => Response::json(releases(200..300)),
+                "/api/v3/repos/team/app/releases?per_page=100&page=2" => Response::json(releases(1..3)),
+                _ => Response::new(404, r#"{"message": "Not Found"}"#),
+            }
+        }).await;
+
+        let api_urls = HashMap::from([("git.example.com".to_string(), format!("{}/api/v3", server.url))]);
+        let apis = GitHubApis::new(Credentials::default(), api_urls, Duration::from_secs(60));
+        let app: AppInfo = serde_yaml::from_str("git: https://git.example.com/team/app").unwrap();
+
+        let versions = upstream_versions(&app, &apis, &Credentials::default()).await.unwrap();
+        assert_eq!(versions.len(), 102);
+        assert!(versions.contains(&"v1".to_string()));
+        assert_eq!(server.requests().len(), 2);
+    }
+}
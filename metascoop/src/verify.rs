@@ -0,0 +1,344 @@
+use anyhow::{Context, Result};
+use log::{info, Level};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::apps::RepoIndex;
+use crate::output::{self, Location};
+use crate::{checksum, jar, md};
+
+/// Signed JARs of the repo and the file each of them holds
+const SIGNED_JARS: &[(&str, &str)] = &[
+    ("entry.jar", "entry.json"),
+    ("index-v1.jar", "index-v1.json"),
+];
+
+/// Problems found in the repo, with the file they were found in
+#[derive(Debug, Default)]
+struct Report {
+    problems: Vec<(PathBuf, String)>,
+    /// Size and SHA-256 of the files checked so far, as APKs are listed in
+    /// both indexes. Files that couldn't be read are reported once.
+    hashes: HashMap<PathBuf, Option<(u64, String)>>,
+    checked: BTreeSet<PathBuf>,
+}
+
+impl Report {
+    fn problem(&mut self, path: &Path, message: String) {
+        self.problems.push((path.to_path_buf(), message));
+    }
+
+    /// Checks that the file `name` listed in `listed_in` exists with the
+    /// given size and hash
+    fn check_file(&mut self, repo_dir: &Path, listed_in: &str, name: &str, sha256: &str, size: Option<u64>) {
+        let name = name.trim_start_matches('/');
+        let path = repo_dir.join(name);
+        self.checked.insert(path.clone());
+
+        let hash = match self.hashes.get(&path) {
+            Some(hash) => hash.clone(),
+            None => {
+                let hash = match fs::read(&path) {
+                    Ok(bytes) => Some((bytes.len() as u64, checksum::sha256_hex(&bytes))),
+                    Err(e) => {
+                        self.problem(&path, format!("{} is listed in {} but can't be read: {}", name, listed_in, e));
+                        None
+                    }
+                };
+                self.hashes.insert(path.clone(), hash.clone());
+                hash
+            }
+        };
+        let Some((actual_size, actual_sha256)) = hash else { return };
+
+        if !actual_sha256.eq_ignore_ascii_case(sha256) {
+            self.problem(&path, format!("SHA-256 of {} is {}, but {} lists {}", name, actual_sha256, listed_in, sha256));
+        }
+        if let Some(size) = size.filter(|&s| s != actual_size) {
+            self.problem(&path, format!("Size of {} is {}, but {} lists {}", name, actual_size, listed_in, size));
+        }
+    }
+}
+
+/// Checks that the published repo is consistent: the APKs and other files
+/// match the index, `entry.json` matches `index-v2.json` and the signed JARs
+/// verify against the fingerprint in the README. Fails if there are problems.
+pub fn run(repo_dir: &Path, readme_path: &Path) -> Result<()> {
+    let fingerprint = read_fingerprint(readme_path)?;
+    let report = check(repo_dir, &fingerprint)?;
+
+    for (path, problem) in &report.problems {
+        let location = Location { file: path.clone(), line: None, column: None };
+        output::annotate(Level::Error, Some(&location), problem);
+    }
+
+    if !report.problems.is_empty() {
+        anyhow::bail!("Found {} problems in {}", report.problems.len(), repo_dir.display());
+    }
+
+    info!(
+        "Repo {} is consistent: {} files match the index and the signatures verify",
+        repo_dir.display(),
+        report.checked.len()
+    );
+
+    Ok(())
+}
+
+/// Checks the files of the repo against its indexes, and its signed JARs
+/// against the repo fingerprint
+fn check(repo_dir: &Path, fingerprint: &str) -> Result<Report> {
+    let mut report = Report::default();
+
+    for (jar_name, file_name) in SIGNED_JARS {
+        verify_jar(repo_dir, jar_name, file_name, fingerprint, &mut report);
+    }
+
+    let entry_path = repo_dir.join("entry.json");
+    match read_json(&entry_path) {
+        Ok(entry) => check_file_entries(repo_dir, "entry.json", &entry, &mut report),
+        Err(e) => report.problem(&entry_path, format!("{:#}", e)),
+    }
+
+    let index_v2_path = repo_dir.join("index-v2.json");
+    match read_json(&index_v2_path) {
+        Ok(index) => check_file_entries(repo_dir, "index-v2.json", &index, &mut report),
+        Err(e) => report.problem(&index_v2_path, format!("{:#}", e)),
+    }
+
+    let index_v1_path = repo_dir.join("index-v1.json");
+    match RepoIndex::read_index(&index_v1_path) {
+        Ok(index) => {
+            for package in index.packages.values().flatten() {
+                if package.hash_type != "sha256" {
+                    let path = repo_dir.join(&package.apk_name);
+                    report.problem(&path, format!("{} has an unsupported hash type {:?}", package.apk_name, package.hash_type));
+                    continue;
+                }
+                let size = u64::try_from(package.size).ok();
+                report.check_file(repo_dir, "index-v1.json", &package.apk_name, &package.hash, size);
+            }
+        }
+        Err(e) => report.problem(&index_v1_path, format!("Failed to read index: {:#}", e)),
+    }
+
+    for apk_path in list_apks(repo_dir)? {
+        if !report.checked.contains(&apk_path) {
+            let name = apk_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            report.problem(&apk_path, format!("{} is not in the index", name));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Reads the repo fingerprint pinned in the link to add the repo
+fn read_fingerprint(readme_path: &Path) -> Result<String> {
+    let content = fs::read_to_string(readme_path)
+        .with_context(|| format!("Failed to read README: {}", readme_path.display()))?;
+
+    md::pinned_fingerprint(&content)
+        .ok_or_else(|| anyhow::anyhow!("Cannot find the repo fingerprint in {}", readme_path.display()))
+}
+
+/// Checks the signature of a JAR, its signer and that it holds the same
+/// file as the repo
+fn verify_jar(repo_dir: &Path, jar_name: &str, file_name: &str, fingerprint: &str, report: &mut Report) {
+    let jar_path = repo_dir.join(jar_name);
+    let signed = match jar::verify_signed_jar(&jar_path) {
+        Ok(signed) => signed,
+        Err(e) => {
+            report.problem(&jar_path, format!("{}: {:#}", jar_name, e));
+            return;
+        }
+    };
+
+    let signer = checksum::sha256_hex(&signed.certificate);
+    if signer != fingerprint {
+        report.problem(&jar_path, format!("{} is signed by {}, not by the repo fingerprint {}", jar_name, signer, fingerprint));
+    }
+
+    match (signed.files.get(file_name), fs::read(repo_dir.join(file_name))) {
+        (None, _) => report.problem(&jar_path, format!("{} doesn't hold {}", jar_name, file_name)),
+        (Some(signed_content), Ok(content)) if *signed_content != content => {
+            report.problem(&jar_path, format!("{} in {} differs from {}", file_name, jar_name, file_name))
+        }
+        (Some(_), Ok(_)) => {}
+        (Some(_), Err(e)) => report.problem(&repo_dir.join(file_name), format!("Failed to read {}: {}", file_name, e)),
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Checks every file an index lists, which are objects with a `name` that
+/// is a path in the repo and a `sha256`: APKs, icons, screenshots and the
+/// indexes and diffs of `entry.json`
+fn check_file_entries(repo_dir: &Path, listed_in: &str, value: &Value, report: &mut Report) {
+    match value {
+        Value::Object(object) => {
+            let name = object.get("name").and_then(|v| v.as_str()).filter(|n| n.starts_with('/'));
+            let sha256 = object.get("sha256").and_then(|v| v.as_str());
+            if let (Some(name), Some(sha256)) = (name, sha256) {
+                let size = object.get("size").and_then(|v| v.as_u64());
+                report.check_file(repo_dir, listed_in, name, sha256, size);
+            }
+            for child in object.values() {
+                check_file_entries(repo_dir, listed_in, child, report);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                check_file_entries(repo_dir, listed_in, item, report);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn list_apks(repo_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut apks = Vec::new();
+    for entry in fs::read_dir(repo_dir).with_context(|| format!("Failed to read {}", repo_dir.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "apk") {
+            apks.push(path);
+        }
+    }
+    apks.sort();
+    Ok(apks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jar::RepoKey;
+
+    const APK: &[u8] = b"stands in for an APK";
+
+    fn test_key() -> RepoKey {
+        let keystore = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/keystore.p12");
+        RepoKey::load(&keystore, "testpass", "repokey").unwrap()
+    }
+
+    /// A consistent repo with one APK, signed with the test key. Returns the
+    /// repo fingerprint.
+    fn repo(dir: &Path) -> String {
+        let key = test_key();
+        let apk_sha256 = checksum::sha256_hex(APK);
+        fs::write(dir.join("app.apk"), APK).unwrap();
+
+        let index_v1 = serde_json::json!({
+            "repo": {},
+            "requests": {},
+            "apps": [],
+            "packages": {
+                "org.example.app": [{
+                    "added": 0,
+                    "apkName": "app.apk",
+                    "hash": apk_sha256,
+                    "hashType": "sha256",
+                    "minSdkVersion": 21,
+                    "packageName": "org.example.app",
+                    "sig": "",
+                    "signer": "",
+                    "size": APK.len(),
+                    "targetSdkVersion": 34,
+                    "versionCode": 1,
+                    "versionName": "1.0",
+                }],
+            },
+        });
+        let index_v1 = serde_json::to_vec(&index_v1).unwrap();
+        fs::write(dir.join("index-v1.json"), &index_v1).unwrap();
+        jar::write_signed_jar(&dir.join("index-v1.jar"), "index-v1.json", &index_v1, &key).unwrap();
+
+        let index_v2 = serde_json::json!({
+            "packages": {
+                "org.example.app": {
+                    "versions": {
+                        apk_sha256.clone(): {
+                            "file": { "name": "/app.apk", "sha256": apk_sha256, "size": APK.len() },
+                        },
+                    },
+                },
+            },
+        });
+        let index_v2 = serde_json::to_vec(&index_v2).unwrap();
+        fs::write(dir.join("index-v2.json"), &index_v2).unwrap();
+
+        let entry = serde_json::json!({
+            "index": { "name": "/index-v2.json", "sha256": checksum::sha256_hex(&index_v2), "size": index_v2.len() },
+        });
+        let entry = serde_json::to_vec(&entry).unwrap();
+        fs::write(dir.join("entry.json"), &entry).unwrap();
+        jar::write_signed_jar(&dir.join("entry.jar"), "entry.json", &entry, &key).unwrap();
+
+        checksum::sha256_hex(key.certificate())
+    }
+
+    fn problems(report: &Report) -> Vec<&str> {
+        report.problems.iter().map(|(_, problem)| problem.as_str()).collect()
+    }
+
+    #[test]
+    fn consistent_repo_has_no_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let fingerprint = repo(dir.path());
+
+        let report = check(dir.path(), &fingerprint).unwrap();
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert!(report.checked.contains(&dir.path().join("app.apk")));
+        assert!(report.checked.contains(&dir.path().join("index-v2.json")));
+    }
+
+    #[test]
+    fn tampered_apk_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let fingerprint = repo(dir.path());
+        fs::write(dir.path().join("app.apk"), b"something else").unwrap();
+
+        let report = check(dir.path(), &fingerprint).unwrap();
+        let problems = problems(&report);
+        assert!(problems.iter().any(|p| p.starts_with("SHA-256 of app.apk") && p.contains("index-v1.json")), "{:?}", problems);
+        assert!(problems.iter().any(|p| p.starts_with("SHA-256 of app.apk") && p.contains("index-v2.json")), "{:?}", problems);
+        assert!(problems.iter().any(|p| p.starts_with("Size of app.apk")), "{:?}", problems);
+    }
+
+    #[test]
+    fn index_not_matching_the_entry_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let fingerprint = repo(dir.path());
+        fs::write(dir.path().join("index-v2.json"), b"{}").unwrap();
+
+        let report = check(dir.path(), &fingerprint).unwrap();
+        let problems = problems(&report);
+        assert!(problems.iter().any(|p| p.starts_with("SHA-256 of index-v2.json") && p.contains("entry.json")), "{:?}", problems);
+    }
+
+    #[test]
+    fn other_signer_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        repo(dir.path());
+
+        let report = check(dir.path(), &"0".repeat(64)).unwrap();
+        let problems = problems(&report);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems.iter().all(|p| p.contains("not by the repo fingerprint")), "{:?}", problems);
+    }
+
+    #[test]
+    fn apk_missing_from_the_index_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let fingerprint = repo(dir.path());
+        fs::write(dir.path().join("other.apk"), APK).unwrap();
+
+        let report = check(dir.path(), &fingerprint).unwrap();
+        assert_eq!(problems(&report), ["other.apk is not in the index"]);
+    }
+}
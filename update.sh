@@ -7,7 +7,7 @@ echo "::endgroup::"
 cd ..
 
 # Run metascoop from parent directory inside nix develop so fdroid is available
-nix develop --command bash -c "./metascoop/target/release/metascoop --apps-path=apps.yaml --repo-dir=fdroid/repo --personal-access-token=\"$GH_ACCESS_TOKEN\" $1"
+nix develop --command bash -c "./metascoop/target/release/metascoop --apps-path=apps.yaml --repo-dir=fdroid/repo --personal-access-token=\"$GH_ACCESS_TOKEN\" --commit-message=\"${COMMIT_MESSAGE_FILE:-/tmp/metascoop-commit-message.txt}\" --summary-json=\"${SUMMARY_FILE:-/tmp/metascoop-summary.json}\" $1"
 EXIT_CODE=$?
 
 echo "Scoop had an exit code of $EXIT_CODE"
@@ -22,6 +22,9 @@ elif [ $EXIT_CODE -eq 0 ]; then
     # Exit code 0 means that we can commit everything & push
 
     echo "This means that we now have changes we should push"
+
+    # Don't push a repo whose files don't match its signed index
+    nix develop --command bash -c "./metascoop/target/release/metascoop --repo-dir=fdroid/repo verify"
 else 
     echo "This is an unexpected error"
 